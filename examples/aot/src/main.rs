@@ -52,13 +52,9 @@ fn aot_to_file() {
 
     let out_path = aot_out_path();
 
-    if let Some(e) = compiler
+    compiler
         .compile_async_module(&mut builder, &wasm, out_path.as_path())
-        .unwrap()
-    {
-        let e: Result<(), std::io::Error> = Err(e);
-        e.unwrap()
-    }
+        .unwrap();
     println!("compile wasm to {:?}", out_path);
 }
 
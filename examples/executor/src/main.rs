@@ -0,0 +1,101 @@
+use std::{
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use wasmedge_asyncify::*;
+
+/// Runs a future to completion on the current thread, without any async runtime.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = Box::pin(fut);
+    let thread = std::thread::current();
+    let waker = waker_fn::waker_fn(move || thread.unpark());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(r) => return r,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// A timer future backed by a plain thread.
+struct Sleep {
+    state: Arc<Mutex<(bool, Option<Waker>)>>,
+}
+
+impl Sleep {
+    fn new(dur: Duration) -> Self {
+        let state = Arc::new(Mutex::new((false, None::<Waker>)));
+        let timer_state = state.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(dur);
+            let mut state = timer_state.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        });
+        Sleep { state }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.0 {
+            Poll::Ready(())
+        } else {
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+fn async_host_sleep(_linker: &mut AsyncLinker, _args: Vec<types::WasmVal>) -> ResultFuture {
+    Box::new(async {
+        println!("host: sleep 1s ...");
+        Sleep::new(Duration::from_secs(1)).await;
+        println!("host: sleep awake");
+
+        Ok(vec![])
+    })
+}
+
+fn main() {
+    let config = crate::Config::create().unwrap();
+
+    let mut builder = crate::AsyncLinkerBuilder::new(&Some(config)).unwrap();
+
+    // create a wasi module
+    builder.create_wasi(&[], &["b=1", "a=1"], &[]).unwrap();
+
+    // create a async import module
+    builder
+        .create_import_object("host", |b| {
+            b.add_async_func("sleep", (vec![], vec![]), async_host_sleep)?;
+            Ok(())
+        })
+        .unwrap();
+
+    // read wasm
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let wasm_path = Path::new(&manifest_dir).join("../../wasm/hello.wasm");
+    println!("load wasm from {:?}", wasm_path);
+    let wasm = std::fs::read(wasm_path).unwrap();
+
+    // load wasm from bytes
+    let module = builder.load_wasm(&wasm).unwrap();
+
+    // instance wasm
+    let mut inst = builder.instance(&module).unwrap();
+
+    // call _start function without tokio
+    block_on(inst.call("_start", vec![])).unwrap();
+}
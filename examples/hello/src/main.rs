@@ -19,7 +19,9 @@ async fn main() {
     let mut builder = crate::AsyncLinkerBuilder::new(&Some(config)).unwrap();
 
     // create a wasi module
-    builder.create_wasi(&[], &["b=1", "a=1"], &[]).unwrap();
+    builder
+        .create_wasi_with(&WasiConfig::new().env("b", "1").env("a", "1"))
+        .unwrap();
 
     // create a async import module
     builder
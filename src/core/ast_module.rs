@@ -1,14 +1,178 @@
 use std::borrow::Cow;
+use std::ffi::{CStr, CString};
+#[cfg(feature = "aot")]
+use std::path::Path;
 
 use wasmedge_types::error::WasmEdgeError;
 
 use super::config::Config;
 use crate::utils::check;
+#[cfg(feature = "aot")]
+use crate::utils::path_to_cstring;
 
 use wasmedge_sys::ffi;
 
 pub type CodegenConfig = binaryen::CodegenConfig;
 
+/// Prefixes the name of an export of `asyncify_get_state` that records the imports
+/// asyncify instrumented, e.g. `asyncify_imports:host.sleep,host.read`.
+///
+/// WasmEdge does not list the custom sections of a module, but it lists its exports, also
+/// for shared libraries compiled by the AOT compiler.
+pub(crate) const ASYNCIFY_IMPORTS_EXPORT: &str = "asyncify_imports:";
+
+/// Routes some calls of a guest function import to another import, chosen by the value
+/// of the call's first `i32` argument.
+///
+/// The guest keeps calling `module.name`; calls whose first argument is in `first_args`
+/// go to `to_module.to_name` instead, which must have the same signature. With
+/// `first_args` set to `None` every call goes there.
+#[derive(Debug, Clone)]
+pub struct ImportIntercept {
+    pub module: String,
+    pub name: String,
+    pub to_module: String,
+    pub to_name: String,
+    pub first_args: Option<Vec<i32>>,
+    /// Whether the module may be used unchanged if it is already asyncified, and so
+    /// cannot be intercepted anymore.
+    pub optional: bool,
+}
+
+impl ImportIntercept {
+    /// Tells whether a module importing `imports` was asyncified with this intercept, or
+    /// did not import the intercepted function anyway.
+    #[cfg(feature = "aot")]
+    pub(crate) fn is_applied_to(&self, imports: &[(String, String)]) -> bool {
+        let imported = |module: &str, name: &str| {
+            imports
+                .iter()
+                .any(|(m, n)| m.as_str() == module && n.as_str() == name)
+        };
+        let original = imported(&self.module, &self.name);
+        let target = imported(&self.to_module, &self.to_name);
+        match &self.first_args {
+            // left untouched by `apply`
+            Some(args) if args.is_empty() => true,
+            // the dispatcher calls both imports
+            Some(_) => original == target,
+            // the import was replaced
+            None => !original,
+        }
+    }
+
+    /// Replaces the intercepted import with a dispatcher calling either import.
+    ///
+    /// Binaryen refers to functions by internal name, so the calls of the guest reach the
+    /// dispatcher once it takes over the name of the removed import.
+    fn apply(&self, module: &binaryen::Module) -> Result<(), WasmEdgeError> {
+        use binaryen::ffi;
+
+        if matches!(&self.first_args, Some(args) if args.is_empty()) {
+            return Ok(());
+        }
+        let to_cstring = |s: &str| CString::new(s).map_err(|_| WasmEdgeError::ModuleCreate);
+        let raw = module.raw();
+
+        unsafe {
+            let mut target = None;
+            for idx in 0..ffi::BinaryenGetNumFunctions(raw) {
+                let func = ffi::BinaryenGetFunctionByIndex(raw, idx);
+                let import_module = ffi::BinaryenFunctionImportGetModule(func);
+                let import_base = ffi::BinaryenFunctionImportGetBase(func);
+                if import_module.is_null() || import_base.is_null() {
+                    continue;
+                }
+                if CStr::from_ptr(import_module).to_bytes() == self.module.as_bytes()
+                    && CStr::from_ptr(import_base).to_bytes() == self.name.as_bytes()
+                {
+                    target = Some(func);
+                    break;
+                }
+            }
+            // the guest does not import it
+            let func = match target {
+                Some(func) => func,
+                None => return Ok(()),
+            };
+
+            let name = CStr::from_ptr(ffi::BinaryenFunctionGetName(func)).to_owned();
+            let params = ffi::BinaryenFunctionGetParams(func);
+            let results = ffi::BinaryenFunctionGetResults(func);
+            let mut param_types = vec![0; ffi::BinaryenTypeArity(params) as usize];
+            ffi::BinaryenTypeExpand(params, param_types.as_mut_ptr());
+            if self.first_args.is_some() && param_types.first() != Some(&ffi::BinaryenTypeInt32()) {
+                return Err(WasmEdgeError::ModuleCreate);
+            }
+
+            let original = to_cstring(&format!("{}.{}$original", self.module, self.name))?;
+            let redirect = to_cstring(&format!("{}.{}$redirect", self.to_module, self.to_name))?;
+            ffi::BinaryenRemoveFunction(raw, name.as_ptr());
+            if self.first_args.is_some() {
+                ffi::BinaryenAddFunctionImport(
+                    raw,
+                    original.as_ptr(),
+                    to_cstring(&self.module)?.as_ptr(),
+                    to_cstring(&self.name)?.as_ptr(),
+                    params,
+                    results,
+                );
+            }
+            ffi::BinaryenAddFunctionImport(
+                raw,
+                redirect.as_ptr(),
+                to_cstring(&self.to_module)?.as_ptr(),
+                to_cstring(&self.to_name)?.as_ptr(),
+                params,
+                results,
+            );
+
+            let call = |target: &CString| {
+                let mut operands = param_types
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, ty)| ffi::BinaryenLocalGet(raw, idx as u32, *ty))
+                    .collect::<Vec<_>>();
+                ffi::BinaryenCall(
+                    raw,
+                    target.as_ptr(),
+                    operands.as_mut_ptr(),
+                    operands.len() as u32,
+                    results,
+                )
+            };
+            let body = match &self.first_args {
+                Some(first_args) => {
+                    let is_redirected = first_args
+                        .iter()
+                        .map(|arg| {
+                            ffi::BinaryenBinary(
+                                raw,
+                                ffi::BinaryenEqInt32(),
+                                ffi::BinaryenLocalGet(raw, 0, ffi::BinaryenTypeInt32()),
+                                ffi::BinaryenConst(raw, ffi::BinaryenLiteralInt32(*arg)),
+                            )
+                        })
+                        .reduce(|l, r| ffi::BinaryenBinary(raw, ffi::BinaryenOrInt32(), l, r))
+                        .unwrap();
+                    ffi::BinaryenIf(raw, is_redirected, call(&redirect), call(&original))
+                }
+                None => call(&redirect),
+            };
+            ffi::BinaryenAddFunction(
+                raw,
+                name.as_ptr(),
+                params,
+                results,
+                std::ptr::null_mut(),
+                0,
+                body,
+            );
+        }
+        Ok(())
+    }
+}
+
 pub struct Loader {
     pub(crate) loader_inner: *mut ffi::WasmEdge_LoaderContext,
     pub(crate) validator_inner: *mut ffi::WasmEdge_ValidatorContext,
@@ -64,33 +228,68 @@ impl Loader {
                 wasm.len() as u32,
             ))?;
 
-            if mod_ctx.is_null() {
-                return Err(WasmEdgeError::ModuleCreate);
-            }
+            self.validate(mod_ctx)
+        }
+    }
 
-            let validate_result = check(ffi::WasmEdge_ValidatorValidate(
-                self.validator_inner,
-                mod_ctx,
-            ));
+    /// Loads the module at `path`, a wasm binary or a shared library compiled by the
+    /// WasmEdge AOT compiler.
+    #[cfg(feature = "aot")]
+    pub fn load_module_from_file(&mut self, path: &Path) -> Result<AstModule, WasmEdgeError> {
+        unsafe {
+            let mut mod_ctx: *mut ffi::WasmEdge_ASTModuleContext = std::ptr::null_mut();
+            let path = path_to_cstring(path)?;
 
-            if let Err(e) = validate_result {
-                ffi::WasmEdge_ASTModuleDelete(mod_ctx);
-                return Err(e);
-            }
+            check(ffi::WasmEdge_LoaderParseFromFile(
+                self.loader_inner,
+                &mut mod_ctx,
+                path.as_ptr(),
+            ))?;
 
-            Ok(AstModule { inner: mod_ctx })
+            self.validate(mod_ctx)
         }
     }
 
-    pub fn pass_async_module_from_bytes<'a, B: AsRef<str>, I: IntoIterator<Item = B>>(
+    /// Validates the parsed `mod_ctx`, taking ownership of it.
+    unsafe fn validate(
         &mut self,
+        mod_ctx: *mut ffi::WasmEdge_ASTModuleContext,
+    ) -> Result<AstModule, WasmEdgeError> {
+        if mod_ctx.is_null() {
+            return Err(WasmEdgeError::ModuleCreate);
+        }
+
+        let validate_result = check(ffi::WasmEdge_ValidatorValidate(
+            self.validator_inner,
+            mod_ctx,
+        ));
+
+        if let Err(e) = validate_result {
+            ffi::WasmEdge_ASTModuleDelete(mod_ctx);
+            return Err(e);
+        }
+
+        Ok(AstModule { inner: mod_ctx })
+    }
+
+    /// Runs `passes` on `wasm` after applying `intercepts`, unless it is asyncified
+    /// already. It needs no loader, so it can run on any thread.
+    ///
+    /// The `asyncify-imports` pass argument of `codegen_config` is recorded in an export,
+    /// see [`AstModule::asyncify_imports`].
+    pub fn pass_async_module_from_bytes<'a, B: AsRef<str>, I: IntoIterator<Item = B>>(
         wasm: &'a [u8],
         passes: I,
         codegen_config: &CodegenConfig,
+        intercepts: &[ImportIntercept],
     ) -> Result<Cow<'a, [u8]>, WasmEdgeError> {
         let mut module = binaryen::Module::read(wasm).map_err(|_| WasmEdgeError::ModuleCreate)?;
 
         if module.get_export("asyncify_get_state").unwrap().is_null() {
+            for intercept in intercepts {
+                intercept.apply(&module)?;
+            }
+
             // skip run start on init
             {
                 if let Some(start) = module.get_start() {
@@ -108,11 +307,33 @@ impl Loader {
             }
 
             module
-                .run_optimization_passes(passes, &codegen_config)
+                .run_optimization_passes(passes, codegen_config)
                 .map_err(|_| WasmEdgeError::ModuleCreate)?;
 
+            let asyncify_imports = codegen_config
+                .pass_argument
+                .iter()
+                .find(|(arg, _)| arg == "asyncify-imports");
+            if let Some((_, imports)) = asyncify_imports {
+                let to_cstring = |s: &str| CString::new(s).map_err(|_| WasmEdgeError::ModuleCreate);
+                let get_state = to_cstring("asyncify_get_state")?;
+                let name = to_cstring(&format!("{}{}", ASYNCIFY_IMPORTS_EXPORT, imports))?;
+                unsafe {
+                    binaryen::ffi::BinaryenAddFunctionExport(
+                        module.raw(),
+                        get_state.as_ptr(),
+                        name.as_ptr(),
+                    );
+                }
+            }
+
             let new_wasm = module.write();
             Ok(Cow::Owned(new_wasm))
+        } else if intercepts.iter().any(|intercept| !intercept.optional) {
+            // the imports were fixed when the module was asyncified
+            Err(WasmEdgeError::Operation(
+                "cannot intercept imports of an already asyncified module".to_string(),
+            ))
         } else {
             Ok(Cow::Borrowed(wasm))
         }
@@ -132,3 +353,118 @@ impl Drop for AstModule {
 }
 unsafe impl Send for AstModule {}
 unsafe impl Sync for AstModule {}
+
+impl AstModule {
+    /// Returns the `(module, name)` of each import of the module.
+    pub fn imports(&self) -> Vec<(String, String)> {
+        unsafe {
+            let len = ffi::WasmEdge_ASTModuleListImportsLength(self.inner);
+            let mut imports = Vec::with_capacity(len as usize);
+            let len = ffi::WasmEdge_ASTModuleListImports(self.inner, imports.as_mut_ptr(), len);
+            imports.set_len(len as usize);
+            imports
+                .into_iter()
+                .map(|ty| {
+                    (
+                        ffi::WasmEdge_ImportTypeGetModuleName(ty).into(),
+                        ffi::WasmEdge_ImportTypeGetExternalName(ty).into(),
+                    )
+                })
+                .collect()
+        }
+    }
+
+    /// Returns the names of the exports of the module.
+    pub fn exports(&self) -> Vec<String> {
+        unsafe {
+            let len = ffi::WasmEdge_ASTModuleListExportsLength(self.inner);
+            let mut exports = Vec::with_capacity(len as usize);
+            let len = ffi::WasmEdge_ASTModuleListExports(self.inner, exports.as_mut_ptr(), len);
+            exports.set_len(len as usize);
+            exports
+                .into_iter()
+                .map(|ty| ffi::WasmEdge_ExportTypeGetExternalName(ty).into())
+                .collect()
+        }
+    }
+
+    /// Returns the imports asyncify instrumented, as recorded by
+    /// [`Loader::pass_async_module_from_bytes`], or `None` if they were not recorded.
+    #[cfg(feature = "aot")]
+    pub(crate) fn asyncify_imports(&self) -> Option<Vec<String>> {
+        recorded_asyncify_imports(&self.exports())
+    }
+}
+
+#[cfg(feature = "aot")]
+fn recorded_asyncify_imports(exports: &[String]) -> Option<Vec<String>> {
+    let imports = exports
+        .iter()
+        .find_map(|name| name.strip_prefix(ASYNCIFY_IMPORTS_EXPORT))?;
+    Some(
+        imports
+            .split(',')
+            .filter(|import| !import.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+#[cfg(all(test, feature = "aot"))]
+mod tests {
+    use super::*;
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_the_recorded_asyncify_imports() {
+        let exports = strings(&["_start", "asyncify_imports:host.sleep,host.read", "memory"]);
+        assert_eq!(
+            recorded_asyncify_imports(&exports),
+            Some(strings(&["host.sleep", "host.read"]))
+        );
+        let exports = strings(&["asyncify_imports:"]);
+        assert_eq!(recorded_asyncify_imports(&exports), Some(vec![]));
+        assert_eq!(recorded_asyncify_imports(&strings(&["_start"])), None);
+    }
+
+    fn intercept(first_args: Option<Vec<i32>>) -> ImportIntercept {
+        ImportIntercept {
+            module: "wasi".to_string(),
+            name: "fd_write".to_string(),
+            to_module: "host".to_string(),
+            to_name: "fd_write".to_string(),
+            first_args,
+            optional: false,
+        }
+    }
+
+    fn imports(names: &[(&str, &str)]) -> Vec<(String, String)> {
+        names
+            .iter()
+            .map(|(m, n)| (m.to_string(), n.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn tells_whether_an_intercept_was_applied() {
+        let original = imports(&[("wasi", "fd_write")]);
+        let replaced = imports(&[("host", "fd_write")]);
+        let dispatched = imports(&[("wasi", "fd_write"), ("host", "fd_write")]);
+        let neither = imports(&[("wasi", "fd_read")]);
+
+        let all = intercept(None);
+        assert!(!all.is_applied_to(&original));
+        assert!(all.is_applied_to(&replaced));
+        assert!(all.is_applied_to(&neither));
+
+        let some = intercept(Some(vec![1, 2]));
+        assert!(some.is_applied_to(&dispatched));
+        assert!(!some.is_applied_to(&original));
+        assert!(some.is_applied_to(&neither));
+
+        assert!(intercept(Some(vec![])).is_applied_to(&original));
+    }
+}
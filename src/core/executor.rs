@@ -1,15 +1,16 @@
 //! Defines WasmEdge Executor.
 use std::collections::HashMap;
+use std::time::Instant;
 
 use wasmedge_types::error::WasmEdgeError;
 use wasmedge_types::WasmEdgeResult;
 
 use super::ast_module::AstModule;
 use super::instance::function::FuncRef;
-use super::module::{ImportModule, InnerInstance, Instance};
+use super::module::{ImportModule, InnerInstance, Instance, WASI_MODULE_NAME};
 use super::{config::Config, types::WasmVal};
 
-use crate::utils::check;
+use crate::utils::{check, is_terminated};
 
 use wasmedge_sys::ffi;
 
@@ -19,6 +20,7 @@ pub struct Executor {
     pub(crate) inner: InnerExecutor,
     pub(crate) inner_store: InnerStore,
     imports: HashMap<String, ImportModule>,
+    terminated: bool,
 }
 impl Executor {
     pub fn create(config: &Option<Config>) -> WasmEdgeResult<Self> {
@@ -36,6 +38,7 @@ impl Executor {
                     inner: InnerExecutor(ctx),
                     inner_store: InnerStore(store_ctx),
                     imports: HashMap::new(),
+                    terminated: false,
                 }),
             }
         }
@@ -82,7 +85,7 @@ impl Executor {
         func: &FuncRef,
         params: &[WasmVal],
     ) -> WasmEdgeResult<Vec<WasmVal>> {
-        let raw_params = params.into_iter().map(|x| x.into()).collect::<Vec<_>>();
+        let raw_params = params.iter().map(|x| x.into()).collect::<Vec<_>>();
 
         // get the length of the function's returns
         let returns_len = func.func_return_size()?;
@@ -90,18 +93,95 @@ impl Executor {
         unsafe {
             let mut returns = Vec::with_capacity(returns_len);
 
-            check(ffi::WasmEdge_ExecutorInvoke(
+            let result = ffi::WasmEdge_ExecutorInvoke(
                 self.inner.0,
                 func.inner.0,
                 raw_params.as_ptr(),
                 raw_params.len() as u32,
                 returns.as_mut_ptr(),
                 returns_len as u32,
-            ))?;
+            );
+            check(result)?;
+            if is_terminated(result) {
+                self.terminated = true;
+                return Ok(vec![]);
+            }
+            returns.set_len(returns_len);
+            Ok(returns.into_iter().map(Into::into).collect::<Vec<_>>())
+        }
+    }
+
+    /// Runs `func` like [`run_func_ref`](Self::run_func_ref), but on a WasmEdge worker
+    /// thread, cancelling it if it still runs at `deadline`.
+    ///
+    /// The current thread waits for the run to end. A cancelled run fails with
+    /// `CoreCommonError::Interrupted`; AOT code is only cancelled if it was compiled
+    /// interruptible.
+    pub(crate) fn run_func_ref_until(
+        &mut self,
+        func: &FuncRef,
+        params: &[WasmVal],
+        deadline: Instant,
+    ) -> WasmEdgeResult<Vec<WasmVal>> {
+        let raw_params = params.iter().map(|x| x.into()).collect::<Vec<_>>();
+
+        let returns_len = func.func_return_size()?;
+
+        unsafe {
+            let async_ctx = ffi::WasmEdge_ExecutorAsyncInvoke(
+                self.inner.0,
+                func.inner.0,
+                raw_params.as_ptr(),
+                raw_params.len() as u32,
+            );
+            if async_ctx.is_null() {
+                return Err(WasmEdgeError::Core(
+                    wasmedge_types::error::CoreError::Common(
+                        wasmedge_types::error::CoreCommonError::RuntimeError,
+                    ),
+                ));
+            }
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let millis = timeout.as_millis().min(u64::MAX as u128) as u64;
+            if !ffi::WasmEdge_AsyncWaitFor(async_ctx, millis) {
+                ffi::WasmEdge_AsyncCancel(async_ctx);
+            }
+
+            // waits for the worker to stop, cancelled or not
+            let mut returns = Vec::with_capacity(returns_len);
+            let result =
+                ffi::WasmEdge_AsyncGet(async_ctx, returns.as_mut_ptr(), returns_len as u32);
+            ffi::WasmEdge_AsyncDelete(async_ctx);
+            check(result)?;
+            if is_terminated(result) {
+                self.terminated = true;
+                return Ok(vec![]);
+            }
+
             returns.set_len(returns_len);
             Ok(returns.into_iter().map(Into::into).collect::<Vec<_>>())
         }
     }
+
+    /// Returns `true`, once, if the last run ended because the guest terminated.
+    ///
+    /// A terminated run returns no values.
+    pub(crate) fn take_terminated(&mut self) -> bool {
+        std::mem::take(&mut self.terminated)
+    }
+
+    /// Returns the exit code of the registered WASI module, if any.
+    pub fn wasi_exit_code(&self) -> Option<u32> {
+        self.imports
+            .get(WASI_MODULE_NAME)
+            .map(|wasi| wasi.wasi_exit_code())
+    }
+
+    /// Returns the registered WASI module, if any.
+    pub(crate) fn wasi_module_mut(&mut self) -> Option<&mut ImportModule> {
+        self.imports.get_mut(WASI_MODULE_NAME)
+    }
 }
 
 #[derive(Debug)]
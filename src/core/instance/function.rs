@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 use crate::core::executor::Executor;
 use crate::core::types::WasmVal;
 use wasmedge_sys::ffi;
@@ -231,9 +233,14 @@ impl FuncRef {
     pub fn call(&self, engine: &mut Executor, args: &[WasmVal]) -> WasmEdgeResult<Vec<WasmVal>> {
         engine.run_func_ref(self, args)
     }
-}
 
-#[derive(Debug, Clone)]
-pub(crate) struct InnerFuncRef(pub(crate) *const ffi::WasmEdge_FunctionInstanceContext);
-unsafe impl Send for InnerFuncRef {}
-unsafe impl Sync for InnerFuncRef {}
+    /// Calls the function like [`call`](Self::call), cancelling it at `deadline`.
+    pub(crate) fn call_until(
+        &self,
+        engine: &mut Executor,
+        args: &[WasmVal],
+        deadline: Instant,
+    ) -> WasmEdgeResult<Vec<WasmVal>> {
+        engine.run_func_ref_until(self, args, deadline)
+    }
+}
@@ -0,0 +1,24 @@
+use crate::core::types::WasmVal;
+use wasmedge_sys::ffi;
+
+/// Defines a WebAssembly global instance exported by a module instance.
+#[derive(Debug)]
+pub struct Global {
+    pub(crate) inner: InnerGlobal,
+}
+
+impl Global {
+    pub fn get_value(&self) -> WasmVal {
+        let val = unsafe { ffi::WasmEdge_GlobalInstanceGetValue(self.inner.0) };
+        val.into()
+    }
+
+    pub fn set_value(&mut self, val: WasmVal) {
+        unsafe { ffi::WasmEdge_GlobalInstanceSetValue(self.inner.0, val.into()) }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct InnerGlobal(pub(crate) *mut ffi::WasmEdge_GlobalInstanceContext);
+unsafe impl Send for InnerGlobal {}
+unsafe impl Sync for InnerGlobal {}
@@ -66,29 +66,23 @@ impl Memory {
         }
     }
 
-    pub fn data_pointer<'a>(&'a self, offset: usize, len: usize) -> WasmEdgeResult<&'a [u8]> {
-        let ptr = unsafe {
-            ffi::WasmEdge_MemoryInstanceGetPointerConst(self.inner.0, offset as u32, len as u32)
-        };
+    pub fn data_pointer(&self, offset: usize, len: usize) -> WasmEdgeResult<&[u8]> {
+        let (offset, len) = to_range(offset, len).ok_or(WasmEdgeError::Mem(MemError::ConstPtr))?;
+        let ptr = unsafe { ffi::WasmEdge_MemoryInstanceGetPointerConst(self.inner.0, offset, len) };
         if ptr.is_null() {
             Err(WasmEdgeError::Mem(MemError::ConstPtr))
         } else {
-            Ok(unsafe { std::slice::from_raw_parts(ptr, len) })
+            Ok(unsafe { std::slice::from_raw_parts(ptr, len as usize) })
         }
     }
 
-    pub fn data_pointer_mut<'a>(
-        &'a mut self,
-        offset: usize,
-        len: usize,
-    ) -> WasmEdgeResult<&'a mut [u8]> {
-        let ptr = unsafe {
-            ffi::WasmEdge_MemoryInstanceGetPointer(self.inner.0, offset as u32, len as u32)
-        };
+    pub fn data_pointer_mut(&mut self, offset: usize, len: usize) -> WasmEdgeResult<&mut [u8]> {
+        let (offset, len) = to_range(offset, len).ok_or(WasmEdgeError::Mem(MemError::MutPtr))?;
+        let ptr = unsafe { ffi::WasmEdge_MemoryInstanceGetPointer(self.inner.0, offset, len) };
         if ptr.is_null() {
             Err(WasmEdgeError::Mem(MemError::MutPtr))
         } else {
-            Ok(unsafe { std::slice::from_raw_parts_mut(ptr, len) })
+            Ok(unsafe { std::slice::from_raw_parts_mut(ptr, len as usize) })
         }
     }
 
@@ -98,9 +92,8 @@ impl Memory {
         offset: usize,
         len: usize,
     ) -> WasmEdgeResult<*const u8> {
-        let ptr = unsafe {
-            ffi::WasmEdge_MemoryInstanceGetPointerConst(self.inner.0, offset as u32, len as u32)
-        };
+        let (offset, len) = to_range(offset, len).ok_or(WasmEdgeError::Mem(MemError::ConstPtr))?;
+        let ptr = unsafe { ffi::WasmEdge_MemoryInstanceGetPointerConst(self.inner.0, offset, len) };
         if ptr.is_null() {
             Err(WasmEdgeError::Mem(MemError::ConstPtr))
         } else {
@@ -114,9 +107,8 @@ impl Memory {
         offset: usize,
         len: usize,
     ) -> WasmEdgeResult<*mut u8> {
-        let ptr = unsafe {
-            ffi::WasmEdge_MemoryInstanceGetPointer(self.inner.0, offset as u32, len as u32)
-        };
+        let (offset, len) = to_range(offset, len).ok_or(WasmEdgeError::Mem(MemError::MutPtr))?;
+        let ptr = unsafe { ffi::WasmEdge_MemoryInstanceGetPointer(self.inner.0, offset, len) };
         if ptr.is_null() {
             Err(WasmEdgeError::Mem(MemError::MutPtr))
         } else {
@@ -137,6 +129,16 @@ impl Memory {
     }
 }
 
+/// Narrows a host range to the `u32` offset and length WasmEdge takes, refusing one
+/// that does not fit rather than truncating it to a smaller range that would.
+fn to_range(offset: usize, len: usize) -> Option<(u32, u32)> {
+    let (offset, len) = (u32::try_from(offset).ok()?, u32::try_from(len).ok()?);
+    match offset as u64 + len as u64 <= 1 << 32 {
+        true => Some((offset, len)),
+        false => None,
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct InnerMemory(pub(crate) *mut ffi::WasmEdge_MemoryInstanceContext);
 unsafe impl Send for InnerMemory {}
@@ -7,8 +7,8 @@ use wasmedge_types::error::{InstanceError, WasmEdgeError};
 use wasmedge_types::WasmEdgeResult;
 
 use super::{
-    instance::{function::FuncRef, memory::Memory},
-    instance::{function::InnerFunc, memory::InnerMemory},
+    instance::{function::FuncRef, global::Global, memory::Memory},
+    instance::{function::InnerFunc, global::InnerGlobal, memory::InnerMemory},
     types::WasmEdgeString,
 };
 
@@ -84,12 +84,24 @@ pub(crate) trait AsInstance {
     fn get_memory(&self, name: &str) -> WasmEdgeResult<Memory>;
 
     /// Returns the length of the exported [memory instances](crate::Memory) in this module instance.
+    #[allow(dead_code)]
     fn mem_len(&self) -> u32;
 
     /// Returns the names of all exported [memory instances](crate::Memory) in this module instance.
+    #[allow(dead_code)]
     fn mem_names(&self) -> Option<Vec<String>>;
+
+    /// Returns the exported global instance by name.
+    ///
+    /// # Error
+    ///
+    /// If fail to find the target global instance, then an error is returned.
+    fn get_global(&self, name: &str) -> WasmEdgeResult<Global>;
 }
 
+/// The module name WASI preview1 imports are resolved from.
+pub(crate) const WASI_MODULE_NAME: &str = "wasi_snapshot_preview1";
+
 #[derive(Debug)]
 pub struct ImportModule {
     pub(crate) inner: InnerInstance,
@@ -110,38 +122,42 @@ impl ImportModule {
         }
     }
 
+    /// Creates the WasmEdge WASI module. `envs` are `KEY=VALUE` pairs and `preopens` are
+    /// `guest:host` directory mappings.
+    ///
+    /// Fails if any entry contains a NUL byte.
     pub fn create_wasi<S: AsRef<str>>(
         args: &[S],
         envs: &[S],
         preopens: &[S],
     ) -> WasmEdgeResult<Self> {
-        fn to_cstring_vec<S: AsRef<str>>(s: &[S]) -> Vec<CString> {
+        fn to_cstring_vec<S: AsRef<str>>(s: &[S]) -> WasmEdgeResult<Vec<CString>> {
             let mut r = vec![];
             for s in s {
-                if let Ok(cs) = CString::new(s.as_ref()) {
-                    r.push(cs);
-                }
-            }
-            r
-        }
-        fn cstring_vec_to_ptr(s: &[CString]) -> Vec<*const c_char> {
-            let mut r = vec![];
-            for cs in s {
-                r.push(cs.as_ptr())
+                r.push(CString::new(s.as_ref())?);
             }
-            r
+            Ok(r)
         }
 
-        let args = to_cstring_vec(args);
-        let args_ptrs = cstring_vec_to_ptr(&args);
+        Self::create_wasi_from_cstrings(
+            &to_cstring_vec(args)?,
+            &to_cstring_vec(envs)?,
+            &to_cstring_vec(preopens)?,
+        )
+    }
+
+    pub(crate) fn create_wasi_from_cstrings(
+        args: &[CString],
+        envs: &[CString],
+        preopens: &[CString],
+    ) -> WasmEdgeResult<Self> {
+        let args_ptrs = cstring_vec_to_ptr(args);
         let args_len = args.len();
 
-        let envs = to_cstring_vec(envs);
-        let envs_ptrs = cstring_vec_to_ptr(&envs);
+        let envs_ptrs = cstring_vec_to_ptr(envs);
         let envs_len = envs.len();
 
-        let preopens = to_cstring_vec(preopens);
-        let preopens_ptrs = cstring_vec_to_ptr(&preopens);
+        let preopens_ptrs = cstring_vec_to_ptr(preopens);
         let preopens_len = preopens.len();
 
         let ctx = unsafe {
@@ -158,14 +174,54 @@ impl ImportModule {
             true => Err(WasmEdgeError::ImportObjCreate),
             false => Ok(Self {
                 inner: InnerInstance(ctx),
-                name: String::from("wasi_snapshot_preview1"),
+                name: String::from(WASI_MODULE_NAME),
             }),
         }
     }
 
+    /// Resets the WasmEdge WASI module to `args`, `envs` and `preopens`, in the formats of
+    /// [`create_wasi_from_cstrings`](Self::create_wasi_from_cstrings). Every fd the guest
+    /// opened is closed and the exit code goes back to 0.
+    pub(crate) fn init_wasi_from_cstrings(
+        &mut self,
+        args: &[CString],
+        envs: &[CString],
+        preopens: &[CString],
+    ) {
+        let args_ptrs = cstring_vec_to_ptr(args);
+        let envs_ptrs = cstring_vec_to_ptr(envs);
+        let preopens_ptrs = cstring_vec_to_ptr(preopens);
+        unsafe {
+            ffi::WasmEdge_ModuleInstanceInitWASI(
+                self.inner.0,
+                args_ptrs.as_ptr(),
+                args.len() as u32,
+                envs_ptrs.as_ptr(),
+                envs.len() as u32,
+                preopens_ptrs.as_ptr(),
+                preopens.len() as u32,
+            )
+        }
+    }
+
     pub fn name(&self) -> String {
         self.name.to_owned()
     }
+
+    /// Returns the code the guest passed to `proc_exit`, or 0 if it has not exited.
+    ///
+    /// Only meaningful for a module created by [`create_wasi`](Self::create_wasi).
+    pub fn wasi_exit_code(&self) -> u32 {
+        unsafe { ffi::WasmEdge_ModuleInstanceWASIGetExitCode(self.inner.0) }
+    }
+}
+
+fn cstring_vec_to_ptr(s: &[CString]) -> Vec<*const c_char> {
+    let mut r = vec![];
+    for cs in s {
+        r.push(cs.as_ptr())
+    }
+    r
 }
 
 impl AsInnerInstance for ImportModule {
@@ -205,6 +261,21 @@ impl<T: AsInnerInstance> AsInstance for T {
         }
     }
 
+    fn get_global(&self, name: &str) -> WasmEdgeResult<Global> {
+        let global_name: WasmEdgeString = WasmEdgeString::new(name)?;
+        let ctx = unsafe {
+            ffi::WasmEdge_ModuleInstanceFindGlobal(self.get_mut_ptr(), global_name.as_raw())
+        };
+        match ctx.is_null() {
+            true => Err(WasmEdgeError::Instance(InstanceError::NotFoundGlobal(
+                name.to_string(),
+            ))),
+            false => Ok(Global {
+                inner: InnerGlobal(ctx),
+            }),
+        }
+    }
+
     /// Returns the length of the exported [function instances](crate::Function) in this module instance.
     fn func_len(&self) -> u32 {
         unsafe { ffi::WasmEdge_ModuleInstanceListFunctionLength(self.get_mut_ptr()) }
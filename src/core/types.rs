@@ -64,9 +64,20 @@ unsafe impl Sync for InnerWasmEdgeString {}
 pub struct Extern {
     ctx: *mut std::ffi::c_void,
 }
+// Safety: this crate never dereferences an `Extern`, and `Extern::new` requires the
+// pointee to be shareable between threads.
+unsafe impl Send for Extern {}
+unsafe impl Sync for Extern {}
 
 impl Extern {
-    pub unsafe fn new<T>(ptr: *mut T) -> Self {
+    /// Wraps `ptr` as an `externref` value.
+    ///
+    /// # Safety
+    ///
+    /// An `Extern` can be sent to and copied across threads along with the linker, so
+    /// `T` must be `Send + Sync` and `ptr` must stay valid for as long as the guest or
+    /// the host can still use the reference.
+    pub unsafe fn new<T: Send + Sync>(ptr: *mut T) -> Self {
         Extern { ctx: ptr.cast() }
     }
 
@@ -112,20 +123,17 @@ impl From<ffi::WasmEdge_Value> for WasmVal {
     }
 }
 
-impl Into<ffi::WasmEdge_Value> for WasmVal {
-    fn into(self) -> ffi::WasmEdge_Value {
+impl From<WasmVal> for ffi::WasmEdge_Value {
+    fn from(val: WasmVal) -> Self {
         unsafe {
-            match self {
+            match val {
                 WasmVal::I32(n) => ffi::WasmEdge_ValueGenI32(n),
                 WasmVal::I64(n) => ffi::WasmEdge_ValueGenI64(n),
                 WasmVal::F32(n) => ffi::WasmEdge_ValueGenF32(n),
                 WasmVal::F64(n) => ffi::WasmEdge_ValueGenF64(n),
                 WasmVal::V128(n) => ffi::WasmEdge_ValueGenV128(n),
-                WasmVal::FuncRef(r) => {
-                    // leak
-                    let new_ctx = std::mem::ManuallyDrop::new(r.inner.clone());
-                    ffi::WasmEdge_ValueGenFuncRef(new_ctx.0)
-                }
+                // the value only borrows the function instance owned by its store
+                WasmVal::FuncRef(r) => ffi::WasmEdge_ValueGenFuncRef(r.inner.0),
                 WasmVal::ExternRef(r) => ffi::WasmEdge_ValueGenExternRef(r.ctx),
                 WasmVal::None => ffi::WasmEdge_ValueGenNullRef(ValType::None.into()),
             }
@@ -133,20 +141,17 @@ impl Into<ffi::WasmEdge_Value> for WasmVal {
     }
 }
 
-impl Into<ffi::WasmEdge_Value> for &WasmVal {
-    fn into(self) -> ffi::WasmEdge_Value {
+impl From<&WasmVal> for ffi::WasmEdge_Value {
+    fn from(val: &WasmVal) -> Self {
         unsafe {
-            match self {
+            match val {
                 WasmVal::I32(n) => ffi::WasmEdge_ValueGenI32(*n),
                 WasmVal::I64(n) => ffi::WasmEdge_ValueGenI64(*n),
                 WasmVal::F32(n) => ffi::WasmEdge_ValueGenF32(*n),
                 WasmVal::F64(n) => ffi::WasmEdge_ValueGenF64(*n),
                 WasmVal::V128(n) => ffi::WasmEdge_ValueGenV128(*n),
-                WasmVal::FuncRef(r) => {
-                    // leak
-                    let new_ctx = std::mem::ManuallyDrop::new(r.inner.clone());
-                    ffi::WasmEdge_ValueGenFuncRef(new_ctx.0)
-                }
+                // the value only borrows the function instance owned by its store
+                WasmVal::FuncRef(r) => ffi::WasmEdge_ValueGenFuncRef(r.inner.0),
                 WasmVal::ExternRef(r) => ffi::WasmEdge_ValueGenExternRef(r.ctx),
                 WasmVal::None => ffi::WasmEdge_ValueGenNullRef(ValType::None.into()),
             }
@@ -1,14 +1,67 @@
 use std::{
+    ffi::CStr,
+    fmt, io,
     ops::{Deref, DerefMut},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
+use sha2::{Digest, Sha256};
 use wasmedge_sys::ffi;
-use wasmedge_types::{error::WasmEdgeError, CompilerOutputFormat, WasmEdgeResult};
+use wasmedge_types::{error::WasmEdgeError, WasmEdgeResult};
 
-pub use wasmedge_types::CompilerOptimizationLevel;
+pub use wasmedge_types::{CompilerOptimizationLevel, CompilerOutputFormat};
 
-use crate::{utils, AsyncLinkerBuilder, Config};
+use crate::{
+    core::{AstModule, ImportIntercept, Loader},
+    utils, AsyncLinkerBuilder, Config,
+};
+
+/// The error of an AOT compilation.
+#[derive(Debug)]
+pub enum AotError {
+    /// Reading or writing the wasm or the compiled module failed.
+    Io(io::Error),
+    /// Asyncifying or compiling the module failed.
+    WasmEdge(WasmEdgeError),
+    /// The compilation was cancelled before it finished.
+    Cancelled,
+    /// The compiler panicked, with this message.
+    Panicked(String),
+}
+
+impl fmt::Display for AotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AotError::Io(e) => write!(f, "AOT compilation I/O failed: {}", e),
+            AotError::WasmEdge(e) => write!(f, "AOT compilation failed: {}", e),
+            AotError::Cancelled => write!(f, "AOT compilation cancelled"),
+            AotError::Panicked(msg) => write!(f, "AOT compiler panicked: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AotError::Io(e) => Some(e),
+            AotError::WasmEdge(e) => Some(e),
+            AotError::Cancelled | AotError::Panicked(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for AotError {
+    fn from(e: io::Error) -> Self {
+        AotError::Io(e)
+    }
+}
+
+impl From<WasmEdgeError> for AotError {
+    fn from(e: WasmEdgeError) -> Self {
+        AotError::WasmEdge(e)
+    }
+}
 
 #[derive(Debug)]
 pub struct AotConfig {
@@ -138,6 +191,42 @@ impl AotConfig {
         unsafe { ffi::WasmEdge_ConfigureCompilerIsInterruptible(self.inner.inner.0) }
     }
 
+    /// Describes every setting that changes the compiled code, to tell compilations with
+    /// different configs apart.
+    fn settings(&self) -> String {
+        let config = &self.inner;
+        format!(
+            "{:?} {:?} {:?} {:?}",
+            (
+                self.get_aot_optimization_level(),
+                self.get_aot_compiler_output_format(),
+                self.generic_binary_enabled(),
+                self.interruptible_enabled(),
+            ),
+            (
+                config.bulk_memory_operations_enabled(),
+                config.exception_handling_enabled(),
+                config.function_references_enabled(),
+                config.memory64_enabled(),
+                config.multi_value_enabled(),
+                config.mutable_globals_enabled(),
+                config.non_trap_conversions_enabled(),
+                config.reference_types_enabled(),
+            ),
+            (
+                config.sign_extension_operators_enabled(),
+                config.simd_enabled(),
+                config.tail_call_enabled(),
+                config.threads_enabled(),
+            ),
+            (
+                config.is_cost_measuring(),
+                config.is_instruction_counting(),
+                config.is_time_measuring(),
+            ),
+        )
+    }
+
     pub fn copy_from(src: &Self) -> WasmEdgeResult<Self> {
         let inner = Config::copy_from(&src.inner)?;
         let mut config = AotConfig { inner };
@@ -193,17 +282,277 @@ impl AotCompiler {
         }
     }
 
+    /// Compiles the wasm binary `wasm` and returns the compiled module, in the output
+    /// format of the [`AotConfig`]. WasmEdge only compiles files, so both go through a
+    /// temporary directory that is removed afterwards.
+    pub fn compile_bytes(&mut self, wasm: &[u8]) -> Result<Vec<u8>, AotError> {
+        let dir = TempDir::new()?;
+        let in_path = dir.path().join("module.wasm");
+        let out_path = dir.path().join("module.aot");
+        std::fs::write(&in_path, wasm)?;
+        self.compile(&in_path, &out_path)?;
+        Ok(std::fs::read(&out_path)?)
+    }
+
+    /// Asyncifies `wasm` for the imports of `builder`, like
+    /// [`AsyncLinkerBuilder::load_wasm`], and compiles the result in memory with
+    /// [`compile_bytes`](Self::compile_bytes).
+    pub fn compile_async_module_bytes(
+        &mut self,
+        builder: &mut AsyncLinkerBuilder,
+        wasm: &[u8],
+    ) -> Result<Vec<u8>, AotError> {
+        let new_wasm = builder.pass_asyncify_wasm(wasm)?;
+        self.compile_bytes(&new_wasm)
+    }
+
+    /// Like [`compile_async_module_bytes`](Self::compile_async_module_bytes), writing the
+    /// compiled module to `out_path`.
+    ///
+    /// With the [`CompilerOutputFormat::Native`] output format, `out_path` is a shared
+    /// library to load with [`AsyncLinkerBuilder::load_aot_library`].
     pub fn compile_async_module<P: AsRef<Path>>(
         &mut self,
         builder: &mut AsyncLinkerBuilder,
         wasm: &[u8],
         out_path: P,
-    ) -> WasmEdgeResult<Option<std::io::Error>> {
-        let new_wasm = builder.pass_asyncify_wasm(wasm)?;
-        if let Err(e) = std::fs::write(&out_path, &new_wasm) {
-            return Ok(Some(e));
+    ) -> Result<(), AotError> {
+        let compiled = self.compile_async_module_bytes(builder, wasm)?;
+        std::fs::write(&out_path, compiled)?;
+        Ok(())
+    }
+}
+
+/// The AOT cache of an [`AsyncLinkerBuilder`], see
+/// [`set_aot_cache`](AsyncLinkerBuilder::set_aot_cache).
+#[derive(Debug)]
+pub(crate) struct AotCache {
+    dir: PathBuf,
+    config: AotConfig,
+}
+
+impl AotCache {
+    pub(crate) fn new(dir: PathBuf, config: AotConfig) -> Self {
+        AotCache { dir, config }
+    }
+
+    /// Returns the file the compilation of `wasm` is cached in. It depends on `wasm`, on
+    /// what its asyncification depends on, on the config and on the WasmEdge version.
+    pub(crate) fn path(
+        &self,
+        wasm: &[u8],
+        async_imports: &[String],
+        intercepts: &[ImportIntercept],
+    ) -> PathBuf {
+        let version = unsafe { CStr::from_ptr(ffi::WasmEdge_VersionGet()) };
+        // length-prefixed fields, so that no two inputs hash the same bytes
+        let mut hasher = Sha256::new();
+        let mut field = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        field(wasm);
+        field(&(async_imports.len() as u64).to_le_bytes());
+        for import in async_imports {
+            field(import.as_bytes());
+        }
+        field(&(intercepts.len() as u64).to_le_bytes());
+        for intercept in intercepts {
+            field(intercept.module.as_bytes());
+            field(intercept.name.as_bytes());
+            field(intercept.to_module.as_bytes());
+            field(intercept.to_name.as_bytes());
+            match &intercept.first_args {
+                Some(args) => {
+                    let args = args.iter().flat_map(|arg| arg.to_le_bytes());
+                    field(&[1]);
+                    field(&args.collect::<Vec<_>>());
+                }
+                None => field(&[0]),
+            }
+            field(&[intercept.optional as u8]);
         }
-        self.compile(&out_path, &out_path)?;
-        Ok(None)
+        field(self.config.settings().as_bytes());
+        field(version.to_bytes());
+        let hash = hasher.finalize();
+
+        let extension = match self.is_native() {
+            true => "so",
+            false => "wasm",
+        };
+        let name = hash
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        self.dir.join(format!("{}.{}", name, extension))
+    }
+
+    fn is_native(&self) -> bool {
+        matches!(
+            self.config.get_aot_compiler_output_format(),
+            CompilerOutputFormat::Native
+        )
+    }
+
+    /// Loads the module cached at `path`, if any. A module that fails to load is treated
+    /// as missing, so that it gets compiled again.
+    pub(crate) fn load(&self, loader: &mut Loader, path: &Path) -> Option<AstModule> {
+        match self.is_native() {
+            true if path.is_file() => loader.load_module_from_file(path).ok(),
+            true => None,
+            false => loader
+                .load_module_from_bytes(&std::fs::read(path).ok()?)
+                .ok(),
+        }
+    }
+
+    /// Compiles the asyncified `wasm`, caches it at `path` and loads it. Returns `None` if
+    /// it cannot be compiled, e.g. because WasmEdge was built without the AOT compiler.
+    /// Failing to cache a module only costs a compilation next time, except for shared
+    /// libraries, which can only be loaded from a file.
+    pub(crate) fn compile(
+        &self,
+        loader: &mut Loader,
+        path: &Path,
+        wasm: &[u8],
+    ) -> Option<AstModule> {
+        let compiled = AotCompiler::create(&self.config)
+            .ok()?
+            .compile_bytes(wasm)
+            .ok()?;
+        let stored = self.store(path, &compiled);
+        match self.is_native() {
+            true => stored
+                .ok()
+                .and_then(|_| loader.load_module_from_file(path).ok()),
+            false => loader.load_module_from_bytes(&compiled).ok(),
+        }
+    }
+
+    /// Writes `compiled` to `path` through a temporary file, so that concurrent loads never
+    /// read a partial module.
+    fn store(&self, path: &Path, compiled: &[u8]) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let dir = TempDir::new_in(&self.dir)?;
+        let tmp = dir.path().join("module");
+        std::fs::write(&tmp, compiled)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+/// A directory, under the system temp directory by default, removed with its content on
+/// drop.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> io::Result<Self> {
+        Self::new_in(&std::env::temp_dir())
+    }
+
+    /// Creates the directory in `parent` instead, e.g. to rename files out of it.
+    fn new_in(parent: &Path) -> io::Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let name = format!(
+            ".wasmedge-asyncify-aot-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = parent.join(name);
+        std::fs::create_dir(&path)?;
+        Ok(TempDir(path))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(configure: impl FnOnce(&mut AotConfig)) -> AotCache {
+        let mut config = AotConfig::create().unwrap();
+        configure(&mut config);
+        AotCache::new(PathBuf::from("cache"), config)
+    }
+
+    fn intercept(first_args: Option<Vec<i32>>) -> ImportIntercept {
+        ImportIntercept {
+            module: "wasi_snapshot_preview1".to_string(),
+            name: "fd_read".to_string(),
+            to_module: "host".to_string(),
+            to_name: "fd_read".to_string(),
+            first_args,
+            optional: false,
+        }
+    }
+
+    #[test]
+    fn path_depends_on_the_wasm_and_its_imports() {
+        let cache = cache(|_| {});
+        let imports = ["env.sleep".to_string()];
+        let path = cache.path(b"wasm", &imports, &[]);
+        assert_eq!(path, cache.path(b"wasm", &imports, &[]));
+        assert_eq!(path.parent(), Some(Path::new("cache")));
+        assert_eq!(path.extension().unwrap(), "wasm");
+
+        assert_ne!(path, cache.path(b"other", &imports, &[]));
+        assert_ne!(path, cache.path(b"wasm", &[], &[]));
+        assert_ne!(path, cache.path(b"wasm", &["env.sle".to_string()], &[]));
+        // fields are not concatenated
+        assert_ne!(
+            cache.path(b"", &["ab".to_string(), "c".to_string()], &[]),
+            cache.path(b"", &["a".to_string(), "bc".to_string()], &[])
+        );
+    }
+
+    #[test]
+    fn path_depends_on_the_intercepts() {
+        let cache = cache(|_| {});
+        let path = |intercepts: &[ImportIntercept]| cache.path(b"wasm", &[], intercepts);
+        let all = path(&[intercept(None)]);
+        assert_ne!(all, path(&[]));
+        assert_ne!(all, path(&[intercept(Some(vec![]))]));
+        assert_ne!(all, path(&[intercept(Some(vec![0]))]));
+        assert_ne!(
+            path(&[intercept(Some(vec![0]))]),
+            path(&[intercept(Some(vec![1]))])
+        );
+
+        let mut optional = intercept(None);
+        optional.optional = true;
+        assert_ne!(all, path(&[optional]));
+        let mut renamed = intercept(None);
+        renamed.to_name = "fd_read_async".to_string();
+        assert_ne!(all, path(&[renamed]));
+    }
+
+    #[test]
+    fn path_depends_on_the_config() {
+        let path = |cache: AotCache| cache.path(b"wasm", &[], &[]);
+        let default = path(cache(|_| {}));
+        assert_eq!(default, path(cache(|_| {})));
+        assert_ne!(default, path(cache(|c| c.interruptible(true))));
+        assert_ne!(default, path(cache(|c| c.generic_binary(true))));
+        assert_ne!(
+            default,
+            path(cache(|c| {
+                c.set_aot_optimization_level(CompilerOptimizationLevel::O0)
+            }))
+        );
+
+        let native = path(cache(|c| {
+            c.set_aot_compiler_output_format(CompilerOutputFormat::Native)
+        }));
+        assert_ne!(default.file_stem(), native.file_stem());
+        assert_eq!(native.extension().unwrap(), "so");
     }
 }
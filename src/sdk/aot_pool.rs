@@ -0,0 +1,241 @@
+//! AOT compilation on dedicated threads, awaited from async code.
+
+use std::{
+    any::Any,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    task::{Context, Poll},
+    thread,
+};
+
+use tokio::sync::{oneshot, watch, OwnedSemaphorePermit, Semaphore};
+use wasmedge_types::WasmEdgeResult;
+
+use super::{
+    aot::{AotCompiler, AotConfig, AotError},
+    linker::{AsyncLinkerBuilder, AsyncifyPass},
+};
+
+type Job = Box<dyn FnOnce(&mut AotCompiler) + Send>;
+
+/// The stage of an [`AotTask`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AotProgress {
+    /// Waiting for a free compiler thread.
+    Queued,
+    /// Being asyncified, if requested, and compiled by WasmEdge.
+    Compiling,
+    /// Compiled, failed or cancelled; the task is ready.
+    Finished,
+}
+
+/// Cancels an [`AotTask`], see [`AotTask::cancel_handle`].
+#[derive(Debug, Clone)]
+pub struct AotCancelHandle(Arc<AtomicBool>);
+
+impl AotCancelHandle {
+    /// Cancels the task. A queued task is dropped without being compiled. WasmEdge cannot
+    /// be interrupted, so a task being compiled keeps its thread busy until it finishes,
+    /// and its result is discarded.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A compilation submitted to an [`AotCompilePool`], resolving to the compiled module.
+///
+/// Dropping the task cancels it.
+#[derive(Debug)]
+pub struct AotTask {
+    result: oneshot::Receiver<Result<Vec<u8>, AotError>>,
+    progress: watch::Receiver<AotProgress>,
+    cancel: AotCancelHandle,
+}
+
+impl AotTask {
+    /// Returns the current stage of the compilation.
+    pub fn progress(&self) -> AotProgress {
+        *self.progress.borrow()
+    }
+
+    /// Waits for the compilation to reach its next stage and returns it.
+    pub async fn next_progress(&mut self) -> AotProgress {
+        // the pool only drops the sender once finished
+        let _ = self.progress.changed().await;
+        self.progress()
+    }
+
+    /// Cancels the task, see [`AotCancelHandle::cancel`].
+    pub fn cancel(&self) {
+        self.cancel.cancel()
+    }
+
+    /// Returns a handle cancelling the task from elsewhere, e.g. while it is awaited.
+    pub fn cancel_handle(&self) -> AotCancelHandle {
+        self.cancel.clone()
+    }
+}
+
+impl Future for AotTask {
+    type Output = Result<Vec<u8>, AotError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // the job catches panics, so a dropped sender means it never ran
+        Pin::new(&mut self.result)
+            .poll(cx)
+            .map(|r| r.unwrap_or(Err(AotError::Cancelled)))
+    }
+}
+
+impl Drop for AotTask {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+struct PoolInner {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+/// Compiles modules with one [`AotConfig`] on a fixed number of threads of its own, so
+/// that compilations taking seconds to minutes never block the async runtime.
+///
+/// The threads stop once the pool and its clones are dropped and the queued compilations
+/// are done.
+#[derive(Clone)]
+pub struct AotCompilePool {
+    inner: Arc<PoolInner>,
+}
+
+impl AotCompilePool {
+    /// Starts `threads` compiler threads, at least one, each with its own compiler created
+    /// from `config`.
+    pub fn new(config: &AotConfig, threads: usize) -> Result<Self, AotError> {
+        let compilers = (0..threads.max(1))
+            .map(|_| AotCompiler::create(config))
+            .collect::<WasmEdgeResult<Vec<_>>>()?;
+
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for (idx, mut compiler) in compilers.into_iter().enumerate() {
+            let queue = queue.clone();
+            thread::Builder::new()
+                .name(format!("wasmedge-aot-{}", idx))
+                .spawn(move || loop {
+                    let job = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    match job {
+                        // a job reports its own panics; this keeps the thread serving
+                        Ok(job) => {
+                            let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&mut compiler)));
+                        }
+                        Err(_) => break,
+                    }
+                })?;
+        }
+
+        Ok(AotCompilePool {
+            inner: Arc::new(PoolInner {
+                jobs: Mutex::new(jobs),
+            }),
+        })
+    }
+
+    /// Queues the compilation of the wasm binary `wasm`, like
+    /// [`AotCompiler::compile_bytes`].
+    pub fn compile(&self, wasm: Vec<u8>) -> AotTask {
+        self.submit(wasm, None, None)
+    }
+
+    /// Queues the asyncification of `wasm` for the imports of `builder` and the
+    /// compilation of the result, like [`AotCompiler::compile_async_module_bytes`]. Both
+    /// run on a compiler thread; asyncification errors are returned by the task.
+    pub fn compile_async_module(&self, builder: &AsyncLinkerBuilder, wasm: Vec<u8>) -> AotTask {
+        self.submit(wasm, Some(builder.asyncify_pass()), None)
+    }
+
+    /// Compiles every module of `wasms`, with at most `concurrency` of them queued or
+    /// being compiled at once, and returns the results in the same order.
+    pub async fn compile_batch<I>(
+        &self,
+        wasms: I,
+        concurrency: usize,
+    ) -> Vec<Result<Vec<u8>, AotError>>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut tasks = vec![];
+        for wasm in wasms {
+            let permit = permits.clone().acquire_owned().await.unwrap();
+            tasks.push(self.submit(wasm, None, Some(permit)));
+        }
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            results.push(task.await);
+        }
+        results
+    }
+
+    /// Queues a compilation, asyncifying first with `asyncify` if set, and holding
+    /// `permit` until it is done.
+    fn submit(
+        &self,
+        wasm: Vec<u8>,
+        asyncify: Option<AsyncifyPass>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> AotTask {
+        let (result_tx, result) = oneshot::channel();
+        let (progress_tx, progress) = watch::channel(AotProgress::Queued);
+        let cancel = AotCancelHandle(Arc::new(AtomicBool::new(false)));
+
+        let job_cancel = cancel.clone();
+        let job: Job = Box::new(move |compiler| {
+            let _permit = permit;
+            let r = match job_cancel.is_cancelled() {
+                true => Err(AotError::Cancelled),
+                false => {
+                    let _ = progress_tx.send(AotProgress::Compiling);
+                    let compile = AssertUnwindSafe(|| match &asyncify {
+                        Some(pass) => compiler.compile_bytes(&pass.run(&wasm)?),
+                        None => compiler.compile_bytes(&wasm),
+                    });
+                    match panic::catch_unwind(compile) {
+                        Ok(Ok(_)) if job_cancel.is_cancelled() => Err(AotError::Cancelled),
+                        Ok(r) => r,
+                        Err(payload) => Err(AotError::Panicked(panic_message(payload))),
+                    }
+                }
+            };
+            let _ = progress_tx.send(AotProgress::Finished);
+            let _ = result_tx.send(r);
+        });
+        // the threads only stop once the sender is dropped, so this cannot fail
+        let _ = self.inner.jobs.lock().unwrap().send(job);
+
+        AotTask {
+            result,
+            progress,
+            cancel,
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
@@ -0,0 +1,279 @@
+//! Runs several guest calls on one instance, each suspended on its own asyncify stack.
+
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use wasmedge_types::{
+    error::{CoreCommonError, CoreError, WasmEdgeError},
+    WasmEdgeResult,
+};
+
+use crate::core::{types::WasmVal, AsInstance};
+
+use super::{
+    error::CallError,
+    instance::function::CallTarget,
+    linker::{AsyncFutureList, AsyncLinker, MAIN_MEMORY},
+};
+
+/// The exported memory holding the asyncify unwind data.
+pub const ASYNCIFY_MEMORY: &str = "asyncify_memory";
+/// The exported shadow stack pointer of wasm32 toolchains.
+pub const STACK_POINTER: &str = "__stack_pointer";
+
+/// An [`AsyncLinker`] shared by many concurrent calls.
+///
+/// Each [`CoroutineFuture`] keeps its own pending host futures, a copy of the asyncify
+/// data and the live part of its shadow stack while it is suspended, so a new call can
+/// start while others wait on host futures.
+///
+/// Every switch copies the whole [`ASYNCIFY_MEMORY`] out and back in, as WasmEdge does
+/// not tell how much of it the unwound stack uses. Its size is set when the module is
+/// asyncified, usually to a single 64 KiB page, and bounds the cost of a switch.
+///
+/// The linker runs one call at a time, switching calls at await points. It is `!Send`,
+/// so poll its calls from a single thread, e.g. on a `tokio::task::LocalSet`.
+#[derive(Clone)]
+pub struct CoroutineLinker {
+    inner: Rc<RefCell<Pin<Box<AsyncLinker>>>>,
+    stack_base: i32,
+}
+
+impl CoroutineLinker {
+    /// Fails if the module does not export its asyncify data as [`ASYNCIFY_MEMORY`] or its
+    /// shadow stack pointer as an `i32` [`STACK_POINTER`] global. Without the latter,
+    /// interleaved calls would overwrite each other's shadow stacks.
+    pub fn new(mut linker: Pin<Box<AsyncLinker>>) -> WasmEdgeResult<Self> {
+        let linker_ctx = unsafe { linker.as_mut().get_unchecked_mut() };
+        let inst = linker_ctx
+            .inst
+            .as_ref()
+            .ok_or(WasmEdgeError::Core(CoreError::Common(
+                CoreCommonError::WrongVMWorkflow,
+            )))?;
+        inst.get_memory(ASYNCIFY_MEMORY)?;
+        let stack_pointer = inst.get_global(STACK_POINTER).map_err(|_| {
+            WasmEdgeError::Operation(format!(
+                "the module does not export {}, e.g. with `-C link-arg=--export={}`",
+                STACK_POINTER, STACK_POINTER
+            ))
+        })?;
+        let stack_base = match stack_pointer.get_value() {
+            WasmVal::I32(sp) => sp,
+            _ => {
+                return Err(WasmEdgeError::Operation(format!(
+                    "the {} global is not an i32",
+                    STACK_POINTER
+                )))
+            }
+        };
+
+        Ok(CoroutineLinker {
+            inner: Rc::new(RefCell::new(linker)),
+            stack_base,
+        })
+    }
+
+    /// Starts a call of the exported function `name` as a new coroutine.
+    pub fn call(&self, name: &str, args: Vec<WasmVal>) -> CoroutineFuture {
+        CoroutineFuture {
+            call: ParkedCall::new(name.into(), args, Some(self.stack_base)),
+            linker: self.inner.clone(),
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.borrow().is_poisoned()
+    }
+}
+
+struct Snapshot {
+    asyncify_data: Vec<u8>,
+    stack: Option<(i32, Vec<u8>)>,
+}
+
+/// A guest call that parks its asyncify data and host futures outside the linker
+/// whenever it suspends, so that other guest calls can run in between.
+pub(crate) struct ParkedCall {
+    futures: AsyncFutureList,
+    snapshot: Option<Snapshot>,
+    stack_base: Option<i32>,
+    target: CallTarget,
+    args: Vec<WasmVal>,
+}
+
+impl ParkedCall {
+    pub(crate) fn new(target: CallTarget, args: Vec<WasmVal>, stack_base: Option<i32>) -> Self {
+        ParkedCall {
+            futures: AsyncFutureList::new(),
+            snapshot: None,
+            stack_base,
+            target,
+            args,
+        }
+    }
+
+    fn save(&mut self, linker: &mut AsyncLinker) -> WasmEdgeResult<()> {
+        let inst = linker.inst.as_ref().ok_or(unreachable())?;
+
+        let asyncify_mem = inst.get_memory(ASYNCIFY_MEMORY)?;
+        let asyncify_data = asyncify_mem.get_data(0, asyncify_mem.size() * 65536)?;
+
+        let stack = match self.stack_base {
+            Some(base) => {
+                let mut sp_global = inst.get_global(STACK_POINTER)?;
+                let sp = match sp_global.get_value() {
+                    WasmVal::I32(sp) => sp,
+                    _ => return Err(unreachable()),
+                };
+                if sp < 0 || sp > base {
+                    return Err(WasmEdgeError::Operation(format!(
+                        "stack pointer {} is outside of the stack below {}",
+                        sp, base
+                    )));
+                }
+                let data = inst
+                    .get_memory(MAIN_MEMORY)?
+                    .get_data(sp as u32, (base - sp) as u32)?;
+                sp_global.set_value(WasmVal::I32(base));
+                Some((sp, data))
+            }
+            None => None,
+        };
+
+        self.snapshot = Some(Snapshot {
+            asyncify_data,
+            stack,
+        });
+        Ok(())
+    }
+
+    /// Puts the parked stacks back and starts rewinding into them.
+    fn restore(&mut self, linker: &mut AsyncLinker) -> WasmEdgeResult<()> {
+        let snapshot = match self.snapshot.take() {
+            Some(snapshot) => snapshot,
+            None => return Ok(()),
+        };
+        let inst = linker.inst.as_ref().ok_or(unreachable())?;
+
+        inst.get_memory(ASYNCIFY_MEMORY)?
+            .set_data(&snapshot.asyncify_data, 0)?;
+
+        if let Some((sp, data)) = snapshot.stack {
+            inst.get_memory(MAIN_MEMORY)?.set_data(&data, sp as u32)?;
+            inst.get_global(STACK_POINTER)?.set_value(WasmVal::I32(sp));
+        }
+
+        // the state was reset to normal when the call was parked
+        linker.real_call("asyncify_start_rewind", &[])?;
+        Ok(())
+    }
+
+    /// Resumes or starts the call. Returns `None` if the guest suspended again.
+    fn step(&mut self, linker: &mut AsyncLinker) -> Result<Option<Vec<WasmVal>>, CallError> {
+        self.restore(linker)?;
+        let v = match linker.call_target(&self.target, &self.args, None) {
+            Ok(v) => v,
+            Err(e) => {
+                let e = linker.vm_err.take().unwrap_or_else(|| e.into());
+                return Err(linker.trap_report(e));
+            }
+        };
+        if linker.asyncify_done()? {
+            return Ok(Some(v));
+        }
+        // the guest is unwound; park its stacks so that other calls can run
+        self.save(linker)?;
+        linker.asyncify_stop_unwind()?;
+        Ok(None)
+    }
+
+    pub(crate) fn poll_call(
+        &mut self,
+        linker: &mut AsyncLinker,
+    ) -> Poll<Result<Vec<WasmVal>, CallError>> {
+        if linker.poisoned {
+            return Poll::Ready(Err(WasmEdgeError::Core(CoreError::Common(
+                CoreCommonError::WrongVMWorkflow,
+            ))
+            .into()));
+        }
+
+        linker.swap_func_futures(&mut self.futures);
+        let r = self.step(linker);
+        if r.is_err() {
+            self.snapshot = None;
+            linker.reset_call_state();
+        }
+        linker.swap_func_futures(&mut self.futures);
+
+        match r {
+            Ok(Some(v)) => Poll::Ready(Ok(v)),
+            Ok(None) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+/// A guest call running as a coroutine of a [`CoroutineLinker`].
+///
+/// Dropping it while suspended drops its host futures and saved stacks; the other
+/// coroutines are not affected.
+pub struct CoroutineFuture {
+    // dropped before `linker`, as the host futures may borrow it
+    call: ParkedCall,
+    linker: Rc<RefCell<Pin<Box<AsyncLinker>>>>,
+}
+
+impl Future for CoroutineFuture {
+    type Output = Result<Vec<WasmVal>, CallError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let shared = this.linker.clone();
+        // a host function of another coroutine polling this one
+        let mut guard = match shared.try_borrow_mut() {
+            Ok(guard) => guard,
+            Err(_) => {
+                return Poll::Ready(Err(WasmEdgeError::Core(CoreError::Common(
+                    CoreCommonError::WrongVMWorkflow,
+                ))
+                .into()))
+            }
+        };
+        let linker = unsafe { guard.as_mut().get_unchecked_mut() };
+
+        linker.set_waker(cx.waker());
+        this.call.poll_call(linker)
+    }
+}
+
+/// The future returned by [`AsyncLinker::call_nested`], for calling back into the guest
+/// from an async host function.
+///
+/// The nested call may suspend on async host functions of its own; its asyncify data is
+/// parked while the outer call unwinds and restored when the outer host function polls it
+/// again. Suspending requires the module to export [`ASYNCIFY_MEMORY`].
+pub struct NestedCallFuture<'a> {
+    pub(crate) call: ParkedCall,
+    pub(crate) linker: &'a mut AsyncLinker,
+}
+
+impl Future for NestedCallFuture<'_> {
+    type Output = Result<Vec<WasmVal>, CallError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let NestedCallFuture { call, linker } = self.get_mut();
+        linker.set_waker(cx.waker());
+        call.poll_call(linker)
+    }
+}
+
+fn unreachable() -> WasmEdgeError {
+    WasmEdgeError::Core(CoreError::Common(CoreCommonError::RuntimeError))
+}
@@ -0,0 +1,271 @@
+use std::{fmt, sync::Arc};
+
+use wasmedge_types::error::WasmEdgeError;
+
+/// Any error a host function can fail with.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The result of a host function. Any `std::error::Error` converts into it with `?`.
+pub type HostResult<T> = Result<T, BoxError>;
+
+/// The error returned by calls that can fail for reasons other than the WasmEdge runtime.
+#[derive(Debug)]
+pub enum CallError {
+    /// The deadline passed before the guest call finished. The instance has been reset.
+    Timeout,
+    /// A host function failed, trapping the guest.
+    Host(HostError),
+    /// The WasmEdge runtime failed.
+    WasmEdge(WasmEdgeError),
+    /// The guest call failed; the report says where and wraps the cause.
+    Trap(Box<TrapReport>),
+    /// The guest terminated the instance, e.g. with WASI `proc_exit`, with this exit code.
+    Exited(u32),
+}
+
+impl CallError {
+    /// Returns the error raised by the host function, if it is of type `E`.
+    pub fn downcast_ref<E: std::error::Error + 'static>(&self) -> Option<&E> {
+        match self.cause() {
+            CallError::Host(e) => e.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the error behind the trap report, if any.
+    pub fn cause(&self) -> &CallError {
+        match self {
+            CallError::Trap(report) => report.cause.cause(),
+            e => e,
+        }
+    }
+
+    /// Returns the exit code if the guest exited instead of returning.
+    pub fn exit_code(&self) -> Option<u32> {
+        match self.cause() {
+            CallError::Exited(code) => Some(*code),
+            _ => None,
+        }
+    }
+
+    pub fn trap(&self) -> Option<&TrapReport> {
+        match self {
+            CallError::Trap(report) => Some(report),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Timeout => write!(f, "guest call timed out"),
+            CallError::Host(e) => write!(f, "{}", e),
+            CallError::WasmEdge(e) => write!(f, "{}", e),
+            CallError::Trap(report) => write!(f, "{}", report),
+            CallError::Exited(code) => write!(f, "guest exited with code {}", code),
+        }
+    }
+}
+
+impl std::error::Error for CallError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CallError::Timeout | CallError::Exited(_) => None,
+            CallError::Host(e) => Some(e),
+            CallError::WasmEdge(e) => Some(e),
+            CallError::Trap(report) => report.cause.source(),
+        }
+    }
+}
+
+impl From<WasmEdgeError> for CallError {
+    fn from(e: WasmEdgeError) -> Self {
+        CallError::WasmEdge(e)
+    }
+}
+
+/// The error a host function returned, along with the import it was called through.
+#[derive(Debug)]
+pub struct HostError {
+    import: String,
+    error: BoxError,
+}
+
+impl HostError {
+    pub(crate) fn new(import: &str, error: BoxError) -> Self {
+        HostError {
+            import: import.to_string(),
+            error,
+        }
+    }
+
+    /// Returns the import that failed, as `module.name`.
+    pub fn import(&self) -> &str {
+        &self.import
+    }
+
+    pub fn downcast_ref<E: std::error::Error + 'static>(&self) -> Option<&E> {
+        self.error.downcast_ref()
+    }
+
+    pub fn into_inner(self) -> BoxError {
+        self.error
+    }
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "host function {} failed: {}", self.import, self.error)
+    }
+}
+
+impl std::error::Error for HostError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+/// Returned by a host function to terminate the guest with an exit code, as WASI
+/// `proc_exit` does. The call fails with [`CallError::Exited`] instead of a trap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestExit(pub u32);
+
+impl fmt::Display for GuestExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "guest exited with code {}", self.0)
+    }
+}
+
+impl std::error::Error for GuestExit {}
+
+/// The asyncify state of the instance when a guest call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsyncifyPhase {
+    /// The guest was running normally.
+    Normal,
+    /// The guest was unwinding its stack to suspend on an async import.
+    Unwinding,
+    /// The guest was rewinding its stack to resume an async import.
+    Rewinding,
+}
+
+impl fmt::Display for AsyncifyPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsyncifyPhase::Normal => write!(f, "normal execution"),
+            AsyncifyPhase::Unwinding => write!(f, "unwind"),
+            AsyncifyPhase::Rewinding => write!(f, "rewind"),
+        }
+    }
+}
+
+/// A call crossing between host and guest, as listed by
+/// [`TrapReport::boundary_frames`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// A guest function entered from the host, named by its export or `<callback>` for
+    /// callbacks that are not exported.
+    Guest(String),
+    /// A host function called by the guest, as `module.name`.
+    Host(Arc<str>),
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Guest(name) => write!(f, "guest {}", name),
+            Frame::Host(name) => write!(f, "host {}", name),
+        }
+    }
+}
+
+/// Where a guest call failed, wrapping the error that made it fail.
+///
+/// WasmEdge 0.10 does not report frames inside the guest, so there is no backtrace: the
+/// report only lists the calls crossing between host and guest, innermost first, i.e.
+/// the guest functions the host entered and the host functions the guest called. The
+/// function that trapped may be any function called from the innermost guest frame.
+#[derive(Debug)]
+pub struct TrapReport {
+    pub(crate) cause: CallError,
+    pub(crate) phase: Option<AsyncifyPhase>,
+    pub(crate) import: Option<Arc<str>>,
+    pub(crate) boundary_frames: Vec<Frame>,
+}
+
+impl TrapReport {
+    pub fn cause(&self) -> &CallError {
+        &self.cause
+    }
+
+    /// Returns the asyncify state at the time of the failure, if it could be read.
+    pub fn phase(&self) -> Option<AsyncifyPhase> {
+        self.phase
+    }
+
+    /// Returns the innermost guest function entered from the host, by export name.
+    ///
+    /// This is where the failing guest code was entered, not necessarily the function
+    /// that trapped, which WasmEdge does not tell.
+    pub fn function(&self) -> Option<&str> {
+        self.boundary_frames.iter().find_map(|frame| match frame {
+            Frame::Guest(name) => Some(name.as_str()),
+            Frame::Host(_) => None,
+        })
+    }
+
+    /// Returns the async import that was in progress or that the guest was suspended on.
+    pub fn import(&self) -> Option<&str> {
+        self.import.as_deref()
+    }
+
+    /// Returns the calls crossing between host and guest that led to the failure,
+    /// innermost first.
+    pub fn boundary_frames(&self) -> &[Frame] {
+        &self.boundary_frames
+    }
+}
+
+impl fmt::Display for TrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.cause)?;
+        if let Some(phase) = self.phase {
+            write!(f, "\n  during {}", phase)?;
+        }
+        if let Some(import) = &self.import {
+            write!(f, "\n  import: {}", import)?;
+        }
+        if !self.boundary_frames.is_empty() {
+            write!(f, "\n  host/guest calls:")?;
+            for (idx, frame) in self.boundary_frames.iter().enumerate() {
+                write!(f, "\n    {}: {}", idx, frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trap_report_lists_the_boundary_frames() {
+        let report = TrapReport {
+            cause: CallError::Timeout,
+            phase: Some(AsyncifyPhase::Unwinding),
+            import: Some("host.sleep".into()),
+            boundary_frames: vec![
+                Frame::Host("host.sleep".into()),
+                Frame::Guest("run".to_string()),
+            ],
+        };
+        assert_eq!(report.function(), Some("run"));
+        let text = report.to_string();
+        assert!(text.contains("during unwind"));
+        assert!(text.contains("import: host.sleep"));
+        assert!(text.contains("0: host host.sleep\n    1: guest run"));
+        assert!(!text.contains("backtrace"));
+    }
+}
@@ -0,0 +1,137 @@
+//! Host functions that run on a blocking thread pool while the guest is suspended.
+
+use std::{ffi::c_void, sync::Arc};
+
+use wasmedge_sys::ffi;
+use wasmedge_types::{
+    error::{FuncError, WasmEdgeError},
+    ValType, WasmEdgeResult,
+};
+
+use crate::{
+    core::types::WasmVal,
+    sdk::{
+        error::HostResult,
+        linker::{AsyncLinker, MAIN_MEMORY},
+    },
+};
+
+use super::function::poll_host_future;
+
+/// A blocking host function. It runs on `tokio`'s blocking pool, so it cannot touch the
+/// linker; guest memory is reached through [`BlockingCall::buffers`]. Called outside a
+/// `tokio` runtime, it fails without running and the guest traps.
+pub type BlockingFn = fn(&mut BlockingCall) -> HostResult<Vec<WasmVal>>;
+
+/// The arguments of a [`BlockingFn`].
+#[derive(Debug)]
+pub struct BlockingCall {
+    /// The raw arguments passed by the guest.
+    pub args: Vec<WasmVal>,
+    /// Copies of the guest memory regions named by the function's buffer parameters, in
+    /// the order they were declared. They are written back to guest memory when the
+    /// function succeeds.
+    pub buffers: Vec<Vec<u8>>,
+}
+
+pub(crate) struct BlockingFuncDef {
+    pub(crate) import: Arc<str>,
+    pub(crate) real_fn: BlockingFn,
+    /// `(ptr, len)` parameter indices of each guest buffer.
+    pub(crate) buffers: Vec<(usize, usize)>,
+}
+
+impl BlockingFuncDef {
+    pub(crate) fn new(
+        import: Arc<str>,
+        ty: &(Vec<ValType>, Vec<ValType>),
+        buffers: &[(usize, usize)],
+        real_fn: BlockingFn,
+    ) -> WasmEdgeResult<Self> {
+        let is_i32 = |idx: usize| matches!(ty.0.get(idx), Some(ValType::I32));
+        if !buffers
+            .iter()
+            .all(|(ptr, len)| is_i32(*ptr) && is_i32(*len))
+        {
+            return Err(WasmEdgeError::Func(FuncError::Type));
+        }
+        Ok(BlockingFuncDef {
+            import,
+            real_fn,
+            buffers: buffers.to_vec(),
+        })
+    }
+
+    fn regions(&self, args: &[WasmVal]) -> WasmEdgeResult<Vec<(usize, usize)>> {
+        self.buffers
+            .iter()
+            .map(|(ptr, len)| match (&args[*ptr], &args[*len]) {
+                (WasmVal::I32(ptr), WasmVal::I32(len)) => {
+                    Ok((*ptr as u32 as usize, *len as u32 as usize))
+                }
+                _ => Err(WasmEdgeError::Func(FuncError::Type)),
+            })
+            .collect()
+    }
+}
+
+async fn run_blocking(
+    linker: &mut AsyncLinker,
+    def: &BlockingFuncDef,
+    args: Vec<WasmVal>,
+) -> HostResult<Vec<WasmVal>> {
+    let regions = def.regions(&args)?;
+    let mut buffers = Vec::with_capacity(regions.len());
+    for (offset, len) in &regions {
+        buffers.push(linker.get_memory(MAIN_MEMORY, *offset, *len)?.to_vec());
+    }
+
+    // panicking here would unwind through the WasmEdge host function callback
+    let rt = tokio::runtime::Handle::try_current().map_err(|_| {
+        WasmEdgeError::Operation(format!(
+            "blocking host function {} called outside a tokio runtime",
+            def.import
+        ))
+    })?;
+
+    let real_fn = def.real_fn;
+    let mut call = BlockingCall { args, buffers };
+    // if the guest call is cancelled meanwhile, the result is dropped and memory is untouched
+    let (call, result) = rt
+        .spawn_blocking(move || {
+            let result = real_fn(&mut call);
+            (call, result)
+        })
+        .await
+        .map_err(|e| WasmEdgeError::Operation(format!("blocking host function failed: {}", e)))?;
+    let returns = result?;
+
+    for ((offset, len), buf) in regions.into_iter().zip(call.buffers) {
+        let mem = linker.get_mut_memory(MAIN_MEMORY, offset, len)?;
+        let n = len.min(buf.len());
+        mem[..n].copy_from_slice(&buf[..n]);
+    }
+    Ok(returns)
+}
+
+pub(crate) extern "C" fn wrapper_blocking_fn(
+    key_ptr: *mut c_void,
+    data_ptr: *mut c_void,
+    _mem_ctx: *mut ffi::WasmEdge_MemoryInstanceContext,
+    params: *const ffi::WasmEdge_Value,
+    param_len: u32,
+    returns: *mut ffi::WasmEdge_Value,
+    return_len: u32,
+) -> ffi::WasmEdge_Result {
+    // owned by `AsyncLinker::host_fns`, which outlives the import
+    let def = unsafe { &*(key_ptr as *const BlockingFuncDef) };
+    poll_host_future(
+        data_ptr,
+        &def.import,
+        params,
+        param_len,
+        returns,
+        return_len,
+        |linker, input| Box::pin(run_blocking(linker, def, input)),
+    )
+}
@@ -1,13 +1,15 @@
 use std::{
     ffi::c_void,
     future::Future,
+    marker::PhantomData,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
 use wasmedge_sys::ffi;
 use wasmedge_types::{
-    error::{FuncError, WasmEdgeError},
+    error::{CoreCommonError, CoreError, FuncError, WasmEdgeError},
     ValType, WasmEdgeResult,
 };
 
@@ -16,45 +18,357 @@ use crate::{
         instance::function::{FuncType, Function, InnerFunc},
         types::WasmVal,
     },
-    sdk::linker::AsyncLinker,
+    sdk::{
+        error::{CallError, Frame, HostResult},
+        linker::AsyncLinker,
+        AsyncFn, SendAsyncFn, SyncFn,
+    },
 };
 
+#[cfg(feature = "tokio")]
+use std::{io, time::Instant};
+
+#[cfg(feature = "tokio")]
+use crate::sdk::wasi::poll::{self, SleepUntil};
+
 pub use crate::core::instance::function::FuncRef;
 
-pub type ResultFuture<'a> = Box<dyn Future<Output = WasmEdgeResult<Vec<WasmVal>>> + 'a>;
+/// A guest function handed to the host as a `funcref` argument, kept so that the host can
+/// call it later with [`AsyncLinker::call_ref`].
+///
+/// Cloning is cheap. The handle is bound to the instance it was created from: once that
+/// instance is dropped, [`call_ref`](AsyncLinker::call_ref) fails with `FuncNotFound`
+/// on any linker, even one reusing its memory, since instance ids are never reused.
+#[derive(Debug, Clone)]
+pub struct GuestCallback {
+    /// Only dereferenced after checking that the linker running `owner` is the caller.
+    pub(crate) func: Arc<FuncRef>,
+    /// The [`AsyncLinker::id`] of the instance the function belongs to.
+    pub(crate) owner: u64,
+    pub(crate) name: Option<String>,
+    pub(crate) ty: Arc<(Vec<ValType>, Vec<ValType>)>,
+}
+
+impl GuestCallback {
+    /// Returns the name of the guest export this callback refers to, if it is exported.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the parameter and result types, read when the callback was retained so
+    /// that they stay available after the instance is gone.
+    pub fn func_type(&self) -> WasmEdgeResult<(Vec<ValType>, Vec<ValType>)> {
+        Ok(self.ty.as_ref().clone())
+    }
+}
+
+/// What a call future invokes: an export by name or a retained guest callback.
+#[derive(Debug, Clone)]
+pub(crate) enum CallTarget {
+    Export(String),
+    Ref(GuestCallback),
+}
+
+impl From<&str> for CallTarget {
+    fn from(name: &str) -> Self {
+        CallTarget::Export(name.to_string())
+    }
+}
+
+pub type ResultFuture<'a> = Box<dyn Future<Output = HostResult<Vec<WasmVal>>> + 'a>;
 
+/// A [`ResultFuture`] that can be sent across threads, returned by [`SendAsyncFn`] host functions.
+pub type SendResultFuture<'a> = Box<dyn Future<Output = HostResult<Vec<WasmVal>>> + Send + 'a>;
+
+/// A host function registered with a linker, along with the import that names it.
+///
+/// Owned by [`AsyncLinker`], which passes it to the wrapper as the key pointer.
+pub(crate) struct HostFuncDef<F> {
+    /// Shared with the frames and errors naming the import, so that a call does not
+    /// copy it.
+    pub(crate) import: Arc<str>,
+    pub(crate) real_fn: F,
+}
+
+/// The future returned by [`AsyncLinker::call`].
+///
+/// Dropping it while the guest is suspended cancels the call: the pending host
+/// futures are dropped and the asyncify state is reset, so the linker can be
+/// called again. If the reset fails the linker is poisoned instead.
+///
+/// While the guest is suspended this future owns the pending host futures, which may
+/// not be `Send`, so it is `!Send`. Run it on a `tokio::task::LocalSet`, or build a
+/// [`SendAsyncLinker`](crate::SendAsyncLinker) to get a [`SendFuture`] instead.
 pub struct WasmEdgeResultFuture<'a> {
     pub(crate) linker: &'a mut AsyncLinker,
-    pub(crate) name: String,
+    pub(crate) target: CallTarget,
     pub(crate) args: Vec<WasmVal>,
+    pub(crate) in_progress: bool,
+    pub(crate) _not_send: PhantomData<*mut ()>,
+}
+
+impl<'a> WasmEdgeResultFuture<'a> {
+    pub(crate) fn new(linker: &'a mut AsyncLinker, target: CallTarget, args: Vec<WasmVal>) -> Self {
+        WasmEdgeResultFuture {
+            linker,
+            target,
+            args,
+            in_progress: false,
+            _not_send: PhantomData,
+        }
+    }
 }
 
 impl Future for WasmEdgeResultFuture<'_> {
-    type Output = WasmEdgeResult<Vec<WasmVal>>;
+    type Output = Result<Vec<WasmVal>, CallError>;
 
     fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> std::task::Poll<Self::Output> {
-        let WasmEdgeResultFuture { linker, name, args } = self.get_mut();
-        linker.cx = cx.waker().clone();
+        let WasmEdgeResultFuture {
+            linker,
+            target,
+            args,
+            in_progress,
+            ..
+        } = self.get_mut();
+
+        if linker.poisoned {
+            return Poll::Ready(Err(WasmEdgeError::Core(CoreError::Common(
+                CoreCommonError::WrongVMWorkflow,
+            ))
+            .into()));
+        }
+
+        linker.set_waker(cx.waker());
+        *in_progress = true;
 
         if let Err(e) = linker.asyncify_resume() {
-            return Poll::Ready(Err(e));
+            linker.reset_call_state();
+            *in_progress = false;
+            return Poll::Ready(Err(e.into()));
         }
 
-        match linker.real_call(name, args) {
+        let r = match linker.call_target(target, args, None) {
             Ok(v) => match linker.asyncify_done() {
                 Ok(true) => Poll::Ready(Ok(v)),
-                Ok(false) => Poll::Pending,
-                Err(e) => return Poll::Ready(Err(e)),
+                Ok(false) => return Poll::Pending,
+                Err(e) => {
+                    linker.reset_call_state();
+                    Poll::Ready(Err(e.into()))
+                }
             },
             Err(e) => {
-                if let Err(asyncify_normal_err) = linker.asyncify_normal() {
-                    Poll::Ready(Err(asyncify_normal_err))
-                } else {
-                    let e = linker.vm_err.take().unwrap_or(e);
-                    Poll::Ready(Err(e))
+                let e = linker.vm_err.take().unwrap_or_else(|| e.into());
+                let e = linker.trap_report(e);
+                linker.reset_call_state();
+                Poll::Ready(Err(e))
+            }
+        };
+        *in_progress = false;
+        r
+    }
+}
+
+impl Drop for WasmEdgeResultFuture<'_> {
+    fn drop(&mut self) {
+        if self.in_progress {
+            self.linker.reset_call_state();
+        }
+    }
+}
+
+/// The future returned by [`AsyncLinker::call_with_deadline`].
+///
+/// Resolves to [`CallError::Timeout`] once the deadline passes, whether the guest is
+/// computing or waiting on a host future. The guest runs on a WasmEdge worker thread
+/// while this future is polled, and the polling thread waits for it; the async imports
+/// it calls hand their futures back to the polling thread, which drives them before
+/// resuming the guest.
+#[cfg(feature = "tokio")]
+pub struct DeadlineFuture<'a> {
+    pub(crate) call: WasmEdgeResultFuture<'a>,
+    pub(crate) deadline: Instant,
+    pub(crate) timer: io::Result<SleepUntil>,
+}
+
+#[cfg(feature = "tokio")]
+impl<'a> DeadlineFuture<'a> {
+    pub(crate) fn new(call: WasmEdgeResultFuture<'a>, deadline: Instant) -> Self {
+        DeadlineFuture {
+            call,
+            deadline,
+            timer: poll::sleep_until(deadline),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Future for DeadlineFuture<'_> {
+    type Output = Result<Vec<WasmVal>, CallError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let DeadlineFuture {
+            call,
+            deadline,
+            timer,
+        } = self.get_mut();
+        let timer = match timer {
+            Ok(timer) => timer,
+            Err(e) => {
+                return Poll::Ready(Err(WasmEdgeError::Operation(format!(
+                    "cannot wait for the deadline: {}",
+                    e
+                ))
+                .into()))
+            }
+        };
+        let WasmEdgeResultFuture {
+            linker,
+            target,
+            args,
+            in_progress,
+            ..
+        } = call;
+
+        if linker.poisoned {
+            return Poll::Ready(Err(WasmEdgeError::Core(CoreError::Common(
+                CoreCommonError::WrongVMWorkflow,
+            ))
+            .into()));
+        }
+
+        linker.set_waker(cx.waker());
+        while Pin::new(&mut *timer).poll(cx).is_pending() {
+            if *in_progress {
+                // the guest is suspended on the future its last async import left here
+                let r = match linker.func_futures().back_mut() {
+                    Some(fut) => match fut.as_mut().poll(cx) {
+                        Poll::Ready(r) => r,
+                        Poll::Pending => return Poll::Pending,
+                    },
+                    None => Err(WasmEdgeError::Core(CoreError::Common(
+                        CoreCommonError::WrongVMWorkflow,
+                    ))
+                    .into()),
+                };
+                // the worker only takes the output back, never the future itself
+                linker.func_futures().pop_back();
+                linker
+                    .func_futures()
+                    .push_back(Box::pin(std::future::ready(r)));
+                if Pin::new(&mut *timer).poll(cx).is_ready() {
+                    break;
+                }
+                if let Err(e) = linker.asyncify_resume() {
+                    linker.reset_call_state();
+                    *in_progress = false;
+                    return Poll::Ready(Err(e.into()));
+                }
+            }
+            *in_progress = true;
+
+            match linker.call_target(target, args, Some(*deadline)) {
+                Ok(v) => match linker.asyncify_done() {
+                    Ok(true) => {
+                        *in_progress = false;
+                        return Poll::Ready(Ok(v));
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        linker.reset_call_state();
+                        *in_progress = false;
+                        return Poll::Ready(Err(e.into()));
+                    }
+                },
+                Err(e) => {
+                    let e = linker.vm_err.take().unwrap_or_else(|| e.into());
+                    let e = linker.trap_report(e);
+                    linker.reset_call_state();
+                    *in_progress = false;
+                    return Poll::Ready(match e.cause() {
+                        CallError::WasmEdge(WasmEdgeError::Core(CoreError::Common(
+                            CoreCommonError::Interrupted,
+                        ))) => Err(CallError::Timeout),
+                        _ => Err(e),
+                    });
                 }
             }
         }
+
+        if *in_progress {
+            linker.reset_call_state();
+            *in_progress = false;
+        }
+        Poll::Ready(Err(CallError::Timeout))
+    }
+}
+
+/// The future of an async import called by a guest running on a WasmEdge worker thread.
+///
+/// The worker only leaves it to the thread polling the call, which creates the host
+/// future on its first poll, so host futures are never created or polled on the worker.
+struct Offloaded<'a> {
+    new_future: Option<Box<dyn FnOnce() -> Pin<ResultFuture<'a>> + Send + 'a>>,
+    fut: Option<Pin<ResultFuture<'a>>>,
+}
+
+impl Future for Offloaded<'_> {
+    type Output = HostResult<Vec<WasmVal>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Offloaded { new_future, fut } = self.get_mut();
+        let fut = match new_future.take() {
+            Some(new_future) => fut.insert(new_future()),
+            None => match fut {
+                Some(fut) => fut,
+                None => panic!("`Offloaded` polled after completion"),
+            },
+        };
+        fut.as_mut().poll(cx)
+    }
+}
+
+mod sealed {
+    /// The call futures a [`SendFuture`](super::SendFuture) can wrap.
+    pub trait CallFuture: std::future::Future + Unpin {}
+}
+
+impl sealed::CallFuture for WasmEdgeResultFuture<'_> {}
+
+#[cfg(feature = "tokio")]
+impl sealed::CallFuture for DeadlineFuture<'_> {}
+
+/// Wraps a call future of a [`SendAsyncLinker`](crate::SendAsyncLinker) so it can be spawned
+/// on a multithreaded runtime.
+///
+/// The call futures of a plain [`AsyncLinker`] stay `!Send`; only a `SendAsyncLinker`
+/// hands out this type.
+pub struct SendFuture<F: sealed::CallFuture> {
+    inner: F,
+}
+
+impl<F: sealed::CallFuture> SendFuture<F> {
+    /// # Safety
+    ///
+    /// Every host future the call can hold while the guest is suspended must be `Send`,
+    /// i.e. the linker must have been built without `add_async_func` and
+    /// `add_blocking_func` functions, as [`AsyncLinkerBuilder::instance_send`] checks.
+    ///
+    /// [`AsyncLinkerBuilder::instance_send`]: crate::AsyncLinkerBuilder::instance_send
+    pub(crate) unsafe fn new(inner: F) -> Self {
+        SendFuture { inner }
+    }
+}
+
+// Safety: a `SendFuture` wraps one of the call futures of this crate, which are `!Send`
+// only because of the host futures they may hold, and `SendFuture::new` requires those
+// to be `Send`.
+unsafe impl<F: sealed::CallFuture> Send for SendFuture<F> {}
+
+impl<F: sealed::CallFuture> Future for SendFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().inner).poll(cx)
     }
 }
 
@@ -77,8 +391,67 @@ pub(crate) extern "C" fn wrapper_async_fn(
     returns: *mut ffi::WasmEdge_Value,
     return_len: u32,
 ) -> ffi::WasmEdge_Result {
+    // owned by `AsyncLinker::host_fns`, which outlives the import
+    let def = unsafe { &*(key_ptr as *const HostFuncDef<AsyncFn>) };
+    poll_host_future(
+        data_ptr,
+        &def.import,
+        params,
+        param_len,
+        returns,
+        return_len,
+        |linker, input| Pin::from((def.real_fn)(linker, input)),
+    )
+}
+
+pub(crate) extern "C" fn wrapper_send_async_fn(
+    key_ptr: *mut c_void,
+    data_ptr: *mut c_void,
+    _mem_ctx: *mut ffi::WasmEdge_MemoryInstanceContext,
+    params: *const ffi::WasmEdge_Value,
+    param_len: u32,
+    returns: *mut ffi::WasmEdge_Value,
+    return_len: u32,
+) -> ffi::WasmEdge_Result {
+    // owned by `AsyncLinker::host_fns`, which outlives the import
+    let def = unsafe { &*(key_ptr as *const HostFuncDef<SendAsyncFn>) };
+    poll_host_future(
+        data_ptr,
+        &def.import,
+        params,
+        param_len,
+        returns,
+        return_len,
+        |linker, input| {
+            let fut: ResultFuture = (def.real_fn)(linker, input);
+            Pin::from(fut)
+        },
+    )
+}
+
+/// Drives the host future behind an async import.
+///
+/// On a normal call `new_future` creates the future; while rewinding, the pending one
+/// is taken back from the linker. If it is still pending the guest is unwound.
+/// An error returned by the future is recorded as raised by `import`.
+///
+/// A guest running on a WasmEdge worker thread is unwound right away, leaving the
+/// future to be created and polled by the thread polling the call.
+pub(crate) fn poll_host_future<'a, F>(
+    data_ptr: *mut c_void,
+    import: &Arc<str>,
+    params: *const ffi::WasmEdge_Value,
+    param_len: u32,
+    returns: *mut ffi::WasmEdge_Value,
+    return_len: u32,
+    new_future: F,
+) -> ffi::WasmEdge_Result
+where
+    F: FnOnce(&'a mut AsyncLinker, Vec<WasmVal>) -> Pin<ResultFuture<'a>> + Send + 'a,
+{
     if let Some(data) = unsafe { (data_ptr as *mut AsyncLinker).as_mut() } {
-        let mut cous = || -> WasmEdgeResult<ffi::WasmEdge_Result> {
+        data.frames.push(Frame::Host(import.clone()));
+        let cous = || -> WasmEdgeResult<ffi::WasmEdge_Result> {
             let linker = unsafe { (data_ptr as *mut AsyncLinker).as_mut().unwrap() };
 
             let cx = data.cx.clone();
@@ -86,9 +459,6 @@ pub(crate) extern "C" fn wrapper_async_fn(
             let fut_is_ready;
             let r = {
                 let fut = if data.asyncify_done()? {
-                    let real_fn: fn(&mut AsyncLinker, Vec<WasmVal>) -> ResultFuture =
-                        unsafe { std::mem::transmute(key_ptr) };
-
                     let input = {
                         let raw_input =
                             unsafe { std::slice::from_raw_parts(params, param_len as usize) };
@@ -98,8 +468,23 @@ pub(crate) extern "C" fn wrapper_async_fn(
                             .collect::<Vec<WasmVal>>()
                     };
 
-                    Some(Pin::from(real_fn(linker, input)))
+                    if data.offloaded {
+                        // on a worker thread: suspend without polling, `DeadlineFuture`
+                        // drives the future and resumes the guest once it is ready
+                        let fut = Offloaded {
+                            new_future: Some(Box::new(move || new_future(linker, input))),
+                            fut: None,
+                        };
+                        data.suspended_import = Some(import.clone());
+                        data.func_futures().push_back(Box::pin(fut));
+                        data.asyncify_yield()?;
+                        return Ok(ffi::WasmEdge_Result { Code: 0 });
+                    }
+                    Some(new_future(linker, input))
                 } else {
+                    // rewound back into this import: leave the rewind before polling,
+                    // so the host function may call back into the guest
+                    data.asyncify_stop_rewind()?;
                     linker.func_futures().pop_back()
                 };
 
@@ -115,6 +500,7 @@ pub(crate) extern "C" fn wrapper_async_fn(
                 match Future::poll(fut.as_mut(), &mut cx) {
                     std::task::Poll::Ready(result) => {
                         fut_is_ready = true;
+                        data.suspended_import = None;
                         match result {
                             Ok(v) => {
                                 assert!(v.len() == return_len);
@@ -124,33 +510,35 @@ pub(crate) extern "C" fn wrapper_async_fn(
                                 ffi::WasmEdge_Result { Code: 0 }
                             }
                             Err(e) => {
-                                let _ = data.vm_err.insert(e);
+                                data.host_failed(import, e);
                                 ffi::WasmEdge_Result { Code: 0x89 }
                             }
                         }
                     }
                     std::task::Poll::Pending => {
                         fut_is_ready = false;
+                        data.suspended_import = Some(import.clone());
                         data.func_futures().push_back(fut);
                         ffi::WasmEdge_Result { Code: 0 }
                     }
                 }
             };
 
-            if fut_is_ready {
-                data.asyncify_normal()?;
-            } else {
+            if !fut_is_ready {
                 data.asyncify_yield()?;
-            };
+            }
             Ok(r)
         };
-        match cous() {
+        let r = match cous() {
             Ok(r) => r,
             Err(e) => {
-                let _ = data.vm_err.insert(e);
+                data.record_trap();
+                let _ = data.vm_err.insert(e.into());
                 ffi::WasmEdge_Result { Code: 0x89 }
             }
-        }
+        };
+        data.frames.pop();
+        r
     } else {
         // unreachable
         ffi::WasmEdge_Result { Code: 0x89 }
@@ -166,8 +554,8 @@ pub extern "C" fn wrapper_fn(
     returns: *mut ffi::WasmEdge_Value,
     return_len: u32,
 ) -> ffi::WasmEdge_Result {
-    let real_fn: fn(&mut AsyncLinker, &[WasmVal]) -> WasmEdgeResult<Vec<WasmVal>> =
-        unsafe { std::mem::transmute(key_ptr) };
+    // owned by `AsyncLinker::host_fns`, which outlives the import
+    let def = unsafe { &*(key_ptr as *const HostFuncDef<SyncFn>) };
 
     let input = {
         let raw_input = unsafe { std::slice::from_raw_parts(params, param_len as usize) };
@@ -181,9 +569,10 @@ pub extern "C" fn wrapper_fn(
     let raw_returns = unsafe { std::slice::from_raw_parts_mut(returns, return_len) };
 
     if let Some(data) = unsafe { (data as *mut AsyncLinker).as_mut() } {
-        let result = real_fn(data, &input);
+        data.frames.push(Frame::Host(def.import.clone()));
+        let result = (def.real_fn)(data, &input);
 
-        match result {
+        let r = match result {
             Ok(v) => {
                 assert!(v.len() == return_len);
                 for (idx, item) in v.into_iter().enumerate() {
@@ -192,10 +581,12 @@ pub extern "C" fn wrapper_fn(
                 ffi::WasmEdge_Result { Code: 0 }
             }
             Err(e) => {
-                let _ = data.vm_err.insert(e);
+                data.host_failed(&def.import, e);
                 ffi::WasmEdge_Result { Code: 0x89 }
             }
-        }
+        };
+        data.frames.pop();
+        r
     } else {
         ffi::WasmEdge_Result { Code: 0x89 }
     }
@@ -229,3 +620,31 @@ impl Function {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn offloaded_future_is_created_on_first_poll() {
+        static CREATED: AtomicBool = AtomicBool::new(false);
+        let mut fut = Offloaded {
+            new_future: Some(Box::new(|| {
+                CREATED.store(true, Ordering::SeqCst);
+                Box::pin(async { Ok(vec![WasmVal::I32(1)]) })
+            })),
+            fut: None,
+        };
+        assert!(!CREATED.load(Ordering::SeqCst));
+
+        let waker = waker_fn::waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        match Pin::new(&mut fut).poll(&mut cx) {
+            Poll::Ready(Ok(v)) => assert!(matches!(v[..], [WasmVal::I32(1)])),
+            _ => panic!("the host future should be ready"),
+        }
+        assert!(CREATED.load(Ordering::SeqCst));
+    }
+}
@@ -1 +1,3 @@
+#[cfg(feature = "tokio")]
+pub mod blocking;
 pub mod function;
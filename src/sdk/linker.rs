@@ -1,4 +1,17 @@
-use std::{borrow::Cow, ffi::c_void, marker::PhantomPinned, pin::Pin, ptr::NonNull, task::Waker};
+use std::{
+    any::Any,
+    borrow::Cow,
+    ffi::c_void,
+    marker::PhantomPinned,
+    pin::Pin,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::Waker,
+    time::Instant,
+};
 
 use wasmedge_types::{
     error::{CoreCommonError, CoreError, WasmEdgeError},
@@ -7,18 +20,54 @@ use wasmedge_types::{
 
 use crate::core::{
     config::Config, executor::Executor, types::WasmVal, AsInstance, AstModule, CodegenConfig,
-    ImportModule, Instance, Loader,
+    ImportIntercept, ImportModule, Instance, Loader, WASI_MODULE_NAME,
 };
 
+#[cfg(feature = "aot")]
+use super::aot::{AotCache, AotConfig};
+#[cfg(feature = "tokio")]
+use super::instance::function::DeadlineFuture;
+#[cfg(feature = "async-process")]
+use super::process::{self, ProcessConfig, ProcessCtx, PROCESS_MODULE};
+#[cfg(feature = "tokio")]
+use super::wasi::poll;
+#[cfg(feature = "async-wasi")]
+use super::wasi::preview1::{self, WasiCtx};
+#[cfg(feature = "async-socket")]
+use super::wasi::socket::{self, SocketConfig, SocketCtx, SOCKET_IMPORTS, SOCKET_MODULE};
 use super::{
-    instance::function::{ResultFuture, WasmEdgeResultFuture},
+    coroutine::{NestedCallFuture, ParkedCall},
+    error::{AsyncifyPhase, BoxError, CallError, Frame, GuestExit, HostError, TrapReport},
+    instance::function::{
+        CallTarget, FuncRef, GuestCallback, ResultFuture, SendFuture, WasmEdgeResultFuture,
+    },
     module::AsyncImportModuleBuilder,
+    wasi::{
+        stdio::{self, Stdio, STDIO_MODULE},
+        WasiConfig,
+    },
+    SendAsyncFn,
 };
 
 // std::collections::LinkedList<Pin<ResultFuture<'this>>>
-struct AsyncFutureList(NonNull<c_void>);
-unsafe impl Sync for AsyncFutureList {}
+pub(crate) struct AsyncFutureList(NonNull<c_void>);
+impl AsyncFutureList {
+    pub(crate) fn new() -> Self {
+        unsafe {
+            let ptr = Box::leak(Box::new(std::collections::LinkedList::<
+                Pin<ResultFuture<'static>>,
+            >::new())) as *mut _ as *mut c_void;
+            AsyncFutureList(NonNull::new_unchecked(ptr))
+        }
+    }
+}
+// Safety: the list is only non-empty while a call future borrows the linker, and it is
+// emptied when that future completes or is dropped. Call futures are `!Send` unless
+// every host future is `Send` (see `SendAsyncLinker`), so a non-`Send` future can never
+// cross threads through the linker. The list is only reachable through `&mut AsyncLinker`,
+// so sharing `&AsyncLinker` never touches it.
 unsafe impl Send for AsyncFutureList {}
+unsafe impl Sync for AsyncFutureList {}
 impl Drop for AsyncFutureList {
     fn drop<'a>(&'a mut self) {
         unsafe {
@@ -29,6 +78,9 @@ impl Drop for AsyncFutureList {
     }
 }
 
+/// The exported linear memory of wasm32 toolchains.
+pub(crate) const MAIN_MEMORY: &str = "memory";
+
 fn unreachable() -> WasmEdgeError {
     use wasmedge_types::error;
     WasmEdgeError::Core(CoreError::Execution(error::CoreExecutionError::Unreachable))
@@ -36,9 +88,38 @@ fn unreachable() -> WasmEdgeError {
 
 pub struct AsyncLinker {
     pub(crate) cx: Waker,
+    /// Unique to the current instance, see [`next_instance_id`].
+    pub(crate) id: u64,
     pub(crate) inst: Option<Instance>,
     pub(crate) executor: Executor,
-    pub(crate) vm_err: Option<WasmEdgeError>,
+    pub(crate) vm_err: Option<CallError>,
+    pub(crate) poisoned: bool,
+    /// The guest and host calls currently on the stack, outermost first.
+    pub(crate) frames: Vec<Frame>,
+    /// The last async import the guest suspended on, until its future completes.
+    pub(crate) suspended_import: Option<Arc<str>>,
+    /// Whether the guest runs on a WasmEdge worker thread, where async imports leave
+    /// their futures to the thread polling the call, see [`DeadlineFuture`].
+    pub(crate) offloaded: bool,
+    /// The frames and asyncify phase of the innermost failure of the current call.
+    trap: Option<(Vec<Frame>, Option<AsyncifyPhase>)>,
+    /// The sinks of the guest's captured stdio.
+    pub(crate) stdio: Stdio,
+    /// The code a host function exited the guest with, see [`GuestExit`].
+    exit_code: Option<u32>,
+    /// The guest paths of the WASI preopens, which the guest's libc lists once.
+    preopens: Vec<String>,
+    /// The state of the async WASI module, if the linker has one.
+    #[cfg(feature = "async-wasi")]
+    pub(crate) wasi: Option<Box<WasiCtx>>,
+    /// The sockets of the async socket module, if the linker has one.
+    #[cfg(feature = "async-socket")]
+    pub(crate) sockets: Option<Box<SocketCtx>>,
+    /// The processes of the async process module, if the linker has one.
+    #[cfg(feature = "async-process")]
+    pub(crate) processes: Option<Box<ProcessCtx>>,
+    /// Per-import definitions handed to the host function wrappers as key pointers.
+    host_fns: Vec<Box<dyn Any + Send + Sync>>,
 
     func_futures_ptr: AsyncFutureList,
     _unpin: PhantomPinned,
@@ -52,30 +133,232 @@ impl AsyncLinker {
     }
 
     fn new(config: &Option<Config>) -> WasmEdgeResult<Box<Self>> {
-        unsafe {
-            let func_futures_ptr = Box::leak(Box::new(std::collections::LinkedList::<
-                Pin<ResultFuture<'static>>,
-            >::new())) as *mut _ as *mut c_void;
+        Ok(Box::new(AsyncLinker {
+            cx: waker_fn::waker_fn(|| {}),
+            func_futures_ptr: AsyncFutureList::new(),
+            _unpin: PhantomPinned,
+            id: 0,
+            inst: None,
+            executor: Executor::create(config)?,
+            vm_err: None,
+            poisoned: false,
+            frames: vec![],
+            suspended_import: None,
+            offloaded: false,
+            trap: None,
+            stdio: Stdio::default(),
+            exit_code: None,
+            preopens: vec![],
+            #[cfg(feature = "async-wasi")]
+            wasi: None,
+            #[cfg(feature = "async-socket")]
+            sockets: None,
+            #[cfg(feature = "async-process")]
+            processes: None,
+            host_fns: vec![],
+        }))
+    }
 
-            Ok(Box::new(AsyncLinker {
-                cx: waker_fn::waker_fn(|| {}),
-                func_futures_ptr: AsyncFutureList(NonNull::new_unchecked(func_futures_ptr)),
-                _unpin: PhantomPinned,
-                inst: None,
-                executor: Executor::create(config)?,
-                vm_err: None,
-            }))
+    /// Keeps `def` alive as long as the linker and returns a pointer to it, for use as
+    /// the key pointer of a host function.
+    pub(crate) fn keep_host_fn<T: Any + Send + Sync>(&mut self, def: T) -> *mut c_void {
+        let def = Box::new(def);
+        let ptr = &*def as *const T as *mut c_void;
+        self.host_fns.push(def);
+        ptr
+    }
+
+    /// Swaps the pending host futures with `list`, used to switch between coroutines.
+    pub(crate) fn swap_func_futures(&mut self, list: &mut AsyncFutureList) {
+        std::mem::swap(&mut self.func_futures_ptr, list);
+    }
+
+    pub fn call(&mut self, name: &str, args: Vec<WasmVal>) -> WasmEdgeResultFuture<'_> {
+        WasmEdgeResultFuture::new(self, name.into(), args)
+    }
+
+    /// Calls a guest callback retained with [`callback`](Self::callback), with the same
+    /// asyncify support as [`call`](Self::call).
+    pub fn call_ref(
+        &mut self,
+        callback: &GuestCallback,
+        args: Vec<WasmVal>,
+    ) -> WasmEdgeResultFuture<'_> {
+        WasmEdgeResultFuture::new(self, CallTarget::Ref(callback.clone()), args)
+    }
+
+    /// Retains a `funcref` the guest passed to a host function, so that it can be called
+    /// later with [`call_ref`](Self::call_ref).
+    ///
+    /// Fails if `val` is not a funcref or is a null reference.
+    pub fn callback(&self, val: &WasmVal) -> WasmEdgeResult<GuestCallback> {
+        match val {
+            WasmVal::FuncRef(func) if !func.inner.0.is_null() => Ok(GuestCallback {
+                func: std::sync::Arc::new(func.clone()),
+                owner: self.id,
+                name: self.export_name(func),
+                ty: std::sync::Arc::new(func.func_type()?),
+            }),
+            _ => Err(WasmEdgeError::Func(wasmedge_types::error::FuncError::Type)),
         }
     }
 
-    pub fn call(&mut self, name: &str, args: Vec<WasmVal>) -> WasmEdgeResultFuture {
-        WasmEdgeResultFuture {
+    /// Finds the export name of `func`, to name callbacks in trap reports.
+    fn export_name(&self, func: &FuncRef) -> Option<String> {
+        let inst = self.inst.as_ref()?;
+        inst.func_names()?
+            .into_iter()
+            .find(|name| inst.get_func(name).is_ok_and(|f| f.inner.0 == func.inner.0))
+    }
+
+    /// Sets the waker that async host functions are polled with.
+    pub(crate) fn set_waker(&mut self, waker: &Waker) {
+        self.cx = waker.clone();
+    }
+
+    /// Calls the exported function `name`, giving up once `deadline` passes.
+    ///
+    /// The guest computes on a WasmEdge worker thread, which WasmEdge interrupts at the
+    /// deadline, while the thread polling the call waits for it; the futures of async
+    /// host functions are still created and polled by the polling thread, and the call
+    /// is cancelled if the deadline passes while the guest waits on one. On timeout the
+    /// instance is reset and [`CallError::Timeout`](crate::CallError::Timeout) is
+    /// returned.
+    ///
+    /// AOT code is only interrupted if it was compiled with
+    /// [`AotConfig::interruptible`](crate::AotConfig::interruptible), and a synchronous
+    /// host function runs to completion before the guest is interrupted.
+    #[cfg(feature = "tokio")]
+    pub fn call_with_deadline(
+        &mut self,
+        name: &str,
+        args: Vec<WasmVal>,
+        deadline: Instant,
+    ) -> DeadlineFuture<'_> {
+        DeadlineFuture::new(WasmEdgeResultFuture::new(self, name.into(), args), deadline)
+    }
+
+    /// Calls the exported function `name` from inside a host function and waits for it
+    /// synchronously.
+    ///
+    /// Fails if the guest tries to suspend on an async host function; use
+    /// [`call_nested`](Self::call_nested) for that.
+    pub fn call_guest(&mut self, name: &str, args: &[WasmVal]) -> Result<Vec<WasmVal>, CallError> {
+        let v = match self.call_target(&name.into(), args, None) {
+            Ok(v) => v,
+            Err(e) => {
+                let e = self.vm_err.take().unwrap_or_else(|| e.into());
+                return Err(self.trap_report(e));
+            }
+        };
+        if !self.asyncify_done()? {
+            // drop the host future the guest is waiting on, along with its unwound frames
+            self.func_futures().pop_back();
+            self.asyncify_stop_unwind()?;
+            return Err(WasmEdgeError::Operation(format!(
+                "guest export {} suspended during a synchronous call",
+                name
+            ))
+            .into());
+        }
+        Ok(v)
+    }
+
+    /// Calls the exported function `name` from inside an async host function.
+    ///
+    /// The nested call may itself suspend on async host functions. Its asyncify state is
+    /// saved and restored around the outer call's suspensions.
+    pub fn call_nested(&mut self, name: &str, args: Vec<WasmVal>) -> NestedCallFuture<'_> {
+        NestedCallFuture {
+            call: ParkedCall::new(name.into(), args, None),
+            linker: self,
+        }
+    }
+
+    /// Like [`call_nested`](Self::call_nested), for a retained guest callback.
+    pub fn call_ref_nested(
+        &mut self,
+        callback: &GuestCallback,
+        args: Vec<WasmVal>,
+    ) -> NestedCallFuture<'_> {
+        NestedCallFuture {
+            call: ParkedCall::new(CallTarget::Ref(callback.clone()), args, None),
             linker: self,
-            name: name.to_string(),
-            args,
         }
     }
 
+    /// Returns the exit code of the WASI module added with
+    /// [`AsyncLinkerBuilder::create_wasi`], or `None` without one.
+    ///
+    /// The code is 0 until the guest calls `proc_exit`; calls that exit fail with
+    /// [`CallError::Exited`]. A host function returning [`GuestExit`] sets it too.
+    pub fn wasi_exit_code(&self) -> Option<u32> {
+        self.exit_code.or_else(|| {
+            #[cfg(feature = "async-wasi")]
+            if self.wasi.is_some() {
+                return Some(0);
+            }
+            self.executor.wasi_exit_code()
+        })
+    }
+
+    /// Reconfigures the WASI module between calls, so that a reused instance can give each
+    /// call its own arguments, environment and stdio without being instantiated again.
+    ///
+    /// The module is reset as if it had been created with `config`: the files the guest
+    /// opened are closed, the preopens of `config` are opened again and the exit code goes
+    /// back to 0. A guest's libc lists the preopens once at startup, so `config` must
+    /// preopen the same guest paths in the same order; the host directories they map to
+    /// may change.
+    ///
+    /// The WasmEdge WASI module redirects only the stdio streams captured when it was
+    /// added, so with it `config` has to capture the same streams: a sink can be swapped
+    /// for another, but an inherited stream stays inherited. Fails without changing
+    /// anything if `config` is invalid, preopens other guest paths or the linker has no
+    /// WASI module.
+    pub fn reconfigure_wasi(&mut self, config: &WasiConfig) -> WasmEdgeResult<()> {
+        let wasi = config.resolve()?;
+        let preopens = wasi.preopen_names();
+        if preopens != self.preopens {
+            return Err(WasmEdgeError::Operation(format!(
+                "the WASI preopens cannot change from {:?} to {:?}",
+                self.preopens, preopens
+            )));
+        }
+        let stdio = Stdio::new(wasi.stdin.clone(), wasi.stdout.clone(), wasi.stderr.clone());
+
+        #[cfg(feature = "async-wasi")]
+        if self.wasi.is_some() {
+            self.wasi = Some(Box::new(WasiCtx::new(&wasi)?));
+            self.stdio = stdio;
+            self.exit_code = None;
+            return Ok(());
+        }
+
+        if !self.stdio.captures_like(&stdio) {
+            return Err(WasmEdgeError::Operation(
+                "the WASI module cannot capture other stdio streams than it was created with"
+                    .to_string(),
+            ));
+        }
+        let [args, envs, preopens] = wasi.to_native()?;
+        let module = self
+            .executor
+            .wasi_module_mut()
+            .ok_or_else(|| WasmEdgeError::Operation("the linker has no WASI module".to_string()))?;
+        module.init_wasi_from_cstrings(&args, &envs, &preopens);
+        self.stdio = stdio;
+        self.exit_code = None;
+        Ok(())
+    }
+
+    /// Returns `true` if a cancelled call left the instance in a state that could not be reset.
+    ///
+    /// Every call on a poisoned linker fails with `WrongVMWorkflow`; the instance has to be rebuilt.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
     pub fn get_memory<'a>(
         &'a self,
         name: &str,
@@ -122,6 +405,116 @@ impl AsyncLinker {
         f.call(&mut self.executor, args)
     }
 
+    /// Calls a guest export or callback, on a WasmEdge worker thread that cancels it at
+    /// `deadline` if there is one.
+    pub(crate) fn call_target(
+        &mut self,
+        target: &CallTarget,
+        args: &[WasmVal],
+        deadline: Option<Instant>,
+    ) -> WasmEdgeResult<Vec<WasmVal>> {
+        let (f, frame) = match target {
+            CallTarget::Export(name) => {
+                let f = match &self.inst {
+                    Some(inst) => inst.get_func(name)?,
+                    None => {
+                        return Err(WasmEdgeError::Core(CoreError::Common(
+                            CoreCommonError::RuntimeError,
+                        )))
+                    }
+                };
+                (f, Frame::Guest(name.clone()))
+            }
+            CallTarget::Ref(callback) => {
+                if self.id == 0 || callback.owner != self.id {
+                    return Err(WasmEdgeError::Core(CoreError::Common(
+                        CoreCommonError::FuncNotFound,
+                    )));
+                }
+                let f = callback.func.as_ref().clone();
+                let name = callback.name.as_deref().unwrap_or("<callback>");
+                (f, Frame::Guest(name.to_string()))
+            }
+        };
+
+        self.frames.push(frame);
+        let r = match deadline {
+            Some(deadline) => {
+                let offloaded = std::mem::replace(&mut self.offloaded, true);
+                let r = f.call_until(&mut self.executor, args, deadline);
+                self.offloaded = offloaded;
+                r
+            }
+            None => f.call(&mut self.executor, args),
+        };
+        if self.executor.take_terminated() {
+            // not a trap: the guest asked to stop
+            let code = self.executor.wasi_exit_code().unwrap_or(0);
+            self.vm_err = Some(CallError::Exited(code));
+            self.frames.pop();
+            return Err(WasmEdgeError::Core(CoreError::Common(
+                CoreCommonError::RuntimeError,
+            )));
+        }
+        if r.is_err() && !matches!(self.vm_err, Some(CallError::Exited(_))) {
+            self.record_trap();
+        }
+        self.frames.pop();
+        r
+    }
+
+    /// Remembers the frames and asyncify phase of a failure, unless a deeper failure of
+    /// the same call was already recorded.
+    pub(crate) fn record_trap(&mut self) {
+        if self.trap.is_none() {
+            let phase = match self.asyncify_state() {
+                Ok(ASYNCIFY_NORMAL) => Some(AsyncifyPhase::Normal),
+                Ok(ASYNCIFY_UNWINDING) => Some(AsyncifyPhase::Unwinding),
+                Ok(ASYNCIFY_REWINDING) => Some(AsyncifyPhase::Rewinding),
+                _ => None,
+            };
+            self.trap = Some((self.frames.clone(), phase));
+        }
+    }
+
+    /// Records `e`, returned by the host function behind `import`, as the error of the
+    /// current call.
+    pub(crate) fn host_failed(&mut self, import: &str, e: BoxError) {
+        let e = match e.downcast::<GuestExit>() {
+            Ok(exit) => {
+                self.exit_code = Some(exit.0);
+                CallError::Exited(exit.0)
+            }
+            Err(e) => {
+                self.record_trap();
+                CallError::Host(HostError::new(import, e))
+            }
+        };
+        let _ = self.vm_err.insert(e);
+    }
+
+    /// Wraps `e` in a [`TrapReport`] for the recorded failure, if any.
+    pub(crate) fn trap_report(&mut self, e: CallError) -> CallError {
+        let (mut boundary_frames, phase) = match self.trap.take() {
+            Some(trap) => trap,
+            None => return e,
+        };
+        boundary_frames.reverse();
+        let import = boundary_frames
+            .iter()
+            .find_map(|frame| match frame {
+                Frame::Host(import) => Some(import.clone()),
+                Frame::Guest(_) => None,
+            })
+            .or_else(|| self.suspended_import.clone());
+        CallError::Trap(Box::new(TrapReport {
+            cause: e,
+            phase,
+            import,
+            boundary_frames,
+        }))
+    }
+
     pub(crate) fn asyncify_yield(&mut self) -> WasmEdgeResult<()> {
         self.real_call("asyncify_start_unwind", &[])?;
         Ok(())
@@ -135,39 +528,171 @@ impl AsyncLinker {
         Ok(())
     }
 
-    pub(crate) fn asyncify_normal(&mut self) -> WasmEdgeResult<()> {
-        self.real_call("asyncify_stop_unwind", &[])?;
+    pub(crate) fn asyncify_stop_unwind(&mut self) -> WasmEdgeResult<()> {
+        if self.asyncify_state()? == ASYNCIFY_UNWINDING {
+            self.real_call("asyncify_stop_unwind", &[])?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn asyncify_stop_rewind(&mut self) -> WasmEdgeResult<()> {
+        if self.asyncify_state()? == ASYNCIFY_REWINDING {
+            self.real_call("asyncify_stop_rewind", &[])?;
+        }
         Ok(())
     }
 
     pub(crate) fn asyncify_done(&mut self) -> WasmEdgeResult<bool> {
+        Ok(self.asyncify_state()? == ASYNCIFY_NORMAL)
+    }
+
+    pub(crate) fn asyncify_state(&mut self) -> WasmEdgeResult<i32> {
         let r = self.real_call("asyncify_get_state", &[])?;
         if let Some(WasmVal::I32(i)) = r.first() {
-            return Ok(*i == 0);
+            return Ok(*i);
+        }
+        Ok(ASYNCIFY_NORMAL)
+    }
+
+    /// Drops every pending host future and brings asyncify back to the normal state,
+    /// so the next call starts from a clean stack. Poisons the linker if that fails.
+    pub(crate) fn reset_call_state(&mut self) {
+        self.func_futures().clear();
+        self.vm_err = None;
+        self.frames.clear();
+        self.suspended_import = None;
+        self.trap = None;
+
+        let r = match self.asyncify_state() {
+            Ok(ASYNCIFY_UNWINDING) => self.real_call("asyncify_stop_unwind", &[]).map(|_| ()),
+            Ok(ASYNCIFY_REWINDING) => self.real_call("asyncify_stop_rewind", &[]).map(|_| ()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        if r.is_err() || !matches!(self.asyncify_done(), Ok(true)) {
+            self.poisoned = true;
         }
-        return Ok(true);
     }
 }
 
+/// Returns an id no instance had before, 0 being that of a linker without one.
+fn next_instance_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+const ASYNCIFY_NORMAL: i32 = 0;
+const ASYNCIFY_UNWINDING: i32 = 1;
+const ASYNCIFY_REWINDING: i32 = 2;
+
 pub trait AsLinker {
-    fn call(&mut self, name: &str, args: Vec<WasmVal>) -> WasmEdgeResultFuture;
+    fn call(&mut self, name: &str, args: Vec<WasmVal>) -> WasmEdgeResultFuture<'_>;
+
+    fn call_ref(
+        &mut self,
+        callback: &GuestCallback,
+        args: Vec<WasmVal>,
+    ) -> WasmEdgeResultFuture<'_>;
+
+    #[cfg(feature = "tokio")]
+    fn call_with_deadline(
+        &mut self,
+        name: &str,
+        args: Vec<WasmVal>,
+        deadline: Instant,
+    ) -> DeadlineFuture<'_>;
 }
 
 impl AsLinker for Pin<Box<AsyncLinker>> {
-    fn call(&mut self, name: &str, args: Vec<WasmVal>) -> WasmEdgeResultFuture {
+    fn call(&mut self, name: &str, args: Vec<WasmVal>) -> WasmEdgeResultFuture<'_> {
+        let linker_ctx = unsafe { self.as_mut().get_unchecked_mut() };
+        WasmEdgeResultFuture::new(linker_ctx, name.into(), args)
+    }
+
+    fn call_ref(
+        &mut self,
+        callback: &GuestCallback,
+        args: Vec<WasmVal>,
+    ) -> WasmEdgeResultFuture<'_> {
         let linker_ctx = unsafe { self.as_mut().get_unchecked_mut() };
-        WasmEdgeResultFuture {
-            linker: linker_ctx,
-            name: name.to_string(),
-            args,
+        linker_ctx.call_ref(callback, args)
+    }
+
+    #[cfg(feature = "tokio")]
+    fn call_with_deadline(
+        &mut self,
+        name: &str,
+        args: Vec<WasmVal>,
+        deadline: Instant,
+    ) -> DeadlineFuture<'_> {
+        let linker_ctx = unsafe { self.as_mut().get_unchecked_mut() };
+        linker_ctx.call_with_deadline(name, args, deadline)
+    }
+}
+
+/// An [`AsyncLinker`] whose async host functions all return `Send` futures.
+///
+/// Its call futures are `Send`, so they can be `tokio::spawn`ed on the multithreaded
+/// runtime. Build it with [`AsyncLinkerBuilder::instance_send`]; linkers with
+/// non-`Send` host functions have to stay on a `tokio::task::LocalSet`.
+pub struct SendAsyncLinker {
+    inner: Pin<Box<AsyncLinker>>,
+}
+
+impl SendAsyncLinker {
+    pub fn call(&mut self, name: &str, args: Vec<WasmVal>) -> SendFuture<WasmEdgeResultFuture<'_>> {
+        // Safety: `instance_send` refused the host functions with non-`Send` futures
+        unsafe { SendFuture::new(AsLinker::call(&mut self.inner, name, args)) }
+    }
+
+    pub fn call_ref(
+        &mut self,
+        callback: &GuestCallback,
+        args: Vec<WasmVal>,
+    ) -> SendFuture<WasmEdgeResultFuture<'_>> {
+        // Safety: `instance_send` refused the host functions with non-`Send` futures
+        unsafe { SendFuture::new(AsLinker::call_ref(&mut self.inner, callback, args)) }
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn call_with_deadline(
+        &mut self,
+        name: &str,
+        args: Vec<WasmVal>,
+        deadline: Instant,
+    ) -> SendFuture<DeadlineFuture<'_>> {
+        // Safety: `instance_send` refused the host functions with non-`Send` futures
+        unsafe {
+            SendFuture::new(AsLinker::call_with_deadline(
+                &mut self.inner,
+                name,
+                args,
+                deadline,
+            ))
         }
     }
+
+    pub fn into_inner(self) -> Pin<Box<AsyncLinker>> {
+        self.inner
+    }
+}
+
+impl std::ops::Deref for SendAsyncLinker {
+    type Target = AsyncLinker;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
 }
 
 pub struct AsyncLinkerBuilder {
     pub(crate) linker: Box<AsyncLinker>,
     pub(crate) loader: Loader,
     pub(crate) async_fn_name: Vec<String>,
+    pub(crate) local_fn_name: Vec<String>,
+    pub(crate) intercepts: Vec<ImportIntercept>,
+    #[cfg(feature = "aot")]
+    pub(crate) aot_cache: Option<AotCache>,
 }
 
 impl AsyncLinkerBuilder {
@@ -175,6 +700,10 @@ impl AsyncLinkerBuilder {
         Ok(AsyncLinkerBuilder {
             linker: AsyncLinker::new(config)?,
             async_fn_name: vec![],
+            local_fn_name: vec![],
+            intercepts: vec![],
+            #[cfg(feature = "aot")]
+            aot_cache: None,
             loader: Loader::create(config)?,
         })
     }
@@ -187,6 +716,132 @@ impl AsyncLinkerBuilder {
     ) -> Result<(), WasmEdgeError> {
         let import_obj = ImportModule::create_wasi(args, envs, preopens)?;
         self.linker.executor.register_import_object(import_obj)?;
+        self.linker.preopens = preopens
+            .iter()
+            .map(|p| p.as_ref().split(':').next().unwrap_or_default().to_string())
+            .collect();
+        Ok(())
+    }
+
+    /// Adds the WasmEdge WASI module configured by `config`.
+    ///
+    /// Captured stdin, stdout and stderr use async host functions, which requires
+    /// the module to be loaded with [`load_wasm`](Self::load_wasm) from a wasm binary that
+    /// is not asyncified yet. With the `tokio` feature `poll_oneoff` is async as well,
+    /// so a guest sleeping suspends instead of blocking the thread; this is skipped for
+    /// wasm that is already asyncified.
+    ///
+    /// Fails without registering anything if an entry of `config` is invalid.
+    pub fn create_wasi_with(&mut self, config: &WasiConfig) -> WasmEdgeResult<()> {
+        let wasi = config.resolve()?;
+        let [args, envs, preopens] = wasi.to_native()?;
+        let import_obj = ImportModule::create_wasi_from_cstrings(&args, &envs, &preopens)?;
+        self.linker.executor.register_import_object(import_obj)?;
+        self.linker.preopens = wasi.preopen_names();
+        self.redirect_wasi(Stdio::new(wasi.stdin, wasi.stdout, wasi.stderr))
+    }
+
+    /// Adds a WASI preview1 module implemented in Rust, as `wasi_snapshot_preview1`.
+    ///
+    /// Calls that may block, such as file and stdio I/O, suspend the guest instead of
+    /// blocking the thread. Read-only preopens are supported, and so are the
+    /// [`Vfs`](super::Vfs) trees of [`WasiConfig::preopen_vfs`]. Use it in place of
+    /// [`create_wasi_with`](Self::create_wasi_with), not along with it.
+    #[cfg(feature = "async-wasi")]
+    pub fn create_async_wasi(&mut self, config: &WasiConfig) -> WasmEdgeResult<()> {
+        let wasi = config.resolve()?;
+        let ctx = WasiCtx::new(&wasi)?;
+        self.create_import_object(WASI_MODULE_NAME, preview1::add_functions)?;
+        self.linker.preopens = wasi.preopen_names();
+        self.linker.stdio = Stdio::new(wasi.stdin, wasi.stdout, wasi.stderr);
+        self.linker.wasi = Some(Box::new(ctx));
+        Ok(())
+    }
+
+    /// Adds the WasmEdge socket functions on `tokio` sockets, so that a guest using
+    /// `wasmedge_wasi_socket` is suspended instead of blocking the thread while it waits
+    /// for a connection or data.
+    ///
+    /// The guest's `sock_*` imports from `wasi_snapshot_preview1` are redirected, which
+    /// requires the module to be loaded with [`load_wasm`](Self::load_wasm) from a wasm
+    /// binary that is not asyncified yet. It works along with either WASI module.
+    #[cfg(feature = "async-socket")]
+    pub fn create_async_sockets(&mut self, config: &SocketConfig) -> WasmEdgeResult<()> {
+        self.create_import_object(SOCKET_MODULE, socket::add_functions)?;
+        let redirects = SOCKET_IMPORTS
+            .iter()
+            .map(|name| (*name, None))
+            .chain([("fd_close", Some(config.fds()))]);
+        for (name, first_args) in redirects {
+            self.intercepts.push(ImportIntercept {
+                module: WASI_MODULE_NAME.to_string(),
+                name: name.to_string(),
+                to_module: SOCKET_MODULE.to_string(),
+                to_name: name.to_string(),
+                first_args,
+                optional: false,
+            });
+        }
+        self.linker.sockets = Some(Box::new(SocketCtx::new(config.clone())));
+        Ok(())
+    }
+
+    /// Adds the `wasmedge_asyncify_process` module, which lets the guest spawn the
+    /// programs allowed by `config` through `tokio::process` and stream their stdio
+    /// through its memory, suspended while it waits for them.
+    ///
+    /// Unlike the WasmEdge process plugin of [`Config::wasmedge_process`], nothing runs
+    /// that `config` does not allow, and a process is killed when its handle is closed
+    /// or the linker is dropped.
+    ///
+    /// [`Config::wasmedge_process`]: crate::Config::wasmedge_process
+    #[cfg(feature = "async-process")]
+    pub fn create_async_process(&mut self, config: &ProcessConfig) -> WasmEdgeResult<()> {
+        self.create_import_object(PROCESS_MODULE, process::add_functions)?;
+        self.linker.processes = Some(Box::new(ProcessCtx::new(config.clone())));
+        Ok(())
+    }
+
+    /// Registers async replacements for the WASI functions that would block or bypass the
+    /// host sinks, and redirects the guest's calls to them: `fd_write` and `fd_read` on
+    /// the captured streams of `stdio` and, with `tokio`, every `poll_oneoff`.
+    fn redirect_wasi(&mut self, stdio: Stdio) -> WasmEdgeResult<()> {
+        let output_fds = stdio.captured_output_fds();
+        let input = stdio.captures_input();
+        self.linker.stdio = stdio;
+
+        // the stdio redirects are needed for the sinks to see any output, the
+        // `poll_oneoff` one only makes sleeping cooperative
+        let mut redirects: Vec<(&str, SendAsyncFn, Option<Vec<i32>>, bool)> = vec![];
+        if !output_fds.is_empty() {
+            redirects.push(("fd_write", stdio::fd_write, Some(output_fds), false));
+        }
+        if input {
+            redirects.push(("fd_read", stdio::fd_read, Some(vec![0]), false));
+        }
+        #[cfg(feature = "tokio")]
+        redirects.push(("poll_oneoff", poll::poll_oneoff, None, true));
+        if redirects.is_empty() {
+            return Ok(());
+        }
+
+        self.create_import_object(STDIO_MODULE, |b| {
+            use wasmedge_types::ValType::I32;
+            for (name, real_fn, _, _) in &redirects {
+                b.add_send_async_func(name, (vec![I32; 4], vec![I32]), *real_fn)?;
+            }
+            Ok(())
+        })?;
+        for (name, _, first_args, optional) in redirects {
+            self.intercepts.push(ImportIntercept {
+                module: WASI_MODULE_NAME.to_string(),
+                name: name.to_string(),
+                to_module: STDIO_MODULE.to_string(),
+                to_name: name.to_string(),
+                first_args,
+                optional,
+            });
+        }
         Ok(())
     }
 
@@ -200,12 +855,14 @@ impl AsyncLinkerBuilder {
         let AsyncLinkerBuilder {
             linker,
             async_fn_name,
+            local_fn_name,
             ..
         } = self;
         let mut builder = AsyncImportModuleBuilder {
             import_obj: ImportModule::create(name)?,
             linker_ctx: linker,
             async_fn_name,
+            local_fn_name,
         };
         f(&mut builder)?;
         let AsyncImportModuleBuilder {
@@ -221,32 +878,159 @@ impl AsyncLinkerBuilder {
         &mut self,
         wasm: &'a [u8],
     ) -> WasmEdgeResult<Cow<'a, [u8]>> {
-        let AsyncLinkerBuilder {
-            async_fn_name,
-            loader,
-            ..
-        } = self;
-
-        let asyncify_imports = async_fn_name.join(",");
+        self.asyncify_pass().run(wasm)
+    }
 
-        let mut codegen_config = CodegenConfig::default();
-        codegen_config.optimization_level = 2;
-        codegen_config
-            .pass_argument
-            .push(("asyncify-imports".to_string(), asyncify_imports));
+    /// Returns the asyncify settings of the builder, for asyncifying on another thread.
+    pub(crate) fn asyncify_pass(&self) -> AsyncifyPass {
+        AsyncifyPass {
+            asyncify_imports: self.async_fn_name.join(","),
+            intercepts: self.intercepts.clone(),
+        }
+    }
 
-        loader.pass_async_module_from_bytes(wasm, ["asyncify", "strip"], &codegen_config)
+    /// Makes [`load_wasm`](Self::load_wasm) run the asyncified modules AOT-compiled with
+    /// `config`, keeping the compiled modules in `dir` for the next loads of the same wasm.
+    ///
+    /// A compiled module is reused only for the same wasm binary, async imports,
+    /// redirected imports, `config` settings and WasmEdge version. Modules that cannot be
+    /// compiled, e.g. because WasmEdge was built without the AOT compiler, run in the
+    /// interpreter instead.
+    #[cfg(feature = "aot")]
+    pub fn set_aot_cache(&mut self, dir: impl Into<std::path::PathBuf>, config: AotConfig) {
+        self.aot_cache = Some(AotCache::new(dir.into(), config));
     }
 
     pub fn load_wasm(&mut self, wasm: &[u8]) -> WasmEdgeResult<AstModule> {
+        #[cfg(feature = "aot")]
+        let cache_path = match &self.aot_cache {
+            Some(cache) => {
+                let path = cache.path(wasm, &self.async_fn_name, &self.intercepts);
+                if let Some(module) = cache.load(&mut self.loader, &path) {
+                    return Ok(module);
+                }
+                Some(path)
+            }
+            None => None,
+        };
+
         let new_wasm = self.pass_asyncify_wasm(wasm)?;
+        #[cfg(feature = "aot")]
+        if let (Some(cache), Some(path)) = (&self.aot_cache, cache_path) {
+            if let Some(module) = cache.compile(&mut self.loader, &path, &new_wasm) {
+                return Ok(module);
+            }
+        }
         self.loader.load_module_from_bytes(&new_wasm)
     }
 
+    /// Loads a shared library compiled by [`AotCompiler::compile_async_module`] with the
+    /// [`CompilerOutputFormat::Native`](super::CompilerOutputFormat::Native) output format.
+    ///
+    /// The library is already asyncified, so it is only loaded if it was asyncified for
+    /// every async host function of this builder that it imports, as recorded by
+    /// [`AotCompiler::compile_async_module`], and if its imports show the redirected
+    /// imports of this builder.
+    ///
+    /// [`AotCompiler::compile_async_module`]: super::AotCompiler::compile_async_module
+    #[cfg(feature = "aot")]
+    pub fn load_aot_library(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> WasmEdgeResult<AstModule> {
+        let path = path.as_ref();
+        let module = self.loader.load_module_from_file(path)?;
+        if !module
+            .exports()
+            .iter()
+            .any(|name| name == "asyncify_get_state")
+        {
+            return Err(WasmEdgeError::Operation(format!(
+                "{:?} is not asyncified",
+                path
+            )));
+        }
+        let recorded = module.asyncify_imports().ok_or_else(|| {
+            WasmEdgeError::Operation(format!(
+                "{:?} does not record the imports it was asyncified for",
+                path
+            ))
+        })?;
+        let imports = module.imports();
+        let not_instrumented = self.async_fn_name.iter().find(|name| {
+            imports
+                .iter()
+                .any(|(module, import)| **name == format!("{}.{}", module, import))
+                && !recorded.contains(name)
+        });
+        if let Some(name) = not_instrumented {
+            return Err(WasmEdgeError::Operation(format!(
+                "{:?} was not asyncified for the async import {}",
+                path, name
+            )));
+        }
+        let missing = self
+            .intercepts
+            .iter()
+            .find(|intercept| !intercept.optional && !intercept.is_applied_to(&imports));
+        if let Some(intercept) = missing {
+            return Err(WasmEdgeError::Operation(format!(
+                "{:?} was not compiled with {}.{} redirected to {}.{}",
+                path, intercept.module, intercept.name, intercept.to_module, intercept.to_name
+            )));
+        }
+        Ok(module)
+    }
+
     pub fn instance(self, module: &AstModule) -> WasmEdgeResult<Pin<Box<AsyncLinker>>> {
         let AsyncLinkerBuilder { mut linker, .. } = self;
         let inst = linker.executor.instantiate(module)?;
         linker.inst = Some(inst);
+        linker.id = next_instance_id();
         Ok(Pin::from(linker))
     }
+
+    /// Instantiates the module like [`instance`](Self::instance), returning a [`SendAsyncLinker`].
+    ///
+    /// Fails if any async host function was added with `add_async_func` instead of
+    /// `add_send_async_func`.
+    pub fn instance_send(self, module: &AstModule) -> WasmEdgeResult<SendAsyncLinker> {
+        if !self.local_fn_name.is_empty() {
+            return Err(WasmEdgeError::Operation(format!(
+                "async host functions without Send futures: {}",
+                self.local_fn_name.join(",")
+            )));
+        }
+        Ok(SendAsyncLinker {
+            inner: self.instance(module)?,
+        })
+    }
+}
+
+/// The async and intercepted imports of an [`AsyncLinkerBuilder`], to asyncify wasm for
+/// it without borrowing it.
+#[derive(Debug, Clone)]
+pub(crate) struct AsyncifyPass {
+    asyncify_imports: String,
+    intercepts: Vec<ImportIntercept>,
+}
+
+impl AsyncifyPass {
+    pub(crate) fn run<'a>(&self, wasm: &'a [u8]) -> WasmEdgeResult<Cow<'a, [u8]>> {
+        let codegen_config = CodegenConfig {
+            optimization_level: 2,
+            pass_argument: vec![(
+                "asyncify-imports".to_string(),
+                self.asyncify_imports.clone(),
+            )],
+            ..Default::default()
+        };
+
+        Loader::pass_async_module_from_bytes(
+            wasm,
+            ["asyncify", "strip"],
+            &codegen_config,
+            &self.intercepts,
+        )
+    }
 }
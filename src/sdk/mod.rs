@@ -2,17 +2,54 @@ use crate::core::types::WasmVal;
 
 #[cfg(feature = "aot")]
 mod aot;
+#[cfg(all(feature = "aot", feature = "tokio"))]
+mod aot_pool;
 
+mod coroutine;
+mod error;
 mod instance;
 mod linker;
 mod module;
+#[cfg(feature = "tokio")]
+mod pool;
+#[cfg(feature = "async-process")]
+mod process;
+mod wasi;
 
 pub use crate::core::instance::memory::Memory;
-pub use instance::function::ResultFuture;
+pub use instance::function::{ResultFuture, SendResultFuture};
 
 pub type AsyncFn = for<'a> fn(&'a mut linker::AsyncLinker, Vec<WasmVal>) -> ResultFuture<'a>;
-pub use instance::function::WasmEdgeResultFuture;
-pub use linker::{AsLinker, AsyncLinker, AsyncLinkerBuilder};
+pub type SendAsyncFn =
+    for<'a> fn(&'a mut linker::AsyncLinker, Vec<WasmVal>) -> SendResultFuture<'a>;
+pub type SyncFn = fn(&mut linker::AsyncLinker, &[WasmVal]) -> HostResult<Vec<WasmVal>>;
+pub use coroutine::{
+    CoroutineFuture, CoroutineLinker, NestedCallFuture, ASYNCIFY_MEMORY, STACK_POINTER,
+};
+pub use error::{
+    AsyncifyPhase, BoxError, CallError, Frame, GuestExit, HostError, HostResult, TrapReport,
+};
+#[cfg(feature = "tokio")]
+pub use instance::blocking::{BlockingCall, BlockingFn};
+#[cfg(feature = "tokio")]
+pub use instance::function::DeadlineFuture;
+pub use instance::function::{GuestCallback, SendFuture, WasmEdgeResultFuture};
+pub use linker::{AsLinker, AsyncLinker, AsyncLinkerBuilder, SendAsyncLinker};
 pub use module::AsyncImportModuleBuilder;
+#[cfg(feature = "tokio")]
+pub use pool::{AsyncLinkerPool, AsyncLinkerPoolBuilder, PooledLinker};
+#[cfg(feature = "async-process")]
+pub use process::ProcessConfig;
+#[cfg(feature = "async-socket")]
+pub use wasi::socket::{SocketAction, SocketConfig};
+#[cfg(feature = "async-wasi")]
+pub use wasi::vfs::Vfs;
+pub use wasi::{
+    stdio::{InputSource, OutputSink},
+    Preopen, WasiConfig,
+};
 
-pub use aot::{AotCompiler, AotConfig, CompilerOptimizationLevel};
+#[cfg(feature = "aot")]
+pub use aot::{AotCompiler, AotConfig, AotError, CompilerOptimizationLevel, CompilerOutputFormat};
+#[cfg(all(feature = "aot", feature = "tokio"))]
+pub use aot_pool::{AotCancelHandle, AotCompilePool, AotProgress, AotTask};
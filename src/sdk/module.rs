@@ -6,12 +6,22 @@ use wasmedge_types::{ValType, WasmEdgeResult};
 use crate::core::ImportModule;
 
 use crate::core::instance::function::Function;
-use crate::core::types::{WasmEdgeString, WasmVal};
+use crate::core::types::WasmEdgeString;
 
+#[cfg(feature = "tokio")]
+use super::instance::blocking::BlockingFn;
+use super::instance::function::HostFuncDef;
 use super::linker::AsyncLinker;
-use super::AsyncFn;
+use super::{AsyncFn, SendAsyncFn, SyncFn};
 
 impl ImportModule {
+    fn host_func_def<F>(&self, name: &str, real_fn: F) -> HostFuncDef<F> {
+        HostFuncDef {
+            import: format!("{}.{}", self.name, name).into(),
+            real_fn,
+        }
+    }
+
     pub fn add_async_func(
         &mut self,
         name: &str,
@@ -22,10 +32,60 @@ impl ImportModule {
     ) -> WasmEdgeResult<()> {
         use super::instance::function::wrapper_async_fn;
 
+        let key_ptr = data.keep_host_fn(self.host_func_def(name, real_fn));
+        let func_name = WasmEdgeString::new(name)?;
+        unsafe {
+            let func = Function::custom_create(ty, wrapper_async_fn, key_ptr, data, cost)?;
+            ffi::WasmEdge_ModuleInstanceAddFunction(
+                self.inner.0,
+                func_name.as_raw(),
+                func.inner.0 as *mut _,
+            );
+            Ok(())
+        }
+    }
+
+    pub fn add_send_async_func(
+        &mut self,
+        name: &str,
+        data: &mut AsyncLinker,
+        ty: (Vec<ValType>, Vec<ValType>),
+        real_fn: SendAsyncFn,
+        cost: u64,
+    ) -> WasmEdgeResult<()> {
+        use super::instance::function::wrapper_send_async_fn;
+
+        let key_ptr = data.keep_host_fn(self.host_func_def(name, real_fn));
+        let func_name = WasmEdgeString::new(name)?;
+        unsafe {
+            let func = Function::custom_create(ty, wrapper_send_async_fn, key_ptr, data, cost)?;
+            ffi::WasmEdge_ModuleInstanceAddFunction(
+                self.inner.0,
+                func_name.as_raw(),
+                func.inner.0 as *mut _,
+            );
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn add_blocking_func(
+        &mut self,
+        name: &str,
+        data: &mut AsyncLinker,
+        ty: (Vec<ValType>, Vec<ValType>),
+        buffers: &[(usize, usize)],
+        real_fn: BlockingFn,
+        cost: u64,
+    ) -> WasmEdgeResult<()> {
+        use super::instance::blocking::{wrapper_blocking_fn, BlockingFuncDef};
+
+        let import = format!("{}.{}", self.name, name).into();
+        let key_ptr = data.keep_host_fn(BlockingFuncDef::new(import, &ty, buffers, real_fn)?);
+
         let func_name = WasmEdgeString::new(name)?;
         unsafe {
-            let func =
-                Function::custom_create(ty, wrapper_async_fn, real_fn as *mut _, data, cost)?;
+            let func = Function::custom_create(ty, wrapper_blocking_fn, key_ptr, data, cost)?;
             ffi::WasmEdge_ModuleInstanceAddFunction(
                 self.inner.0,
                 func_name.as_raw(),
@@ -35,19 +95,20 @@ impl ImportModule {
         }
     }
 
-    pub fn add_func<T: Sized>(
+    pub fn add_func(
         &mut self,
         name: &str,
-        data: *mut T,
+        data: &mut AsyncLinker,
         ty: (Vec<ValType>, Vec<ValType>),
-        real_fn: fn(&mut AsyncLinker, &[WasmVal]) -> WasmEdgeResult<Vec<WasmVal>>,
+        real_fn: SyncFn,
         cost: u64,
     ) -> WasmEdgeResult<()> {
         use super::instance::function::wrapper_fn;
 
+        let key_ptr = data.keep_host_fn(self.host_func_def(name, real_fn));
         let func_name = WasmEdgeString::new(name)?;
         unsafe {
-            let func = Function::custom_create(ty, wrapper_fn, real_fn as *mut _, data, cost)?;
+            let func = Function::custom_create(ty, wrapper_fn, key_ptr, data, cost)?;
             ffi::WasmEdge_ModuleInstanceAddFunction(
                 self.inner.0,
                 func_name.as_raw(),
@@ -63,6 +124,7 @@ pub struct AsyncImportModuleBuilder<'a, 'b> {
     pub(crate) import_obj: ImportModule,
     pub(crate) linker_ctx: &'a mut AsyncLinker,
     pub(crate) async_fn_name: &'b mut Vec<String>,
+    pub(crate) local_fn_name: &'b mut Vec<String>,
 }
 
 impl AsyncImportModuleBuilder<'_, '_> {
@@ -74,16 +136,58 @@ impl AsyncImportModuleBuilder<'_, '_> {
     ) -> WasmEdgeResult<()> {
         self.import_obj
             .add_async_func(name, self.linker_ctx, ty, real_fn, 0)?;
+        let full_name = format!("{}.{}", self.import_obj.name, name);
+        self.local_fn_name.push(full_name.clone());
+        self.async_fn_name.push(full_name);
+        Ok(())
+    }
+
+    /// Adds an async host function whose future is `Send`, which keeps the linker usable
+    /// through [`AsyncLinkerBuilder::instance_send`](crate::AsyncLinkerBuilder::instance_send).
+    pub fn add_send_async_func(
+        &mut self,
+        name: &str,
+        ty: (Vec<ValType>, Vec<ValType>),
+        real_fn: SendAsyncFn,
+    ) -> WasmEdgeResult<()> {
+        self.import_obj
+            .add_send_async_func(name, self.linker_ctx, ty, real_fn, 0)?;
         self.async_fn_name
             .push(format!("{}.{}", self.import_obj.name, name));
         Ok(())
     }
 
+    /// Adds a host function that calls blocking code, such as compression or sync file I/O.
+    ///
+    /// `real_fn` runs on `tokio`'s blocking pool while the guest is suspended. Each
+    /// `(ptr, len)` pair in `buffers` names two `i32` parameters describing a region of
+    /// guest memory; the regions are copied into [`BlockingCall::buffers`](crate::BlockingCall)
+    /// and copied back once `real_fn` succeeds.
+    ///
+    /// Like `add_async_func`, it keeps the module from being instantiated with
+    /// [`AsyncLinkerBuilder::instance_send`](crate::AsyncLinkerBuilder::instance_send).
+    #[cfg(feature = "tokio")]
+    pub fn add_blocking_func(
+        &mut self,
+        name: &str,
+        ty: (Vec<ValType>, Vec<ValType>),
+        buffers: &[(usize, usize)],
+        real_fn: BlockingFn,
+    ) -> WasmEdgeResult<()> {
+        self.import_obj
+            .add_blocking_func(name, self.linker_ctx, ty, buffers, real_fn, 0)?;
+        // the future borrows the linker across the blocking call, so it is not `Send`
+        let full_name = format!("{}.{}", self.import_obj.name, name);
+        self.local_fn_name.push(full_name.clone());
+        self.async_fn_name.push(full_name);
+        Ok(())
+    }
+
     pub fn add_func(
         &mut self,
         name: &str,
         ty: (Vec<ValType>, Vec<ValType>),
-        real_fn: fn(&mut AsyncLinker, &[WasmVal]) -> WasmEdgeResult<Vec<WasmVal>>,
+        real_fn: SyncFn,
     ) -> WasmEdgeResult<()> {
         self.import_obj
             .add_func(name, self.linker_ctx, ty, real_fn, 0)
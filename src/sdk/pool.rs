@@ -0,0 +1,418 @@
+//! A pool of pre-instantiated [`AsyncLinker`]s sharing one module and host definition.
+
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    runtime::Handle,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use wasmedge_types::{error::WasmEdgeError, WasmEdgeResult};
+
+use crate::core::AstModule;
+
+use super::linker::{AsyncLinker, AsyncLinkerBuilder};
+
+type LinkerFactory = dyn Fn() -> WasmEdgeResult<AsyncLinkerBuilder> + Send + Sync;
+type HealthCheck = dyn Fn(&AsyncLinker) -> bool + Send + Sync;
+type ResetHook = dyn Fn(&mut AsyncLinker) -> bool + Send + Sync;
+
+struct IdleLinker {
+    linker: Pin<Box<AsyncLinker>>,
+    uses: usize,
+    idle_since: Instant,
+}
+
+struct Idle {
+    linkers: VecDeque<IdleLinker>,
+    /// The linkers being instantiated for the queue.
+    pending: usize,
+}
+
+struct PoolInner {
+    factory: Box<LinkerFactory>,
+    module: AstModule,
+    idle: Mutex<Idle>,
+    permits: Arc<Semaphore>,
+    min_size: usize,
+    max_size: usize,
+    reuse: bool,
+    idle_timeout: Option<Duration>,
+    max_uses: Option<usize>,
+    health_check: Option<Box<HealthCheck>>,
+    reset: Option<Box<ResetHook>>,
+}
+
+impl PoolInner {
+    fn new_linker(&self) -> WasmEdgeResult<Pin<Box<AsyncLinker>>> {
+        (self.factory)()?.instance(&self.module)
+    }
+
+    fn is_healthy(&self, linker: &AsyncLinker) -> bool {
+        !linker.is_poisoned() && self.health_check.as_ref().is_none_or(|f| f(linker))
+    }
+
+    /// Returns the number of linkers handed out.
+    fn in_use(&self) -> usize {
+        self.max_size - self.permits.available_permits()
+    }
+
+    /// Drops idle linkers that outlived the idle timeout, keeping at least `min_size`.
+    fn evict_expired(&self, idle: &mut Idle) {
+        if let Some(timeout) = self.idle_timeout {
+            // the most recently returned linkers are at the back
+            while idle.linkers.len() > self.min_size {
+                match idle.linkers.front() {
+                    Some(front) if front.idle_since.elapsed() >= timeout => {
+                        idle.linkers.pop_front();
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    /// Returns how many linkers to instantiate so that the pool holds `min_size`, counting
+    /// the idle ones, those being instantiated and the `in_use` ones.
+    fn missing(&self, idle: &Idle, in_use: usize) -> usize {
+        self.min_size
+            .saturating_sub(idle.linkers.len() + idle.pending + in_use)
+    }
+
+    /// Instantiates the linkers missing for the pool to hold `min_size` again.
+    fn replenish(self: &Arc<Self>) {
+        let n = {
+            let mut idle = self.idle.lock().unwrap();
+            let n = self.missing(&idle, self.in_use());
+            idle.pending += n;
+            n
+        };
+        self.spawn_instances(n);
+    }
+
+    /// Instantiates `n` idle linkers already counted as pending, on blocking threads of the
+    /// runtime when there is one.
+    fn spawn_instances(self: &Arc<Self>, n: usize) {
+        for _ in 0..n {
+            let pool = self.clone();
+            let instantiate = move || {
+                let linker = pool.new_linker();
+                let mut idle = pool.idle.lock().unwrap();
+                idle.pending -= 1;
+                // a failing instantiation shows when `acquire` instantiates on demand
+                if let Ok(linker) = linker {
+                    idle.linkers.push_back(IdleLinker {
+                        linker,
+                        uses: 0,
+                        idle_since: Instant::now(),
+                    });
+                }
+            };
+            match Handle::try_current() {
+                Ok(rt) => {
+                    rt.spawn_blocking(instantiate);
+                }
+                Err(_) => instantiate(),
+            }
+        }
+    }
+}
+
+/// Hands out [`AsyncLinker`]s instantiated from one module, up to a maximum size.
+///
+/// When a [`PooledLinker`] is dropped, its linker is discarded and a fresh instance of
+/// the module takes its place in the pool, instantiated on a blocking thread of the
+/// runtime, so no guest memory, globals, tables, WASI state or captured stdio is shared
+/// between uses. Pools built with
+/// [`reuse_instances`](AsyncLinkerPoolBuilder::reuse_instances) take the linker back as
+/// it is instead, except for poisoned linkers, linkers failing the reset hook and linkers
+/// that reached `max_uses`.
+///
+/// Idle linkers failing the health check are discarded when acquired. The pool
+/// instantiates linkers again whenever discarded ones leave it with fewer than
+/// `min_size`.
+#[derive(Clone)]
+pub struct AsyncLinkerPool {
+    inner: Arc<PoolInner>,
+}
+
+impl AsyncLinkerPool {
+    /// Starts building a pool for `wasm`. `factory` is called once per instance and must set
+    /// up the same WASI and import modules every time.
+    pub fn builder<F>(wasm: &[u8], factory: F) -> AsyncLinkerPoolBuilder
+    where
+        F: Fn() -> WasmEdgeResult<AsyncLinkerBuilder> + Send + Sync + 'static,
+    {
+        AsyncLinkerPoolBuilder {
+            wasm: wasm.to_vec(),
+            factory: Box::new(factory),
+            min_size: 0,
+            max_size: 16,
+            reuse: false,
+            idle_timeout: None,
+            max_uses: None,
+            health_check: None,
+            reset: None,
+        }
+    }
+
+    /// Waits for a free slot and returns a linker, reusing an idle one when possible.
+    pub async fn acquire(&self) -> WasmEdgeResult<PooledLinker> {
+        let permit = self
+            .inner
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| WasmEdgeError::Operation("linker pool closed".to_string()))?;
+
+        let mut discarded = false;
+        let idle = loop {
+            let idle = {
+                let mut idle = self.inner.idle.lock().unwrap();
+                self.inner.evict_expired(&mut idle);
+                idle.linkers.pop_back()
+            };
+            match idle {
+                Some(idle) if self.inner.is_healthy(&idle.linker) => break Some(idle),
+                Some(_) => discarded = true,
+                None => break None,
+            }
+        };
+        if discarded {
+            self.inner.replenish();
+        }
+
+        let (linker, uses) = match idle {
+            Some(idle) => (idle.linker, idle.uses + 1),
+            None => (self.inner.new_linker()?, 1),
+        };
+        Ok(PooledLinker {
+            linker: Some(linker),
+            uses,
+            pool: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// Returns the number of idle linkers ready to be acquired.
+    pub fn idle_len(&self) -> usize {
+        self.inner.idle.lock().unwrap().linkers.len()
+    }
+
+    /// Returns the number of linkers that can still be acquired without waiting.
+    pub fn available(&self) -> usize {
+        self.inner.permits.available_permits()
+    }
+}
+
+pub struct AsyncLinkerPoolBuilder {
+    wasm: Vec<u8>,
+    factory: Box<LinkerFactory>,
+    min_size: usize,
+    max_size: usize,
+    reuse: bool,
+    idle_timeout: Option<Duration>,
+    max_uses: Option<usize>,
+    health_check: Option<Box<HealthCheck>>,
+    reset: Option<Box<ResetHook>>,
+}
+
+impl AsyncLinkerPoolBuilder {
+    /// Sets the number of linkers instantiated up front and kept despite the idle timeout.
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the maximum number of linkers handed out at the same time. Defaults to 16.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Takes returned linkers back as they are instead of replacing them with fresh
+    /// instances. Their guest memory, globals, tables, WASI state and captured stdio are
+    /// then what the previous user left, unless a [`reset_hook`](Self::reset_hook) cleans
+    /// them up. Off by default.
+    pub fn reuse_instances(mut self, reuse: bool) -> Self {
+        self.reuse = reuse;
+        self
+    }
+
+    /// Drops linkers that stayed idle longer than `timeout`. Expired linkers are dropped
+    /// when a linker is acquired or returned.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Replaces a reused linker with a fresh instance after it was acquired `max_uses`
+    /// times.
+    pub fn max_uses(mut self, max_uses: usize) -> Self {
+        self.max_uses = Some(max_uses);
+        self
+    }
+
+    /// Checks idle linkers before handing them out; linkers failing the check are dropped.
+    pub fn health_check<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&AsyncLinker) -> bool + Send + Sync + 'static,
+    {
+        self.health_check = Some(Box::new(f));
+        self
+    }
+
+    /// Runs `f` on every linker returned to a pool that
+    /// [reuses instances](Self::reuse_instances), e.g. to zero guest buffers or call a
+    /// guest export resetting its state. Linkers for which `f` returns `false` are
+    /// dropped instead of being reused.
+    pub fn reset_hook<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut AsyncLinker) -> bool + Send + Sync + 'static,
+    {
+        self.reset = Some(Box::new(f));
+        self
+    }
+
+    pub fn build(self) -> WasmEdgeResult<AsyncLinkerPool> {
+        let AsyncLinkerPoolBuilder {
+            wasm,
+            factory,
+            min_size,
+            max_size,
+            reuse,
+            idle_timeout,
+            max_uses,
+            health_check,
+            reset,
+        } = self;
+
+        if max_size == 0 || min_size > max_size {
+            return Err(WasmEdgeError::Operation(format!(
+                "invalid linker pool size: min {} max {}",
+                min_size, max_size
+            )));
+        }
+
+        let mut builder = factory()?;
+        let module = builder.load_wasm(&wasm)?;
+        let first = builder.instance(&module)?;
+
+        let inner = PoolInner {
+            factory,
+            module,
+            idle: Mutex::new(Idle {
+                linkers: VecDeque::with_capacity(max_size),
+                pending: 0,
+            }),
+            permits: Arc::new(Semaphore::new(max_size)),
+            min_size,
+            max_size,
+            reuse,
+            idle_timeout,
+            max_uses,
+            health_check,
+            reset,
+        };
+
+        {
+            let mut idle = inner.idle.lock().unwrap();
+            idle.linkers.push_back(IdleLinker {
+                linker: first,
+                uses: 0,
+                idle_since: Instant::now(),
+            });
+            while idle.linkers.len() < min_size {
+                idle.linkers.push_back(IdleLinker {
+                    linker: inner.new_linker()?,
+                    uses: 0,
+                    idle_since: Instant::now(),
+                });
+            }
+        }
+
+        Ok(AsyncLinkerPool {
+            inner: Arc::new(inner),
+        })
+    }
+}
+
+/// A linker borrowed from an [`AsyncLinkerPool`], returned to it on drop.
+pub struct PooledLinker {
+    linker: Option<Pin<Box<AsyncLinker>>>,
+    uses: usize,
+    pool: Arc<PoolInner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledLinker {
+    /// Drops the linker instead of returning it to the pool.
+    pub fn discard(mut self) {
+        self.linker.take();
+    }
+
+    /// Returns `linker` if the pool may reuse it, after running the reset hook.
+    fn reusable(&self, mut linker: Pin<Box<AsyncLinker>>) -> Option<Pin<Box<AsyncLinker>>> {
+        let pool = &self.pool;
+        if !pool.reuse || linker.is_poisoned() || pool.max_uses.is_some_and(|max| self.uses >= max)
+        {
+            return None;
+        }
+        if let Some(reset) = &pool.reset {
+            // the hook gets the linker the same way a call does
+            if !reset(unsafe { linker.as_mut().get_unchecked_mut() }) {
+                return None;
+            }
+        }
+        Some(linker)
+    }
+}
+
+impl Deref for PooledLinker {
+    type Target = Pin<Box<AsyncLinker>>;
+    fn deref(&self) -> &Self::Target {
+        self.linker.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledLinker {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.linker.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledLinker {
+    fn drop(&mut self) {
+        let returned = self.linker.take();
+        // a returned linker the pool does not reuse is replaced by a fresh one
+        let recycled = returned.is_some() && !self.pool.reuse;
+        let reused = returned.and_then(|linker| self.reusable(linker));
+
+        let n = {
+            let mut idle = self.pool.idle.lock().unwrap();
+            if let Some(linker) = reused {
+                idle.linkers.push_back(IdleLinker {
+                    linker,
+                    uses: self.uses,
+                    idle_since: Instant::now(),
+                });
+            }
+            self.pool.evict_expired(&mut idle);
+            // the permit of this linker is released after `drop`
+            let n = self.pool.missing(&idle, self.pool.in_use() - 1);
+            let n = match recycled {
+                true => n.max(1),
+                false => n,
+            };
+            idle.pending += n;
+            n
+        };
+        self.pool.spawn_instances(n);
+    }
+}
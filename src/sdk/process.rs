@@ -0,0 +1,480 @@
+//! An async host module spawning subprocesses through `tokio::process`.
+//!
+//! Unlike the WasmEdge process plugin, which runs a command to completion while the
+//! thread blocks, the guest holds a handle to the running process and streams its stdio
+//! through guest memory; every wait suspends the guest. The functions of the
+//! `wasmedge_asyncify_process` module return a WASI errno:
+//!
+//! - `spawn(prog, prog_len, args, args_len, handle)` starts `prog` with the
+//!   NUL-separated arguments in `args` and stores a handle at `handle`.
+//! - `write_stdin(handle, buf, len, nwritten)` writes all of `buf` to the process.
+//! - `close_stdin(handle)` ends its input.
+//! - `read_stdout(handle, buf, len, nread)` and `read_stderr(...)` read what the process
+//!   wrote so far, waiting for at least a byte; 0 bytes mean the stream ended.
+//! - `wait(handle, status)` waits for the process to exit and stores its exit code, or
+//!   128 plus the signal that ended it.
+//! - `kill(handle)` kills the process and `close(handle)` releases the handle, killing
+//!   the process if it still runs.
+//!
+//! Only allowlisted programs start, and nothing of the host environment is passed on.
+//! A process that outlives the timeout is killed and the call waiting on it fails with
+//! `ETIMEDOUT`; one writing more output than allowed is killed and the read fails with
+//! `EFBIG`. On unix, memory and CPU time limits are set on the process before it runs
+//! the program. Reading one stream while the process is blocked writing the other one
+//! waits forever, so guests read stdout and stderr alternately or close one.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    future::Future,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command},
+    time::Instant,
+};
+use wasmedge_types::{ValType::I32, WasmEdgeResult};
+
+use super::{
+    instance::function::SendResultFuture,
+    linker::{AsyncLinker, MAIN_MEMORY},
+    module::AsyncImportModuleBuilder,
+    wasi::preview1::{abi::*, add_async},
+};
+use crate::core::types::WasmVal;
+
+/// The import module of the process functions.
+pub(crate) const PROCESS_MODULE: &str = "wasmedge_asyncify_process";
+
+type ArgFilter = Arc<dyn Fn(&str, &[String]) -> bool + Send + Sync>;
+
+/// Which programs a guest may run and with what, for
+/// [`create_async_process`](crate::sdk::AsyncLinkerBuilder::create_async_process).
+///
+/// Nothing is allowed until programs are added with [`allow`](Self::allow).
+#[derive(Clone)]
+pub struct ProcessConfig {
+    allowed: HashSet<String>,
+    arg_filter: Option<ArgFilter>,
+    envs: Vec<(String, String)>,
+    current_dir: Option<PathBuf>,
+    max_processes: usize,
+    timeout: Option<Duration>,
+    max_output: Option<u64>,
+    max_memory: Option<u64>,
+    max_cpu_time: Option<Duration>,
+}
+
+impl ProcessConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows the guest to run `program`, compared with the program the guest names as
+    /// is. A bare name is looked up by the host in its own `PATH`, as the processes run
+    /// without one.
+    pub fn allow(mut self, program: impl Into<String>) -> Self {
+        self.allowed.insert(program.into());
+        self
+    }
+
+    /// Asks `f` with the program and its arguments before each spawn. Refused spawns
+    /// fail with `EACCES`.
+    pub fn arg_filter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str, &[String]) -> bool + Send + Sync + 'static,
+    {
+        self.arg_filter = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets an environment variable of the spawned processes, which get no other.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        self.envs.retain(|(k, _)| *k != key);
+        self.envs.push((key, value.into()));
+        self
+    }
+
+    /// Runs the processes in `dir` instead of the working directory of the host.
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Limits how many processes the guest may hold handles to at once, 8 by default.
+    /// Spawning more fails with `EAGAIN`.
+    pub fn max_processes(mut self, max_processes: usize) -> Self {
+        self.max_processes = max_processes;
+        self
+    }
+
+    /// Kills a process once it has run for `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kills a process once it has written `bytes` to its stdout and stderr together;
+    /// the read past the limit fails with `EFBIG`.
+    pub fn max_output(mut self, bytes: u64) -> Self {
+        self.max_output = Some(bytes);
+        self
+    }
+
+    /// Limits the address space of each process to `bytes` (`RLIMIT_AS`). Only
+    /// supported on unix; elsewhere spawning fails with `ENOTSUP`.
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Limits the CPU time of each process (`RLIMIT_CPU`, rounded up to whole seconds);
+    /// past it the process is killed with `SIGXCPU`. Only supported on unix; elsewhere
+    /// spawning fails with `ENOTSUP`.
+    pub fn max_cpu_time(mut self, time: Duration) -> Self {
+        self.max_cpu_time = Some(time);
+        self
+    }
+
+    fn allows(&self, program: &str, args: &[String]) -> bool {
+        self.allowed.contains(program)
+            && self
+                .arg_filter
+                .as_ref()
+                .is_none_or(|filter| filter(program, args))
+    }
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        ProcessConfig {
+            allowed: HashSet::new(),
+            arg_filter: None,
+            envs: vec![],
+            current_dir: None,
+            max_processes: 8,
+            timeout: None,
+            max_output: None,
+            max_memory: None,
+            max_cpu_time: None,
+        }
+    }
+}
+
+impl fmt::Debug for ProcessConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessConfig")
+            .field("allowed", &self.allowed)
+            .field("arg_filter", &self.arg_filter.as_ref().map(|_| ".."))
+            .field("envs", &self.envs)
+            .field("current_dir", &self.current_dir)
+            .field("max_processes", &self.max_processes)
+            .field("timeout", &self.timeout)
+            .field("max_output", &self.max_output)
+            .field("max_memory", &self.max_memory)
+            .field("max_cpu_time", &self.max_cpu_time)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    deadline: Option<Instant>,
+    /// How many more bytes of output the process may write, if limited.
+    output_left: Option<u64>,
+}
+
+impl Process {
+    /// Runs `fut` until the deadline of the process, killing it when the deadline passes.
+    async fn until_deadline<T>(
+        deadline: Option<Instant>,
+        child: &mut Child,
+        fut: impl Future<Output = std::io::Result<T>>,
+    ) -> WasiResult<T> {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return Ok(fut.await?),
+        };
+        match tokio::time::timeout_at(deadline, fut).await {
+            Ok(r) => Ok(r?),
+            Err(_) => {
+                let _ = child.start_kill();
+                Err(Errno::TIMEDOUT)
+            }
+        }
+    }
+}
+
+/// The processes of a guest.
+#[derive(Debug)]
+pub(crate) struct ProcessCtx {
+    config: ProcessConfig,
+    processes: BTreeMap<u32, Process>,
+    next_handle: u32,
+}
+
+impl ProcessCtx {
+    pub(crate) fn new(config: ProcessConfig) -> Self {
+        ProcessCtx {
+            config,
+            processes: BTreeMap::new(),
+            next_handle: 1,
+        }
+    }
+
+    fn get_mut(&mut self, handle: u32) -> WasiResult<&mut Process> {
+        self.processes.get_mut(&handle).ok_or(Errno::BADF)
+    }
+}
+
+fn processes(linker: &mut AsyncLinker) -> WasiResult<&mut ProcessCtx> {
+    linker.processes.as_deref_mut().ok_or(Errno::BADF)
+}
+
+/// `spawn(prog, prog_len, args, args_len, handle) -> errno`
+async fn spawn(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let program = read_string(linker, args.usize(0)?, args.usize(1)?)?;
+    let argv = read_string(linker, args.usize(2)?, args.usize(3)?)?;
+    let argv = argv
+        .split('\0')
+        .filter(|arg| !arg.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    let ctx = processes(linker)?;
+    if !ctx.config.allows(&program, &argv) {
+        return Err(Errno::ACCES);
+    }
+    if ctx.processes.len() >= ctx.config.max_processes {
+        return Err(Errno::AGAIN);
+    }
+
+    let mut command = Command::new(resolve_program(&program).await?);
+    command
+        .args(&argv)
+        .env_clear()
+        .envs(ctx.config.envs.iter().cloned())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(dir) = &ctx.config.current_dir {
+        command.current_dir(dir);
+    }
+    set_limits(&mut command, &ctx.config)?;
+    let mut child = command.spawn()?;
+    let process = Process {
+        stdin: child.stdin.take(),
+        stdout: child.stdout.take().ok_or(Errno::IO)?,
+        stderr: child.stderr.take().ok_or(Errno::IO)?,
+        deadline: ctx.config.timeout.map(|timeout| Instant::now() + timeout),
+        output_left: ctx.config.max_output,
+        child,
+    };
+
+    let handle = ctx.next_handle;
+    ctx.next_handle += 1;
+    ctx.processes.insert(handle, process);
+    write_u32(linker, args.usize(4)?, handle)
+}
+
+/// `write_stdin(handle, buf, len, nwritten) -> errno`
+async fn write_stdin(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let handle = args.u32(0)?;
+    let data = read_bytes(linker, args.usize(1)?, args.usize(2)?)?;
+
+    let process = processes(linker)?.get_mut(handle)?;
+    let stdin = process.stdin.as_mut().ok_or(Errno::PIPE)?;
+    Process::until_deadline(process.deadline, &mut process.child, async {
+        stdin.write_all(&data).await?;
+        stdin.flush().await
+    })
+    .await?;
+    write_u32(linker, args.usize(3)?, data.len() as u32)
+}
+
+/// `close_stdin(handle) -> errno`
+async fn close_stdin(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    processes(linker)?.get_mut(args.u32(0)?)?.stdin = None;
+    Ok(())
+}
+
+async fn read_stream(linker: &mut AsyncLinker, args: Args<'_>, stderr: bool) -> WasiResult<()> {
+    let handle = args.u32(0)?;
+    let (ptr, len) = (args.usize(1)?, args.usize(2)?);
+    linker.get_memory(MAIN_MEMORY, ptr, len)?;
+    let mut buf = vec![0; len.min(MAX_READ)];
+
+    let process = processes(linker)?.get_mut(handle)?;
+    let stream: &mut (dyn AsyncRead + Send + Unpin) = match stderr {
+        true => &mut process.stderr,
+        false => &mut process.stdout,
+    };
+    let n = Process::until_deadline(process.deadline, &mut process.child, stream.read(&mut buf))
+        .await?;
+    if let Some(left) = &mut process.output_left {
+        match left.checked_sub(n as u64) {
+            Some(rest) => *left = rest,
+            None => {
+                let _ = process.child.start_kill();
+                return Err(Errno::FBIG);
+            }
+        }
+    }
+
+    write_bytes(linker, args.usize(1)?, &buf[..n])?;
+    write_u32(linker, args.usize(3)?, n as u32)
+}
+
+/// Returns the path to run for `program`: bare names are looked up in the `PATH` of the
+/// host, since the process itself gets no `PATH` to look them up in.
+async fn resolve_program(program: &str) -> WasiResult<PathBuf> {
+    let path = Path::new(program);
+    if path.parent() != Some(Path::new("")) {
+        return Ok(path.to_path_buf());
+    }
+    let dirs = std::env::var_os("PATH").ok_or(Errno::NOENT)?;
+    for dir in std::env::split_paths(&dirs) {
+        let candidate = dir.join(program);
+        match tokio::fs::metadata(&candidate).await {
+            Ok(meta) if meta.is_file() && is_executable(&meta) => return Ok(candidate),
+            _ => {}
+        }
+    }
+    Err(Errno::NOENT)
+}
+
+fn is_executable(meta: &std::fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        true
+    }
+}
+
+/// Sets the memory and CPU time limits of `config` on the processes `command` spawns.
+fn set_limits(command: &mut Command, config: &ProcessConfig) -> WasiResult<()> {
+    if config.max_memory.is_none() && config.max_cpu_time.is_none() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        let memory = config.max_memory;
+        let cpu = config
+            .max_cpu_time
+            .map(|time| time.as_secs() + u64::from(time.subsec_nanos() > 0));
+        let set = |resource, limit: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: limit as libc::rlim_t,
+                rlim_max: limit as libc::rlim_t,
+            };
+            match unsafe { libc::setrlimit(resource, &limit) } {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            }
+        };
+        // SAFETY: the closure only calls `setrlimit`, which is async-signal-safe, as the
+        // child runs it between `fork` and `exec`
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(bytes) = memory {
+                    set(libc::RLIMIT_AS, bytes)?;
+                }
+                if let Some(secs) = cpu {
+                    set(libc::RLIMIT_CPU, secs)?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = command;
+        Err(Errno::NOTSUP)
+    }
+}
+
+/// `read_stdout(handle, buf, len, nread) -> errno`
+async fn read_stdout(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    read_stream(linker, args, false).await
+}
+
+/// `read_stderr(handle, buf, len, nread) -> errno`
+async fn read_stderr(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    read_stream(linker, args, true).await
+}
+
+/// `wait(handle, status) -> errno`
+async fn wait(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let process = processes(linker)?.get_mut(args.u32(0)?)?;
+    // the process may wait for its input to end
+    process.stdin = None;
+    let deadline = process.deadline;
+    let child = &mut process.child;
+    let status = match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, child.wait()).await {
+            Ok(status) => status?,
+            Err(_) => {
+                let _ = child.start_kill();
+                return Err(Errno::TIMEDOUT);
+            }
+        },
+        None => child.wait().await?,
+    };
+
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+    #[cfg(not(unix))]
+    let signal: Option<i32> = None;
+    let code = match (status.code(), signal) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => -1,
+    };
+    write_u32(linker, args.usize(1)?, code as u32)
+}
+
+/// `kill(handle) -> errno`
+async fn kill(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let process = processes(linker)?.get_mut(args.u32(0)?)?;
+    Ok(process.child.start_kill()?)
+}
+
+/// `close(handle) -> errno`
+async fn close(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    // dropping the child kills it if it still runs
+    processes(linker)?
+        .processes
+        .remove(&args.u32(0)?)
+        .ok_or(Errno::BADF)?;
+    Ok(())
+}
+
+/// Adds the process functions to `builder`.
+pub(crate) fn add_functions(b: &mut AsyncImportModuleBuilder) -> WasmEdgeResult<()> {
+    add_async!(b, spawn, [I32, I32, I32, I32, I32]);
+    add_async!(b, write_stdin, [I32, I32, I32, I32]);
+    add_async!(b, close_stdin, [I32]);
+    add_async!(b, read_stdout, [I32, I32, I32, I32]);
+    add_async!(b, read_stderr, [I32, I32, I32, I32]);
+    add_async!(b, wait, [I32, I32]);
+    add_async!(b, kill, [I32]);
+    add_async!(b, close, [I32]);
+    Ok(())
+}
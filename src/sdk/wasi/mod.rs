@@ -0,0 +1,433 @@
+//! Configuration of the WASI module a guest imports as `wasi_snapshot_preview1`.
+
+#[cfg(feature = "tokio")]
+pub(crate) mod poll;
+#[cfg(feature = "async-wasi")]
+pub(crate) mod preview1;
+// the errno values and guest memory helpers are shared with the stdio and
+// `poll_oneoff` replacements of WasmEdge WASI functions
+#[cfg(not(feature = "async-wasi"))]
+pub(crate) mod preview1 {
+    pub(crate) mod abi;
+}
+#[cfg(feature = "async-socket")]
+pub(crate) mod socket;
+pub(crate) mod stdio;
+#[cfg(feature = "async-wasi")]
+pub(crate) mod vfs;
+
+use std::{
+    ffi::CString,
+    path::{Path, PathBuf},
+};
+
+use wasmedge_types::{error::WasmEdgeError, WasmEdgeResult};
+
+use stdio::{InputSource, OutputSink};
+#[cfg(feature = "async-wasi")]
+use vfs::Vfs;
+
+/// A host directory made visible to the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preopen {
+    /// The path the guest opens, e.g. `/data` or `.`.
+    pub guest: String,
+    /// The host directory it maps to.
+    pub host: PathBuf,
+    /// Whether the guest may only read from the directory.
+    pub read_only: bool,
+}
+
+/// Builds the arguments, environment and preopened directories of a WASI module.
+///
+/// Entries are validated when the module is created, so a NUL byte, a malformed
+/// variable or a missing directory is reported instead of silently dropped.
+#[derive(Debug, Clone, Default)]
+pub struct WasiConfig {
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    preopens: Vec<Preopen>,
+    #[cfg(feature = "async-wasi")]
+    mounts: Vec<(String, Vfs)>,
+    inherit_args: bool,
+    inherit_env: bool,
+    stdin: InputSource,
+    stdout: OutputSink,
+    stderr: OutputSink,
+}
+
+impl WasiConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an argument. By convention the first one is the program name.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets an environment variable, replacing any earlier value of `key`.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        self.envs.retain(|(k, _)| *k != key);
+        self.envs.push((key, value.into()));
+        self
+    }
+
+    pub fn envs<I, K, V>(mut self, envs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        for (key, value) in envs {
+            self = self.env(key, value);
+        }
+        self
+    }
+
+    /// Passes the arguments of the host process first, followed by the ones added with
+    /// [`arg`](Self::arg).
+    pub fn inherit_args(mut self) -> Self {
+        self.inherit_args = true;
+        self
+    }
+
+    /// Passes the environment of the host process. Variables set with [`env`](Self::env)
+    /// take precedence.
+    pub fn inherit_env(mut self) -> Self {
+        self.inherit_env = true;
+        self
+    }
+
+    /// Maps the host directory `host` to `guest`.
+    pub fn preopen(mut self, guest: impl Into<String>, host: impl AsRef<Path>) -> Self {
+        self.preopens.push(Preopen {
+            guest: guest.into(),
+            host: host.as_ref().to_path_buf(),
+            read_only: false,
+        });
+        self
+    }
+
+    /// Maps the host directory `host` to `guest`, without write access.
+    pub fn preopen_read_only(mut self, guest: impl Into<String>, host: impl AsRef<Path>) -> Self {
+        self.preopens.push(Preopen {
+            guest: guest.into(),
+            host: host.as_ref().to_path_buf(),
+            read_only: true,
+        });
+        self
+    }
+
+    /// Maps the file tree `vfs` to `guest`. Trees are mounted after the host
+    /// directories of [`preopen`](Self::preopen).
+    ///
+    /// Only the async WASI module of
+    /// [`create_async_wasi`](crate::sdk::AsyncLinkerBuilder::create_async_wasi) can
+    /// mount a [`Vfs`].
+    #[cfg(feature = "async-wasi")]
+    pub fn preopen_vfs(mut self, guest: impl Into<String>, vfs: Vfs) -> Self {
+        self.mounts.push((guest.into(), vfs));
+        self
+    }
+
+    /// Feeds the guest's stdin from `source` instead of the host's stdin.
+    pub fn stdin(mut self, source: InputSource) -> Self {
+        self.stdin = source;
+        self
+    }
+
+    /// Sends the guest's stdout to `sink` instead of the host's stdout.
+    pub fn stdout(mut self, sink: OutputSink) -> Self {
+        self.stdout = sink;
+        self
+    }
+
+    /// Sends the guest's stderr to `sink` instead of the host's stderr.
+    pub fn stderr(mut self, sink: OutputSink) -> Self {
+        self.stderr = sink;
+        self
+    }
+
+    /// Resolves inherited values and checks every entry.
+    pub(crate) fn resolve(&self) -> WasmEdgeResult<ResolvedWasi> {
+        let mut args = vec![];
+        if self.inherit_args {
+            for arg in std::env::args_os() {
+                args.push(arg.into_string().map_err(|arg| {
+                    invalid(format!("host argument {:?} is not valid UTF-8", arg))
+                })?);
+            }
+        }
+        args.extend(self.args.iter().cloned());
+        for arg in &args {
+            check_nul("argument", arg)?;
+        }
+
+        let mut envs: Vec<(String, String)> = vec![];
+        if self.inherit_env {
+            for (key, value) in std::env::vars_os() {
+                match (key.into_string(), value.into_string()) {
+                    (Ok(key), Ok(value)) => {
+                        if !self.envs.iter().any(|(k, _)| *k == key) {
+                            envs.push((key, value));
+                        }
+                    }
+                    (key, _) => {
+                        return Err(invalid(format!(
+                            "host environment variable {:?} is not valid UTF-8",
+                            key.unwrap_or_else(|k| k.to_string_lossy().into_owned())
+                        )))
+                    }
+                }
+            }
+        }
+        envs.extend(self.envs.iter().cloned());
+        for (key, value) in &envs {
+            if key.is_empty() || key.contains('=') {
+                return Err(invalid(format!(
+                    "invalid environment variable name {:?}",
+                    key
+                )));
+            }
+            check_nul("environment variable", key)?;
+            check_nul("environment variable", value)?;
+        }
+
+        for preopen in &self.preopens {
+            check_guest(&preopen.guest)?;
+            match preopen.host.to_str() {
+                Some(host) => check_nul("host path", host)?,
+                None => {
+                    return Err(invalid(format!(
+                        "host path {:?} is not valid UTF-8",
+                        preopen.host
+                    )))
+                }
+            }
+            if !preopen.host.is_dir() {
+                return Err(invalid(format!(
+                    "preopened directory {:?} does not exist",
+                    preopen.host
+                )));
+            }
+        }
+
+        #[cfg(feature = "async-wasi")]
+        for (guest, _) in &self.mounts {
+            check_guest(guest)?;
+        }
+
+        Ok(ResolvedWasi {
+            args,
+            envs,
+            preopens: self.preopens.clone(),
+            #[cfg(feature = "async-wasi")]
+            mounts: self.mounts.clone(),
+            stdin: self.stdin.clone(),
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+        })
+    }
+}
+
+/// A validated [`WasiConfig`].
+#[derive(Debug, Clone)]
+pub(crate) struct ResolvedWasi {
+    pub(crate) args: Vec<String>,
+    pub(crate) envs: Vec<(String, String)>,
+    pub(crate) preopens: Vec<Preopen>,
+    #[cfg(feature = "async-wasi")]
+    pub(crate) mounts: Vec<(String, Vfs)>,
+    pub(crate) stdin: InputSource,
+    pub(crate) stdout: OutputSink,
+    pub(crate) stderr: OutputSink,
+}
+
+impl ResolvedWasi {
+    /// Returns the guest paths of the preopened directories, in the order of their fds.
+    pub(crate) fn preopen_names(&self) -> Vec<String> {
+        let names = self.preopens.iter().map(|p| p.guest.clone());
+        #[cfg(feature = "async-wasi")]
+        let names = names.chain(self.mounts.iter().map(|(guest, _)| guest.clone()));
+        names.collect()
+    }
+
+    /// Returns the args, `KEY=VALUE` envs and `guest:host` preopens expected by
+    /// `WasmEdge_ModuleInstanceCreateWASI`.
+    ///
+    /// The WasmEdge WASI module cannot restrict a preopen to reads, so read-only
+    /// preopens are refused rather than granted write access, and so are file trees.
+    pub(crate) fn to_native(&self) -> WasmEdgeResult<[Vec<CString>; 3]> {
+        #[cfg(feature = "async-wasi")]
+        if let Some((guest, _)) = self.mounts.first() {
+            return Err(invalid(format!(
+                "file tree mounted at {:?} is not supported by the WasmEdge WASI module",
+                guest
+            )));
+        }
+        if let Some(preopen) = self.preopens.iter().find(|p| p.read_only) {
+            return Err(invalid(format!(
+                "read-only preopen {:?} is not supported by the WasmEdge WASI module",
+                preopen.guest
+            )));
+        }
+
+        let args = self
+            .args
+            .iter()
+            .map(|arg| CString::new(arg.as_str()))
+            .collect::<Result<_, _>>()?;
+        let envs = self
+            .envs
+            .iter()
+            .map(|(key, value)| CString::new(format!("{}={}", key, value)))
+            .collect::<Result<_, _>>()?;
+        let preopens = self
+            .preopens
+            .iter()
+            .map(|p| CString::new(format!("{}:{}", p.guest, p.host.to_string_lossy())))
+            .collect::<Result<_, _>>()?;
+        Ok([args, envs, preopens])
+    }
+}
+
+fn check_guest(guest: &str) -> WasmEdgeResult<()> {
+    if guest.is_empty() || guest.contains(':') {
+        return Err(invalid(format!("invalid guest path {:?}", guest)));
+    }
+    check_nul("guest path", guest)
+}
+
+fn check_nul(what: &str, s: &str) -> WasmEdgeResult<()> {
+    if s.contains('\0') {
+        return Err(invalid(format!("{} {:?} contains a NUL byte", what, s)));
+    }
+    Ok(())
+}
+
+fn invalid(msg: String) -> WasmEdgeError {
+    WasmEdgeError::Operation(format!("invalid WASI config: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused(config: WasiConfig, what: &str) {
+        match config.resolve() {
+            Ok(_) => panic!("{} was accepted", what),
+            Err(e) => assert!(e.to_string().contains(what), "{}: {}", what, e),
+        }
+    }
+
+    #[test]
+    fn resolve_refuses_nul_bytes() {
+        let dir = std::env::temp_dir();
+        refused(WasiConfig::new().arg("a\0b"), "argument");
+        refused(WasiConfig::new().env("A\0", "1"), "environment variable");
+        refused(WasiConfig::new().env("A", "1\0"), "environment variable");
+        refused(WasiConfig::new().preopen("/d\0", &dir), "guest path");
+        refused(
+            WasiConfig::new().preopen("/d", dir.join("a\0b")),
+            "host path",
+        );
+    }
+
+    #[test]
+    fn resolve_refuses_malformed_entries() {
+        let dir = std::env::temp_dir();
+        refused(
+            WasiConfig::new().env("A=B", "1"),
+            "environment variable name",
+        );
+        refused(WasiConfig::new().env("", "1"), "environment variable name");
+        refused(WasiConfig::new().preopen("/a:b", &dir), "guest path");
+        refused(WasiConfig::new().preopen("", &dir), "guest path");
+        refused(
+            WasiConfig::new().preopen("/missing", dir.join("wasmedge-asyncify-missing")),
+            "does not exist",
+        );
+        #[cfg(feature = "async-wasi")]
+        refused(
+            WasiConfig::new().preopen_vfs("/a:b", Vfs::memory()),
+            "guest path",
+        );
+    }
+
+    #[test]
+    fn env_replaces_earlier_values() {
+        let config = WasiConfig::new()
+            .env("A", "1")
+            .envs([("B", "2"), ("A", "3")]);
+        let resolved = config.resolve().unwrap();
+        assert_eq!(
+            resolved.envs,
+            [
+                ("B".to_string(), "2".to_string()),
+                ("A".to_string(), "3".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_envs_override_inherited_ones() {
+        const KEY: &str = "WASMEDGE_ASYNCIFY_RESOLVE_TEST";
+        std::env::set_var(KEY, "host");
+        let inherited = WasiConfig::new().inherit_env().resolve().unwrap();
+        let overridden = WasiConfig::new()
+            .env(KEY, "guest")
+            .inherit_env()
+            .resolve()
+            .unwrap();
+        std::env::remove_var(KEY);
+
+        let values = |resolved: &ResolvedWasi| -> Vec<String> {
+            resolved
+                .envs
+                .iter()
+                .filter(|(key, _)| key == KEY)
+                .map(|(_, value)| value.clone())
+                .collect()
+        };
+        assert_eq!(values(&inherited), ["host"]);
+        assert_eq!(values(&overridden), ["guest"]);
+        // explicit variables come after the inherited ones
+        assert_eq!(overridden.envs.last().unwrap().0, KEY);
+    }
+
+    #[test]
+    fn native_config_refuses_read_only_preopens() {
+        let dir = std::env::temp_dir();
+        let resolved = WasiConfig::new()
+            .arg("prog")
+            .env("A", "1")
+            .preopen("/tmp", &dir)
+            .resolve()
+            .unwrap();
+        let [args, envs, preopens] = resolved.to_native().unwrap();
+        assert_eq!(args, [CString::new("prog").unwrap()]);
+        assert_eq!(envs, [CString::new("A=1").unwrap()]);
+        assert_eq!(
+            preopens,
+            [CString::new(format!("/tmp:{}", dir.to_string_lossy())).unwrap()]
+        );
+
+        let resolved = WasiConfig::new()
+            .preopen_read_only("/tmp", &dir)
+            .resolve()
+            .unwrap();
+        assert!(resolved.to_native().is_err());
+    }
+}
@@ -0,0 +1,697 @@
+//! An async `poll_oneoff`, suspending the guest until a subscription fires.
+//!
+//! Clock subscriptions are woken by a timer thread shared by all linkers, so they need
+//! no runtime timer. Reads of captured stdin wait for the input source, sockets for the
+//! readiness of the host socket and, on unix, the host's stdin, stdout and stderr for
+//! the readiness of the host fd on a poller thread shared by all linkers, so a guest
+//! sleeping or waiting for input no longer blocks the thread. Files of the async WASI
+//! module are always ready; the other fds of the WasmEdge WASI module cannot be waited
+//! for and fail with `ENOTSUP`.
+
+use std::{
+    future::Future,
+    io, mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex, OnceLock,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    core::types::WasmVal,
+    sdk::{
+        instance::function::SendResultFuture,
+        linker::{AsyncLinker, MAIN_MEMORY},
+    },
+};
+
+use super::{
+    preview1::abi::{
+        errno, write_bytes, write_u32, Errno, WasiResult, CLOCK_MONOTONIC, CLOCK_PROCESS_CPUTIME,
+        CLOCK_REALTIME, CLOCK_THREAD_CPUTIME,
+    },
+    stdio::i32_args,
+};
+
+const EVENTTYPE_CLOCK: u8 = 0;
+const EVENTTYPE_FD_READ: u8 = 1;
+const EVENTTYPE_FD_WRITE: u8 = 2;
+
+const SUBCLOCKFLAGS_ABSTIME: u16 = 1;
+const EVENTRWFLAGS_HANGUP: u16 = 1;
+
+const SUBSCRIPTION_SIZE: usize = 48;
+const EVENT_SIZE: usize = 32;
+
+/// Whether an fd subscription can fire.
+pub(crate) enum Readiness {
+    /// Ready now, with this many bytes to read.
+    Ready(u64),
+    /// Ready once the stdin source has data or reaches end of file.
+    Stdin,
+    /// Ready once the socket with this fd can be read from or written to.
+    #[cfg(feature = "async-socket")]
+    Socket(u32),
+    /// Ready once the host fd with this number can be read from or written to.
+    #[cfg(unix)]
+    Host(u32),
+    /// Fails with this errno.
+    Error(Errno),
+}
+
+enum Kind {
+    /// When the clock fires, or the errno of an unsupported clock.
+    Clock(Result<Instant, Errno>),
+    Fd(u8, Readiness),
+}
+
+struct Subscription {
+    userdata: u64,
+    kind: Kind,
+}
+
+struct Event {
+    userdata: u64,
+    error: Errno,
+    ty: u8,
+    nbytes: u64,
+    hangup: bool,
+}
+
+impl Event {
+    fn new(userdata: u64, ty: u8) -> Self {
+        Event {
+            userdata,
+            error: Errno::SUCCESS,
+            ty,
+            nbytes: 0,
+            hangup: false,
+        }
+    }
+
+    fn encode(&self) -> [u8; EVENT_SIZE] {
+        let mut raw = [0; EVENT_SIZE];
+        raw[0..8].copy_from_slice(&self.userdata.to_le_bytes());
+        raw[8..10].copy_from_slice(&self.error.0.to_le_bytes());
+        raw[10] = self.ty;
+        raw[16..24].copy_from_slice(&self.nbytes.to_le_bytes());
+        if self.hangup {
+            raw[24..26].copy_from_slice(&EVENTRWFLAGS_HANGUP.to_le_bytes());
+        }
+        raw
+    }
+}
+
+fn u16_at(raw: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([raw[at], raw[at + 1]])
+}
+
+fn u32_at(raw: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([raw[at], raw[at + 1], raw[at + 2], raw[at + 3]])
+}
+
+fn u64_at(raw: &[u8], at: usize) -> u64 {
+    let mut b = [0; 8];
+    b.copy_from_slice(&raw[at..at + 8]);
+    u64::from_le_bytes(b)
+}
+
+/// The instant the monotonic clock of the guest counts from, if it is known.
+fn monotonic_base(_linker: &AsyncLinker) -> Option<Instant> {
+    #[cfg(feature = "async-wasi")]
+    if let Some(wasi) = &_linker.wasi {
+        return Some(wasi.start);
+    }
+    None
+}
+
+fn clock_deadline(linker: &AsyncLinker, raw: &[u8]) -> Result<Instant, Errno> {
+    let id = u32_at(raw, 16);
+    let timeout = u64_at(raw, 24);
+    let flags = u16_at(raw, 40);
+    let now = Instant::now();
+
+    if flags & SUBCLOCKFLAGS_ABSTIME == 0 {
+        return match id {
+            CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_PROCESS_CPUTIME | CLOCK_THREAD_CPUTIME => {
+                Ok(now + Duration::from_nanos(timeout))
+            }
+            _ => Err(Errno::INVAL),
+        };
+    }
+    match id {
+        CLOCK_REALTIME => {
+            let at = UNIX_EPOCH + Duration::from_nanos(timeout);
+            Ok(now + at.duration_since(SystemTime::now()).unwrap_or_default())
+        }
+        // the WasmEdge WASI module does not tell its monotonic clock's origin
+        CLOCK_MONOTONIC => monotonic_base(linker)
+            .map(|base| base + Duration::from_nanos(timeout))
+            .ok_or(Errno::NOTSUP),
+        CLOCK_PROCESS_CPUTIME | CLOCK_THREAD_CPUTIME => Err(Errno::NOTSUP),
+        _ => Err(Errno::INVAL),
+    }
+}
+
+fn readiness(linker: &AsyncLinker, fd: u32, read: bool) -> Readiness {
+    #[cfg(feature = "async-socket")]
+    if matches!(&linker.sockets, Some(sockets) if sockets.contains(fd)) {
+        return Readiness::Socket(fd);
+    }
+    #[cfg(feature = "async-wasi")]
+    if let Some(readiness) = super::preview1::readiness(linker, fd, read) {
+        return readiness;
+    }
+    // every other fd belongs to the WasmEdge WASI module, where 0 to 2 are the host's
+    let captured = match fd {
+        0 => linker.stdio.captures_input(),
+        _ => linker.stdio.captured_output_fds().contains(&(fd as i32)),
+    };
+    match fd {
+        0 if read && captured => Readiness::Stdin,
+        0..=2 if captured => Readiness::Ready(0),
+        #[cfg(unix)]
+        0..=2 => Readiness::Host(fd),
+        // its files, sockets and pipes are out of reach of this module
+        _ => Readiness::Error(Errno::NOTSUP),
+    }
+}
+
+fn read_subscriptions(linker: &AsyncLinker, ptr: usize, n: usize) -> WasiResult<Vec<Subscription>> {
+    let size = n.checked_mul(SUBSCRIPTION_SIZE).ok_or(Errno::FAULT)?;
+    let raw = linker.get_memory(MAIN_MEMORY, ptr, size)?.to_vec();
+    raw.chunks_exact(SUBSCRIPTION_SIZE)
+        .map(|raw| {
+            let kind = match raw[8] {
+                EVENTTYPE_CLOCK => Kind::Clock(clock_deadline(linker, raw)),
+                ty @ (EVENTTYPE_FD_READ | EVENTTYPE_FD_WRITE) => Kind::Fd(
+                    ty,
+                    readiness(linker, u32_at(raw, 16), ty == EVENTTYPE_FD_READ),
+                ),
+                _ => return Err(Errno::INVAL),
+            };
+            Ok(Subscription {
+                userdata: u64_at(raw, 0),
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// What waiting for the fd subscriptions found.
+#[derive(Default)]
+struct Woken {
+    /// The outcome of waiting for stdin, if it was waited for.
+    stdin: Option<std::io::Result<usize>>,
+    /// The indices of the socket subscriptions found ready.
+    #[cfg(feature = "async-socket")]
+    sockets: Vec<usize>,
+    /// The indices of the host fd subscriptions found ready.
+    #[cfg(unix)]
+    host: Vec<usize>,
+}
+
+impl Woken {
+    fn is_empty(&self) -> bool {
+        #[cfg(feature = "async-socket")]
+        if !self.sockets.is_empty() {
+            return false;
+        }
+        #[cfg(unix)]
+        if !self.host.is_empty() {
+            return false;
+        }
+        self.stdin.is_none()
+    }
+}
+
+/// Returns the events of the subscriptions that fired by `now`, or were found ready in
+/// `woken`.
+fn fired(subs: &[Subscription], now: Instant, woken: &Woken) -> Vec<Event> {
+    let mut events = vec![];
+    for (_idx, sub) in subs.iter().enumerate() {
+        let mut event = match &sub.kind {
+            Kind::Clock(_) => Event::new(sub.userdata, EVENTTYPE_CLOCK),
+            Kind::Fd(ty, _) => Event::new(sub.userdata, *ty),
+        };
+        match &sub.kind {
+            Kind::Clock(Ok(deadline)) if *deadline <= now => {}
+            Kind::Clock(Ok(_)) => continue,
+            Kind::Clock(Err(errno)) | Kind::Fd(_, Readiness::Error(errno)) => event.error = *errno,
+            Kind::Fd(_, Readiness::Ready(nbytes)) => event.nbytes = *nbytes,
+            Kind::Fd(_, Readiness::Stdin) => match &woken.stdin {
+                Some(Ok(n)) => {
+                    event.nbytes = *n as u64;
+                    event.hangup = *n == 0;
+                }
+                Some(Err(_)) => event.error = Errno::IO,
+                None => continue,
+            },
+            #[cfg(feature = "async-socket")]
+            Kind::Fd(_, Readiness::Socket(_)) if woken.sockets.contains(&_idx) => {}
+            #[cfg(feature = "async-socket")]
+            Kind::Fd(_, Readiness::Socket(_)) => continue,
+            #[cfg(unix)]
+            Kind::Fd(_, Readiness::Host(_)) if woken.host.contains(&_idx) => {}
+            #[cfg(unix)]
+            Kind::Fd(_, Readiness::Host(_)) => continue,
+        }
+        events.push(event);
+    }
+    events
+}
+
+/// Hands out the ids of [`SleepUntil`] and [`HostFds`] registrations.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The `(id, deadline, waker)` of pending [`SleepUntil`]s, woken by one thread.
+struct Timers {
+    pending: Mutex<Vec<(u64, Instant, Waker)>>,
+    changed: Condvar,
+}
+
+static TIMERS: Timers = Timers {
+    pending: Mutex::new(Vec::new()),
+    changed: Condvar::new(),
+};
+
+fn run_timers() {
+    let mut pending = TIMERS.pending.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        let (due, later): (Vec<_>, Vec<_>) = mem::take(&mut *pending)
+            .into_iter()
+            .partition(|(_, deadline, _)| *deadline <= now);
+        *pending = later;
+        if !due.is_empty() {
+            // a waker may poll right away, so it must not find the lock held
+            drop(pending);
+            due.into_iter().for_each(|(_, _, waker)| waker.wake());
+            pending = TIMERS.pending.lock().unwrap();
+            continue;
+        }
+        pending = match pending.iter().map(|(_, deadline, _)| *deadline).min() {
+            Some(next) => TIMERS.changed.wait_timeout(pending, next - now).unwrap().0,
+            None => TIMERS.changed.wait(pending).unwrap(),
+        };
+    }
+}
+
+/// A timer that works on any executor, unlike `tokio`'s, which panic unless polled in a
+/// runtime with the time driver enabled.
+///
+/// It keeps at most one registration with the timer thread, updated when it is polled
+/// again and removed when it is dropped.
+pub(crate) struct SleepUntil {
+    deadline: Instant,
+    id: u64,
+}
+
+pub(crate) fn sleep_until(deadline: Instant) -> io::Result<SleepUntil> {
+    static STARTED: OnceLock<bool> = OnceLock::new();
+    let started = STARTED.get_or_init(|| {
+        thread::Builder::new()
+            .name("wasmedge-poll-timer".to_string())
+            .spawn(run_timers)
+            .is_ok()
+    });
+    match *started {
+        true => Ok(SleepUntil {
+            deadline,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }),
+        false => Err(io::Error::other("cannot start the timer thread")),
+    }
+}
+
+impl Future for SleepUntil {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let mut pending = TIMERS.pending.lock().unwrap();
+        match pending.iter_mut().find(|(id, ..)| *id == self.id) {
+            Some((_, _, waker)) => {
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                pending.push((self.id, self.deadline, cx.waker().clone()));
+                TIMERS.changed.notify_one();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for SleepUntil {
+    fn drop(&mut self) {
+        let mut pending = TIMERS.pending.lock().unwrap();
+        if let Some(pos) = pending.iter().position(|(id, ..)| *id == self.id) {
+            pending.swap_remove(pos);
+        }
+    }
+}
+
+/// A wait of [`HostFds`] for some of the host fds.
+#[cfg(unix)]
+struct HostWait {
+    id: u64,
+    /// The `(index, fd, read)` of each subscription.
+    fds: Vec<(usize, u32, bool)>,
+    /// The indices of the subscriptions found ready.
+    ready: Option<Vec<usize>>,
+    waker: Waker,
+}
+
+/// The pending [`HostFds`] waits, polled by one thread.
+#[cfg(unix)]
+static HOST_WAITS: Mutex<Vec<HostWait>> = Mutex::new(Vec::new());
+
+/// Wakes the poller thread to pick up a change of [`HOST_WAITS`], or `None` if the
+/// thread could not be started.
+#[cfg(unix)]
+fn poller() -> Option<&'static std::os::unix::net::UnixStream> {
+    use std::os::unix::net::UnixStream;
+
+    static NOTIFY: OnceLock<Option<UnixStream>> = OnceLock::new();
+    NOTIFY
+        .get_or_init(|| {
+            let (notify, woken) = UnixStream::pair().ok()?;
+            notify.set_nonblocking(true).ok()?;
+            woken.set_nonblocking(true).ok()?;
+            thread::Builder::new()
+                .name("wasmedge-poll-fds".to_string())
+                .spawn(move || run_poller(woken))
+                .ok()?;
+            Some(notify)
+        })
+        .as_ref()
+}
+
+#[cfg(unix)]
+fn notify_poller() {
+    use std::io::Write;
+
+    if let Some(mut notify) = poller() {
+        // a full buffer already has the thread woken
+        let _ = notify.write(&[0]);
+    }
+}
+
+#[cfg(unix)]
+fn run_poller(mut woken: std::os::unix::net::UnixStream) {
+    use std::{io::Read, os::fd::AsRawFd};
+
+    loop {
+        let mut pollfds = vec![libc::pollfd {
+            fd: woken.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        let mut owners = vec![];
+        for wait in HOST_WAITS.lock().unwrap().iter() {
+            if wait.ready.is_some() {
+                continue;
+            }
+            for (idx, fd, read) in &wait.fds {
+                pollfds.push(libc::pollfd {
+                    fd: *fd as i32,
+                    events: if *read { libc::POLLIN } else { libc::POLLOUT },
+                    revents: 0,
+                });
+                owners.push((wait.id, *idx));
+            }
+        }
+
+        let n = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, -1) };
+        if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        while matches!(woken.read(&mut [0; 64]), Ok(n) if n > 0) {}
+
+        let mut wakers = vec![];
+        let mut waits = HOST_WAITS.lock().unwrap();
+        for (pollfd, (id, idx)) in pollfds[1..].iter().zip(owners) {
+            // on failure every fd is reported, for the next call to tell why
+            if n >= 0 && pollfd.revents == 0 {
+                continue;
+            }
+            if let Some(wait) = waits.iter_mut().find(|wait| wait.id == id) {
+                if wait.ready.is_none() {
+                    wakers.push(wait.waker.clone());
+                }
+                wait.ready.get_or_insert_with(Vec::new).push(idx);
+            }
+        }
+        // a waker may poll right away, so it must not find the lock held
+        drop(waits);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Waits for one of the host fds to be ready on the poller thread shared by all linkers,
+/// resolving to the indices of the ready subscriptions.
+#[cfg(unix)]
+struct HostFds {
+    id: u64,
+    fds: Vec<(usize, u32, bool)>,
+}
+
+#[cfg(unix)]
+impl HostFds {
+    /// Waits for each `(index, fd, read)` of `fds`.
+    fn new(fds: Vec<(usize, u32, bool)>) -> io::Result<Self> {
+        match poller() {
+            Some(_) => Ok(HostFds {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                fds,
+            }),
+            None => Err(io::Error::other("cannot start the poll_oneoff fd poller")),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Future for HostFds {
+    type Output = Vec<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Vec<usize>> {
+        let mut waits = HOST_WAITS.lock().unwrap();
+        let wait = match waits.iter().position(|wait| wait.id == self.id) {
+            Some(pos) => pos,
+            None => {
+                waits.push(HostWait {
+                    id: self.id,
+                    fds: self.fds.clone(),
+                    ready: None,
+                    waker: cx.waker().clone(),
+                });
+                drop(waits);
+                notify_poller();
+                return Poll::Pending;
+            }
+        };
+        match waits[wait].ready.is_some() {
+            true => Poll::Ready(waits.swap_remove(wait).ready.unwrap_or_default()),
+            false => {
+                if !waits[wait].waker.will_wake(cx.waker()) {
+                    waits[wait].waker = cx.waker().clone();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for HostFds {
+    fn drop(&mut self) {
+        let mut waits = HOST_WAITS.lock().unwrap();
+        if let Some(pos) = waits.iter().position(|wait| wait.id == self.id) {
+            // stops polling its fds, which the guest may close
+            if waits.swap_remove(pos).ready.is_none() {
+                drop(waits);
+                notify_poller();
+            }
+        }
+    }
+}
+
+/// Waits for an fd subscribed to in `subs` to be ready, or for `deadline`.
+async fn wait_ready(
+    linker: &mut AsyncLinker,
+    subs: &[Subscription],
+    deadline: Option<Instant>,
+) -> io::Result<Woken> {
+    let waits_stdin = subs
+        .iter()
+        .any(|sub| matches!(sub.kind, Kind::Fd(_, Readiness::Stdin)));
+    let mut stdin = waits_stdin.then(|| Box::pin(linker.stdio.stdin.ready()));
+    #[cfg(feature = "async-socket")]
+    let mut sockets = linker.sockets.as_deref_mut();
+    #[cfg(unix)]
+    let mut host = {
+        let fds = subs
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, sub)| match &sub.kind {
+                Kind::Fd(ty, Readiness::Host(fd)) => Some((idx, *fd, *ty == EVENTTYPE_FD_READ)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match fds.is_empty() {
+            true => None,
+            false => Some(HostFds::new(fds)?),
+        }
+    };
+    let mut timer = deadline.map(sleep_until).transpose()?;
+
+    let woken = std::future::poll_fn(|cx| {
+        let mut woken = Woken::default();
+        if let Some(stdin) = &mut stdin {
+            if let Poll::Ready(r) = stdin.as_mut().poll(cx) {
+                woken.stdin = Some(r);
+            }
+        }
+        #[cfg(feature = "async-socket")]
+        if let Some(sockets) = &mut sockets {
+            for (idx, sub) in subs.iter().enumerate() {
+                if let Kind::Fd(ty, Readiness::Socket(fd)) = &sub.kind {
+                    if sockets.poll_ready(*fd, *ty == EVENTTYPE_FD_READ, cx) {
+                        woken.sockets.push(idx);
+                    }
+                }
+            }
+        }
+        #[cfg(unix)]
+        if let Some(host) = &mut host {
+            if let Poll::Ready(ready) = Pin::new(host).poll(cx) {
+                woken.host = ready;
+            }
+        }
+        if !woken.is_empty() {
+            return Poll::Ready(woken);
+        }
+        match timer.as_mut().map(|timer| Pin::new(timer).poll(cx)) {
+            Some(Poll::Ready(())) => Poll::Ready(woken),
+            _ => Poll::Pending,
+        }
+    })
+    .await;
+    Ok(woken)
+}
+
+/// Waits for the first subscriptions to fire.
+async fn wait(linker: &mut AsyncLinker, subs: &[Subscription]) -> io::Result<Vec<Event>> {
+    let events = fired(subs, Instant::now(), &Woken::default());
+    if !events.is_empty() {
+        return Ok(events);
+    }
+
+    let deadline = subs
+        .iter()
+        .filter_map(|sub| match &sub.kind {
+            Kind::Clock(Ok(deadline)) => Some(*deadline),
+            _ => None,
+        })
+        .min();
+    let woken = wait_ready(linker, subs, deadline).await?;
+    Ok(fired(subs, Instant::now(), &woken))
+}
+
+/// `poll_oneoff(in, out, nsubscriptions, nevents) -> errno`
+pub(crate) fn poll_oneoff(linker: &mut AsyncLinker, args: Vec<WasmVal>) -> SendResultFuture<'_> {
+    Box::new(async move {
+        let [input, out, nsubs, nevents] = i32_args::<4>(&args)?;
+        errno(poll_subscriptions(linker, input, out, nsubs, nevents).await)
+    })
+}
+
+async fn poll_subscriptions(
+    linker: &mut AsyncLinker,
+    input: usize,
+    out: usize,
+    nsubs: usize,
+    nevents: usize,
+) -> WasiResult<()> {
+    if nsubs == 0 {
+        return Err(Errno::INVAL);
+    }
+    let subs = read_subscriptions(linker, input, nsubs)?;
+    let events = wait(linker, &subs).await.map_err(|_| Errno::IO)?;
+
+    let raw = events.iter().flat_map(Event::encode).collect::<Vec<u8>>();
+    write_bytes(linker, out, &raw)?;
+    write_u32(linker, nevents, events.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registrations(timer: &SleepUntil) -> usize {
+        let pending = TIMERS.pending.lock().unwrap();
+        pending.iter().filter(|(id, ..)| *id == timer.id).count()
+    }
+
+    #[test]
+    fn a_timer_keeps_one_registration_until_dropped() {
+        let waker = waker_fn::waker_fn(|| {});
+        let mut cx = Context::from_waker(&waker);
+        let mut timer = sleep_until(Instant::now() + Duration::from_secs(60)).unwrap();
+
+        for _ in 0..3 {
+            assert!(Pin::new(&mut timer).poll(&mut cx).is_pending());
+        }
+        assert_eq!(registrations(&timer), 1);
+
+        let id = timer.id;
+        drop(timer);
+        let pending = TIMERS.pending.lock().unwrap();
+        assert!(!pending.iter().any(|(other, ..)| *other == id));
+    }
+
+    #[test]
+    fn a_timer_wakes_its_task() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let waker = waker_fn::waker_fn(move || {
+            let _ = tx.send(());
+        });
+        let mut cx = Context::from_waker(&waker);
+        let mut timer = sleep_until(Instant::now() + Duration::from_millis(10)).unwrap();
+
+        assert!(Pin::new(&mut timer).poll(&mut cx).is_pending());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(Pin::new(&mut timer).poll(&mut cx).is_ready());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn host_fds_resolve_to_the_ready_subscriptions() {
+        use std::{
+            io::Write,
+            os::{fd::AsRawFd, unix::net::UnixStream},
+        };
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let waker = waker_fn::waker_fn(move || {
+            let _ = tx.send(());
+        });
+        let mut cx = Context::from_waker(&waker);
+        let (mut a, b) = UnixStream::pair().unwrap();
+        let mut wait = HostFds::new(vec![(3, b.as_raw_fd() as u32, true)]).unwrap();
+
+        assert!(Pin::new(&mut wait).poll(&mut cx).is_pending());
+        a.write_all(b"x").unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(Pin::new(&mut wait).poll(&mut cx), Poll::Ready(vec![3]));
+    }
+}
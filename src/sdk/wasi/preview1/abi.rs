@@ -0,0 +1,327 @@
+//! WASI preview1 constants, errno values and guest memory access.
+//!
+//! Without `async-wasi` only the parts shared with the stdio and `poll_oneoff`
+//! replacements of the WasmEdge WASI module are used, and some errno values and
+//! helpers are only used by the socket and process modules.
+#![cfg_attr(
+    not(all(
+        feature = "async-wasi",
+        feature = "async-socket",
+        feature = "async-process"
+    )),
+    allow(dead_code)
+)]
+
+use std::io;
+
+use wasmedge_types::error::WasmEdgeError;
+
+use crate::{
+    core::types::WasmVal,
+    sdk::{
+        error::HostResult,
+        linker::{AsyncLinker, MAIN_MEMORY},
+    },
+};
+
+#[cfg(feature = "async-wasi")]
+use crate::sdk::wasi::vfs::{FileType, FsError, Stat};
+
+/// A WASI errno value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Errno(pub(crate) u16);
+
+impl Errno {
+    pub(crate) const SUCCESS: Errno = Errno(0);
+    pub(crate) const ACCES: Errno = Errno(2);
+    pub(crate) const ADDRINUSE: Errno = Errno(3);
+    pub(crate) const ADDRNOTAVAIL: Errno = Errno(4);
+    pub(crate) const AGAIN: Errno = Errno(6);
+    pub(crate) const BADF: Errno = Errno(8);
+    pub(crate) const BUSY: Errno = Errno(10);
+    pub(crate) const CONNABORTED: Errno = Errno(13);
+    pub(crate) const CONNREFUSED: Errno = Errno(14);
+    pub(crate) const CONNRESET: Errno = Errno(15);
+    pub(crate) const EXIST: Errno = Errno(20);
+    pub(crate) const FAULT: Errno = Errno(21);
+    pub(crate) const FBIG: Errno = Errno(22);
+    pub(crate) const INVAL: Errno = Errno(28);
+    pub(crate) const IO: Errno = Errno(29);
+    pub(crate) const ISCONN: Errno = Errno(30);
+    pub(crate) const ISDIR: Errno = Errno(31);
+    pub(crate) const LOOP: Errno = Errno(32);
+    pub(crate) const MFILE: Errno = Errno(33);
+    pub(crate) const NAMETOOLONG: Errno = Errno(37);
+    pub(crate) const NOENT: Errno = Errno(44);
+    pub(crate) const NOSYS: Errno = Errno(52);
+    pub(crate) const NOTCONN: Errno = Errno(53);
+    pub(crate) const NOTDIR: Errno = Errno(54);
+    pub(crate) const NOTEMPTY: Errno = Errno(55);
+    pub(crate) const NOTSUP: Errno = Errno(58);
+    pub(crate) const PIPE: Errno = Errno(64);
+    pub(crate) const ROFS: Errno = Errno(69);
+    pub(crate) const SPIPE: Errno = Errno(70);
+    pub(crate) const TIMEDOUT: Errno = Errno(73);
+    pub(crate) const XDEV: Errno = Errno(75);
+    pub(crate) const NOTCAPABLE: Errno = Errno(76);
+}
+
+impl From<io::Error> for Errno {
+    fn from(e: io::Error) -> Self {
+        #[cfg(feature = "async-wasi")]
+        match FsError::of(&e) {
+            Some(FsError::NotADirectory) => return Errno::NOTDIR,
+            Some(FsError::IsADirectory) => return Errno::ISDIR,
+            Some(FsError::DirectoryNotEmpty) => return Errno::NOTEMPTY,
+            Some(FsError::NotALink) => return Errno::INVAL,
+            Some(FsError::ReadOnly) => return Errno::ROFS,
+            Some(FsError::Busy) => return Errno::BUSY,
+            Some(FsError::Escapes) => return Errno::NOTCAPABLE,
+            Some(FsError::Loop) => return Errno::LOOP,
+            Some(FsError::Unsupported) => return Errno::NOTSUP,
+            None => {}
+        }
+        #[cfg(target_os = "linux")]
+        match e.raw_os_error() {
+            Some(20) => return Errno::NOTDIR,
+            Some(21) => return Errno::ISDIR,
+            Some(36) => return Errno::NAMETOOLONG,
+            Some(39) => return Errno::NOTEMPTY,
+            Some(30) => return Errno::ROFS,
+            Some(40) => return Errno::LOOP,
+            _ => {}
+        }
+        match e.kind() {
+            io::ErrorKind::NotFound => Errno::NOENT,
+            io::ErrorKind::PermissionDenied => Errno::ACCES,
+            io::ErrorKind::AlreadyExists => Errno::EXIST,
+            io::ErrorKind::InvalidInput => Errno::INVAL,
+            io::ErrorKind::WouldBlock => Errno::AGAIN,
+            io::ErrorKind::Unsupported => Errno::NOTSUP,
+            io::ErrorKind::AddrInUse => Errno::ADDRINUSE,
+            io::ErrorKind::AddrNotAvailable => Errno::ADDRNOTAVAIL,
+            io::ErrorKind::ConnectionAborted => Errno::CONNABORTED,
+            io::ErrorKind::ConnectionRefused => Errno::CONNREFUSED,
+            io::ErrorKind::ConnectionReset => Errno::CONNRESET,
+            io::ErrorKind::NotConnected => Errno::NOTCONN,
+            io::ErrorKind::BrokenPipe => Errno::PIPE,
+            io::ErrorKind::TimedOut => Errno::TIMEDOUT,
+            _ => Errno::IO,
+        }
+    }
+}
+
+impl From<WasmEdgeError> for Errno {
+    fn from(_: WasmEdgeError) -> Self {
+        Errno::FAULT
+    }
+}
+
+pub(crate) type WasiResult<T> = Result<T, Errno>;
+
+/// Turns the outcome of a WASI function into its errno return value.
+pub(crate) fn errno(r: WasiResult<()>) -> HostResult<Vec<WasmVal>> {
+    let code = match r {
+        Ok(()) => Errno::SUCCESS,
+        Err(e) => e,
+    };
+    Ok(vec![WasmVal::I32(code.0 as i32)])
+}
+
+pub(crate) const FILETYPE_UNKNOWN: u8 = 0;
+pub(crate) const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+pub(crate) const FILETYPE_DIRECTORY: u8 = 3;
+pub(crate) const FILETYPE_REGULAR_FILE: u8 = 4;
+pub(crate) const FILETYPE_SYMBOLIC_LINK: u8 = 7;
+
+pub(crate) const CLOCK_REALTIME: u32 = 0;
+pub(crate) const CLOCK_MONOTONIC: u32 = 1;
+pub(crate) const CLOCK_PROCESS_CPUTIME: u32 = 2;
+pub(crate) const CLOCK_THREAD_CPUTIME: u32 = 3;
+
+pub(crate) const WHENCE_SET: u32 = 0;
+pub(crate) const WHENCE_CUR: u32 = 1;
+pub(crate) const WHENCE_END: u32 = 2;
+
+pub(crate) const OFLAGS_CREAT: u32 = 1;
+pub(crate) const OFLAGS_DIRECTORY: u32 = 2;
+pub(crate) const OFLAGS_EXCL: u32 = 4;
+pub(crate) const OFLAGS_TRUNC: u32 = 8;
+
+pub(crate) const FDFLAGS_APPEND: u32 = 1;
+
+pub(crate) const LOOKUPFLAGS_SYMLINK_FOLLOW: u32 = 1;
+
+pub(crate) const RIGHTS_FD_READ: u64 = 1 << 1;
+pub(crate) const RIGHTS_FD_WRITE: u64 = 1 << 6;
+/// Every right defined by preview1; rights are not tracked beyond read and write.
+pub(crate) const RIGHTS_ALL: u64 = (1 << 29) - 1;
+
+/// Typed access to the arguments of a WASI call.
+pub(crate) struct Args<'a>(pub(crate) &'a [WasmVal]);
+
+impl Args<'_> {
+    pub(crate) fn u32(&self, idx: usize) -> WasiResult<u32> {
+        match self.0.get(idx) {
+            Some(WasmVal::I32(v)) => Ok(*v as u32),
+            _ => Err(Errno::INVAL),
+        }
+    }
+
+    pub(crate) fn u64(&self, idx: usize) -> WasiResult<u64> {
+        match self.0.get(idx) {
+            Some(WasmVal::I64(v)) => Ok(*v as u64),
+            _ => Err(Errno::INVAL),
+        }
+    }
+
+    /// An `i32` argument used as a guest address or length.
+    pub(crate) fn usize(&self, idx: usize) -> WasiResult<usize> {
+        Ok(self.u32(idx)? as usize)
+    }
+}
+
+pub(crate) fn read_bytes(linker: &AsyncLinker, ptr: usize, len: usize) -> WasiResult<Vec<u8>> {
+    Ok(linker.get_memory(MAIN_MEMORY, ptr, len)?.to_vec())
+}
+
+pub(crate) fn read_string(linker: &AsyncLinker, ptr: usize, len: usize) -> WasiResult<String> {
+    String::from_utf8(read_bytes(linker, ptr, len)?).map_err(|_| Errno::INVAL)
+}
+
+pub(crate) fn write_bytes(linker: &mut AsyncLinker, ptr: usize, data: &[u8]) -> WasiResult<()> {
+    linker
+        .get_mut_memory(MAIN_MEMORY, ptr, data.len())?
+        .copy_from_slice(data);
+    Ok(())
+}
+
+pub(crate) fn write_u32(linker: &mut AsyncLinker, ptr: usize, v: u32) -> WasiResult<()> {
+    write_bytes(linker, ptr, &v.to_le_bytes())
+}
+
+pub(crate) fn write_u64(linker: &mut AsyncLinker, ptr: usize, v: u64) -> WasiResult<()> {
+    write_bytes(linker, ptr, &v.to_le_bytes())
+}
+
+pub(crate) fn read_u32(linker: &AsyncLinker, ptr: usize) -> WasiResult<u32> {
+    let b = linker.get_memory(MAIN_MEMORY, ptr, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// The most bytes a single read hands to the guest, so that the buffer allocated for it
+/// does not grow with the lengths the guest asks for.
+pub(crate) const MAX_READ: usize = 64 * 1024;
+
+/// Reads the `(buf, len)` iovecs at `iovs`, each checked to lie inside of guest memory.
+pub(crate) fn read_iovecs(
+    linker: &AsyncLinker,
+    iovs: usize,
+    iovs_len: usize,
+) -> WasiResult<Vec<(usize, usize)>> {
+    let size = iovs_len.checked_mul(8).ok_or(Errno::FAULT)?;
+    let raw = linker.get_memory(MAIN_MEMORY, iovs, size)?;
+    let mut r = Vec::with_capacity(iovs_len);
+    for iov in raw.chunks_exact(8) {
+        let buf = u32::from_le_bytes([iov[0], iov[1], iov[2], iov[3]]) as usize;
+        let len = u32::from_le_bytes([iov[4], iov[5], iov[6], iov[7]]) as usize;
+        linker.get_memory(MAIN_MEMORY, buf, len)?;
+        r.push((buf, len));
+    }
+    Ok(r)
+}
+
+/// Returns how many bytes a read into `iovecs` should ask for, at most [`MAX_READ`].
+pub(crate) fn read_len(iovecs: &[(usize, usize)]) -> usize {
+    iovecs
+        .iter()
+        .fold(0usize, |sum, (_, len)| sum.saturating_add(*len))
+        .min(MAX_READ)
+}
+
+/// Returns the first `iovecs`, cut to hold at most `max` bytes, for a write that may be
+/// short.
+pub(crate) fn truncate_iovecs(iovecs: &[(usize, usize)], mut max: usize) -> Vec<(usize, usize)> {
+    let mut r = vec![];
+    for (buf, len) in iovecs {
+        if max == 0 {
+            break;
+        }
+        let n = (*len).min(max);
+        r.push((*buf, n));
+        max -= n;
+    }
+    r
+}
+
+/// Concatenates the guest buffers described by `iovecs`.
+pub(crate) fn gather(linker: &AsyncLinker, iovecs: &[(usize, usize)]) -> WasiResult<Vec<u8>> {
+    let mut data = vec![];
+    for (buf, len) in iovecs {
+        data.extend_from_slice(linker.get_memory(MAIN_MEMORY, *buf, *len)?);
+    }
+    Ok(data)
+}
+
+/// Spreads `data` over the guest buffers described by `iovecs`.
+pub(crate) fn scatter(
+    linker: &mut AsyncLinker,
+    iovecs: &[(usize, usize)],
+    mut data: &[u8],
+) -> WasiResult<()> {
+    for (buf, len) in iovecs {
+        if data.is_empty() {
+            break;
+        }
+        let n = (*len).min(data.len());
+        write_bytes(linker, *buf, &data[..n])?;
+        data = &data[n..];
+    }
+    Ok(())
+}
+
+#[cfg(feature = "async-wasi")]
+pub(crate) fn filetype(ty: FileType) -> u8 {
+    match ty {
+        FileType::Unknown => FILETYPE_UNKNOWN,
+        FileType::CharacterDevice => FILETYPE_CHARACTER_DEVICE,
+        FileType::Directory => FILETYPE_DIRECTORY,
+        FileType::RegularFile => FILETYPE_REGULAR_FILE,
+        FileType::SymbolicLink => FILETYPE_SYMBOLIC_LINK,
+    }
+}
+
+/// Writes `stat` as a `filestat` record at `ptr`.
+#[cfg(feature = "async-wasi")]
+pub(crate) fn write_filestat(linker: &mut AsyncLinker, ptr: usize, stat: &Stat) -> WasiResult<()> {
+    let mut buf = [0u8; 64];
+    buf[0..8].copy_from_slice(&stat.dev.to_le_bytes());
+    buf[8..16].copy_from_slice(&stat.ino.to_le_bytes());
+    buf[16] = filetype(stat.filetype);
+    buf[24..32].copy_from_slice(&stat.nlink.to_le_bytes());
+    buf[32..40].copy_from_slice(&stat.size.to_le_bytes());
+    buf[40..48].copy_from_slice(&stat.atim.to_le_bytes());
+    buf[48..56].copy_from_slice(&stat.mtim.to_le_bytes());
+    buf[56..64].copy_from_slice(&stat.ctim.to_le_bytes());
+    write_bytes(linker, ptr, &buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_ask_for_at_most_max_read() {
+        assert_eq!(read_len(&[(0, 10), (16, 6)]), 16);
+        assert_eq!(read_len(&[(0, MAX_READ), (0, 1)]), MAX_READ);
+        assert_eq!(read_len(&[(0, usize::MAX), (0, usize::MAX)]), MAX_READ);
+    }
+
+    #[test]
+    fn truncated_iovecs_hold_at_most_max_bytes() {
+        let iovecs = [(0, 10), (16, 6), (32, 4)];
+        assert_eq!(truncate_iovecs(&iovecs, 12), vec![(0, 10), (16, 2)]);
+        assert_eq!(truncate_iovecs(&iovecs, 100), iovecs.to_vec());
+        assert_eq!(truncate_iovecs(&iovecs, 0), vec![]);
+    }
+}
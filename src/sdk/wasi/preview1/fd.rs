@@ -0,0 +1,323 @@
+//! The `fd_*` functions.
+
+use std::io::SeekFrom;
+
+use tokio::io::AsyncWriteExt;
+
+use super::{
+    abi::*,
+    ctx,
+    table::{Descriptor, FileDesc},
+};
+use crate::sdk::{linker::AsyncLinker, wasi::vfs::Stat};
+
+/// What reads and writes on a descriptor go to.
+enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+    File,
+}
+
+fn stream(linker: &mut AsyncLinker, fd: u32) -> WasiResult<Stream> {
+    match ctx(linker)?.get(fd)? {
+        Descriptor::Stdin => Ok(Stream::Stdin),
+        Descriptor::Stdout => Ok(Stream::Stdout),
+        Descriptor::Stderr => Ok(Stream::Stderr),
+        Descriptor::File(_) => Ok(Stream::File),
+        Descriptor::Dir(_) => Err(Errno::ISDIR),
+    }
+}
+
+fn readable(linker: &mut AsyncLinker, fd: u32) -> WasiResult<&mut FileDesc> {
+    let file = ctx(linker)?.file_mut(fd)?;
+    if !file.read {
+        return Err(Errno::BADF);
+    }
+    Ok(file)
+}
+
+fn writable(linker: &mut AsyncLinker, fd: u32) -> WasiResult<&mut FileDesc> {
+    let file = ctx(linker)?.file_mut(fd)?;
+    if !file.write {
+        return Err(Errno::BADF);
+    }
+    Ok(file)
+}
+
+/// `fd_read(fd, iovs, iovs_len, nread) -> errno`
+pub(crate) async fn fd_read(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let iovecs = read_iovecs(linker, args.usize(1)?, args.usize(2)?)?;
+    let max = read_len(&iovecs);
+
+    let data = match stream(linker, fd)? {
+        Stream::Stdin => linker.stdio.stdin.read(max).await?,
+        Stream::Stdout | Stream::Stderr => return Err(Errno::BADF),
+        Stream::File => readable(linker, fd)?.file.read(max).await?,
+    };
+
+    scatter(linker, &iovecs, &data)?;
+    write_u32(linker, args.usize(3)?, data.len() as u32)
+}
+
+/// `fd_write(fd, iovs, iovs_len, nwritten) -> errno`
+pub(crate) async fn fd_write(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let iovecs = read_iovecs(linker, args.usize(1)?, args.usize(2)?)?;
+    let data = gather(linker, &iovecs)?;
+
+    match stream(linker, fd)? {
+        Stream::Stdin => return Err(Errno::BADF),
+        Stream::Stdout if linker.stdio.stdout.is_inherited() => {
+            let mut out = tokio::io::stdout();
+            out.write_all(&data).await?;
+            out.flush().await?;
+        }
+        Stream::Stderr if linker.stdio.stderr.is_inherited() => {
+            let mut out = tokio::io::stderr();
+            out.write_all(&data).await?;
+            out.flush().await?;
+        }
+        Stream::Stdout => linker.stdio.stdout.write(&data).await?,
+        Stream::Stderr => linker.stdio.stderr.write(&data).await?,
+        Stream::File => {
+            writable(linker, fd)?.file.write(&data).await?;
+        }
+    }
+
+    write_u32(linker, args.usize(3)?, data.len() as u32)
+}
+
+/// `fd_pread(fd, iovs, iovs_len, offset, nread) -> errno`
+pub(crate) async fn fd_pread(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let iovecs = read_iovecs(linker, args.usize(1)?, args.usize(2)?)?;
+    let offset = args.u64(3)?;
+    let max = read_len(&iovecs);
+
+    let data = readable(linker, fd)?.file.pread(offset, max).await?;
+
+    scatter(linker, &iovecs, &data)?;
+    write_u32(linker, args.usize(4)?, data.len() as u32)
+}
+
+/// `fd_pwrite(fd, iovs, iovs_len, offset, nwritten) -> errno`
+pub(crate) async fn fd_pwrite(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let iovecs = read_iovecs(linker, args.usize(1)?, args.usize(2)?)?;
+    let offset = args.u64(3)?;
+    let data = gather(linker, &iovecs)?;
+
+    writable(linker, fd)?.file.pwrite(offset, &data).await?;
+
+    write_u32(linker, args.usize(4)?, data.len() as u32)
+}
+
+/// `fd_seek(fd, offset, whence, newoffset) -> errno`
+pub(crate) async fn fd_seek(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let offset = args.u64(1)? as i64;
+    let pos = match args.u32(2)? {
+        WHENCE_SET if offset >= 0 => SeekFrom::Start(offset as u64),
+        WHENCE_CUR => SeekFrom::Current(offset),
+        WHENCE_END => SeekFrom::End(offset),
+        _ => return Err(Errno::INVAL),
+    };
+    let pos = ctx(linker)?.file_mut(fd)?.file.seek(pos).await?;
+    write_u64(linker, args.usize(3)?, pos)
+}
+
+/// `fd_tell(fd, offset) -> errno`
+pub(crate) async fn fd_tell(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let pos = ctx(linker)?
+        .file_mut(fd)?
+        .file
+        .seek(SeekFrom::Current(0))
+        .await?;
+    write_u64(linker, args.usize(1)?, pos)
+}
+
+/// `fd_close(fd) -> errno`
+pub(crate) async fn fd_close(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    // writes are flushed as they happen, so there is nothing left to wait for
+    ctx(linker)?.remove(args.u32(0)?)?;
+    Ok(())
+}
+
+/// `fd_sync(fd) -> errno`
+pub(crate) async fn fd_sync(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    match ctx(linker)?.file_mut(args.u32(0)?) {
+        Ok(file) => Ok(file.file.sync(false).await?),
+        Err(Errno::ISDIR) | Err(Errno::SPIPE) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// `fd_datasync(fd) -> errno`
+pub(crate) async fn fd_datasync(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    match ctx(linker)?.file_mut(args.u32(0)?) {
+        Ok(file) => Ok(file.file.sync(true).await?),
+        Err(Errno::ISDIR) | Err(Errno::SPIPE) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// `fd_fdstat_get(fd, stat) -> errno`
+pub(crate) fn fd_fdstat_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let (filetype, flags, rights) = match ctx(linker)?.get(args.u32(0)?)? {
+        Descriptor::Stdin => (FILETYPE_CHARACTER_DEVICE, 0, RIGHTS_ALL & !RIGHTS_FD_WRITE),
+        Descriptor::Stdout | Descriptor::Stderr => {
+            (FILETYPE_CHARACTER_DEVICE, 0, RIGHTS_ALL & !RIGHTS_FD_READ)
+        }
+        Descriptor::Dir(_) => (
+            FILETYPE_DIRECTORY,
+            0,
+            RIGHTS_ALL & !(RIGHTS_FD_READ | RIGHTS_FD_WRITE),
+        ),
+        Descriptor::File(file) => {
+            let mut rights = RIGHTS_ALL;
+            if !file.read {
+                rights &= !RIGHTS_FD_READ;
+            }
+            if !file.write {
+                rights &= !RIGHTS_FD_WRITE;
+            }
+            let flags = if file.append {
+                FDFLAGS_APPEND as u16
+            } else {
+                0
+            };
+            (FILETYPE_REGULAR_FILE, flags, rights)
+        }
+    };
+
+    let mut stat = [0u8; 24];
+    stat[0] = filetype;
+    stat[2..4].copy_from_slice(&flags.to_le_bytes());
+    stat[8..16].copy_from_slice(&rights.to_le_bytes());
+    stat[16..24].copy_from_slice(&RIGHTS_ALL.to_le_bytes());
+    write_bytes(linker, args.usize(1)?, &stat)
+}
+
+/// `fd_fdstat_set_flags(fd, flags) -> errno`
+///
+/// Flags are fixed when a file is opened; only setting the current ones succeeds.
+pub(crate) fn fd_fdstat_set_flags(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let flags = args.u32(1)?;
+    let current = match ctx(linker)?.get(args.u32(0)?)? {
+        Descriptor::File(file) if file.append => FDFLAGS_APPEND,
+        _ => 0,
+    };
+    if flags != current {
+        return Err(Errno::NOTSUP);
+    }
+    Ok(())
+}
+
+/// `fd_fdstat_set_rights(fd, base, inheriting) -> errno`
+pub(crate) fn fd_fdstat_set_rights(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    // rights are not tracked, so dropping them is a no-op
+    ctx(linker)?.get(args.u32(0)?)?;
+    Ok(())
+}
+
+/// `fd_filestat_get(fd, stat) -> errno`
+pub(crate) async fn fd_filestat_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let stat = match ctx(linker)?.get(args.u32(0)?)? {
+        Descriptor::Stdin | Descriptor::Stdout | Descriptor::Stderr => Stat::character_device(),
+        Descriptor::Dir(dir) => dir.fs.stat(&dir.path, true).await?,
+        Descriptor::File(file) => file.file.stat().await?,
+    };
+    write_filestat(linker, args.usize(1)?, &stat)
+}
+
+/// `fd_filestat_set_size(fd, size) -> errno`
+pub(crate) async fn fd_filestat_set_size(
+    linker: &mut AsyncLinker,
+    args: Args<'_>,
+) -> WasiResult<()> {
+    let size = args.u64(1)?;
+    Ok(writable(linker, args.u32(0)?)?.file.set_len(size).await?)
+}
+
+/// `fd_allocate(fd, offset, len) -> errno`
+pub(crate) async fn fd_allocate(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let end = args.u64(1)?.checked_add(args.u64(2)?).ok_or(Errno::INVAL)?;
+    let file = &mut writable(linker, args.u32(0)?)?.file;
+    if file.stat().await?.size < end {
+        file.set_len(end).await?;
+    }
+    Ok(())
+}
+
+/// `fd_advise(fd, offset, len, advice) -> errno`
+pub(crate) fn fd_advise(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    ctx(linker)?.file_mut(args.u32(0)?)?;
+    Ok(())
+}
+
+/// `fd_prestat_get(fd, prestat) -> errno`
+pub(crate) fn fd_prestat_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let len = match ctx(linker)?.get(args.u32(0)?)? {
+        Descriptor::Dir(dir) => dir.preopen.as_ref().ok_or(Errno::BADF)?.len(),
+        _ => return Err(Errno::BADF),
+    };
+    let mut prestat = [0u8; 8];
+    prestat[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+    write_bytes(linker, args.usize(1)?, &prestat)
+}
+
+/// `fd_prestat_dir_name(fd, path, path_len) -> errno`
+pub(crate) fn fd_prestat_dir_name(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let name = match ctx(linker)?.get(args.u32(0)?)? {
+        Descriptor::Dir(dir) => dir.preopen.clone().ok_or(Errno::BADF)?,
+        _ => return Err(Errno::BADF),
+    };
+    if args.usize(2)? < name.len() {
+        return Err(Errno::NAMETOOLONG);
+    }
+    write_bytes(linker, args.usize(1)?, name.as_bytes())
+}
+
+/// `fd_readdir(fd, buf, buf_len, cookie, bufused) -> errno`
+///
+/// The cookie is the index of the next entry, with `.` and `..` listed first.
+pub(crate) async fn fd_readdir(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let dir = ctx(linker)?.dir(args.u32(0)?)?.clone();
+    let buf_len = args.usize(2)?;
+    let cookie = args.u64(3)?;
+
+    let mut entries = vec![
+        (".".to_string(), FILETYPE_DIRECTORY, 0),
+        ("..".to_string(), FILETYPE_DIRECTORY, 0),
+    ];
+    for entry in dir.fs.read_dir(&dir.path).await? {
+        entries.push((entry.name, filetype(entry.filetype), entry.ino));
+    }
+
+    let mut out = vec![];
+    for (idx, (name, filetype, ino)) in entries.into_iter().enumerate().skip(cookie as usize) {
+        if out.len() >= buf_len {
+            break;
+        }
+        let mut dirent = [0u8; 24];
+        dirent[0..8].copy_from_slice(&(idx as u64 + 1).to_le_bytes());
+        dirent[8..16].copy_from_slice(&ino.to_le_bytes());
+        dirent[16..20].copy_from_slice(&(name.len() as u32).to_le_bytes());
+        dirent[20] = filetype;
+        out.extend_from_slice(&dirent);
+        out.extend_from_slice(name.as_bytes());
+    }
+    // a full buffer tells the guest to call again from its last complete entry
+    out.truncate(buf_len);
+
+    write_bytes(linker, args.usize(1)?, &out)?;
+    write_u32(linker, args.usize(4)?, out.len() as u32)
+}
+
+/// `fd_renumber(fd, to) -> errno`
+pub(crate) fn fd_renumber(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    ctx(linker)?.renumber(args.u32(0)?, args.u32(1)?)
+}
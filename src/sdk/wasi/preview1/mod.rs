@@ -0,0 +1,267 @@
+//! A WASI preview1 module written in Rust on top of async host functions.
+//!
+//! Unlike the WasmEdge WASI module, every call that may block (file I/O, stdio, yields)
+//! is an async import: the guest is suspended through asyncify while `tokio` does the
+//! work, so a guest reading a slow file does not block the executor. Preopens are
+//! [`Vfs`](super::vfs::Vfs) trees, so besides host directories a guest can be given
+//! trees kept in memory, and read-only preopens are enforced.
+
+pub(crate) mod abi;
+mod fd;
+mod path;
+mod table;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use wasmedge_types::{
+    ValType::{I32, I64},
+    WasmEdgeResult,
+};
+
+use crate::{
+    core::types::WasmVal,
+    sdk::{
+        error::{GuestExit, HostResult},
+        instance::function::SendResultFuture,
+        linker::{AsyncLinker, MAIN_MEMORY},
+        module::AsyncImportModuleBuilder,
+    },
+};
+
+use super::poll::{self, Readiness};
+use abi::*;
+use fd::*;
+use path::*;
+use table::Descriptor;
+pub(crate) use table::WasiCtx;
+
+fn ctx(linker: &mut AsyncLinker) -> WasiResult<&mut WasiCtx> {
+    linker.wasi.as_deref_mut().ok_or(Errno::BADF)
+}
+
+/// Returns whether `fd` can be polled for reads or writes, or `None` without the module.
+pub(crate) fn readiness(linker: &AsyncLinker, fd: u32, read: bool) -> Option<Readiness> {
+    let bad = Readiness::Error(Errno::BADF);
+    let readiness = match (linker.wasi.as_ref()?.get(fd), read) {
+        (Ok(Descriptor::Stdin), true) => Readiness::Stdin,
+        (Ok(Descriptor::Stdout | Descriptor::Stderr), false) => Readiness::Ready(0),
+        // regular files never block
+        (Ok(Descriptor::File(file)), true) if file.read => Readiness::Ready(0),
+        (Ok(Descriptor::File(file)), false) if file.write => Readiness::Ready(0),
+        _ => bad,
+    };
+    Some(readiness)
+}
+
+/// Registers `$f`, an `async fn(&mut AsyncLinker, Args) -> WasiResult<()>`, as an async
+/// import taking `$params` and returning an errno.
+macro_rules! add_async {
+    ($builder:expr, $f:ident, [$($param:expr),*]) => {{
+        fn wrapper(linker: &mut AsyncLinker, args: Vec<WasmVal>) -> SendResultFuture<'_> {
+            Box::new(async move { errno($f(linker, Args(&args)).await) })
+        }
+        $builder.add_send_async_func(stringify!($f), (vec![$($param),*], vec![I32]), wrapper)?;
+    }};
+}
+#[cfg(any(feature = "async-socket", feature = "async-process"))]
+pub(crate) use add_async;
+
+/// Registers `$f`, a `fn(&mut AsyncLinker, Args) -> WasiResult<()>`, as a sync import.
+macro_rules! add_sync {
+    ($builder:expr, $f:ident, [$($param:expr),*]) => {{
+        fn wrapper(linker: &mut AsyncLinker, args: &[WasmVal]) -> HostResult<Vec<WasmVal>> {
+            errno($f(linker, Args(args)))
+        }
+        $builder.add_func(stringify!($f), (vec![$($param),*], vec![I32]), wrapper)?;
+    }};
+}
+
+/// Adds every preview1 function to `builder`.
+pub(crate) fn add_functions(b: &mut AsyncImportModuleBuilder) -> WasmEdgeResult<()> {
+    add_sync!(b, args_get, [I32, I32]);
+    add_sync!(b, args_sizes_get, [I32, I32]);
+    add_sync!(b, environ_get, [I32, I32]);
+    add_sync!(b, environ_sizes_get, [I32, I32]);
+    add_sync!(b, clock_res_get, [I32, I32]);
+    add_sync!(b, clock_time_get, [I32, I64, I32]);
+    add_sync!(b, random_get, [I32, I32]);
+    add_sync!(b, proc_raise, [I32]);
+    add_async!(b, sched_yield, []);
+    b.add_func("proc_exit", (vec![I32], vec![]), proc_exit)?;
+
+    add_async!(b, fd_read, [I32, I32, I32, I32]);
+    add_async!(b, fd_write, [I32, I32, I32, I32]);
+    add_async!(b, fd_pread, [I32, I32, I32, I64, I32]);
+    add_async!(b, fd_pwrite, [I32, I32, I32, I64, I32]);
+    add_async!(b, fd_seek, [I32, I64, I32, I32]);
+    add_async!(b, fd_tell, [I32, I32]);
+    add_async!(b, fd_close, [I32]);
+    add_async!(b, fd_sync, [I32]);
+    add_async!(b, fd_datasync, [I32]);
+    add_async!(b, fd_filestat_get, [I32, I32]);
+    add_async!(b, fd_filestat_set_size, [I32, I64]);
+    add_async!(b, fd_allocate, [I32, I64, I64]);
+    add_async!(b, fd_readdir, [I32, I32, I32, I64, I32]);
+    add_sync!(b, fd_filestat_set_times, [I32, I64, I64, I32]);
+    add_sync!(b, fd_fdstat_get, [I32, I32]);
+    add_sync!(b, fd_fdstat_set_flags, [I32, I32]);
+    add_sync!(b, fd_fdstat_set_rights, [I32, I64, I64]);
+    add_sync!(b, fd_advise, [I32, I64, I64, I32]);
+    add_sync!(b, fd_prestat_get, [I32, I32]);
+    add_sync!(b, fd_prestat_dir_name, [I32, I32, I32]);
+    add_sync!(b, fd_renumber, [I32, I32]);
+
+    add_async!(b, path_open, [I32, I32, I32, I32, I32, I64, I64, I32, I32]);
+    add_async!(b, path_create_directory, [I32, I32, I32]);
+    add_async!(b, path_remove_directory, [I32, I32, I32]);
+    add_async!(b, path_unlink_file, [I32, I32, I32]);
+    add_async!(b, path_rename, [I32, I32, I32, I32, I32, I32]);
+    add_async!(b, path_link, [I32, I32, I32, I32, I32, I32, I32]);
+    add_async!(b, path_symlink, [I32, I32, I32, I32, I32]);
+    add_async!(b, path_readlink, [I32, I32, I32, I32, I32, I32]);
+    add_async!(b, path_filestat_get, [I32, I32, I32, I32, I32]);
+    add_sync!(
+        b,
+        path_filestat_set_times,
+        [I32, I32, I32, I32, I64, I64, I32]
+    );
+
+    b.add_send_async_func("poll_oneoff", (vec![I32; 4], vec![I32]), poll::poll_oneoff)?;
+    add_sync!(b, sock_accept, [I32, I32, I32]);
+    add_sync!(b, sock_recv, [I32, I32, I32, I32, I32, I32]);
+    add_sync!(b, sock_send, [I32, I32, I32, I32, I32]);
+    add_sync!(b, sock_shutdown, [I32, I32]);
+    Ok(())
+}
+
+/// Writes `items` as NUL-terminated strings at `buf`, with pointers to them at `ptrs`.
+fn write_strings(
+    linker: &mut AsyncLinker,
+    items: &[String],
+    ptrs: usize,
+    buf: usize,
+) -> WasiResult<()> {
+    let mut offset = buf;
+    for (idx, item) in items.iter().enumerate() {
+        write_u32(linker, ptrs + idx * 4, offset as u32)?;
+        write_bytes(linker, offset, item.as_bytes())?;
+        write_bytes(linker, offset + item.len(), &[0])?;
+        offset += item.len() + 1;
+    }
+    Ok(())
+}
+
+fn write_sizes(
+    linker: &mut AsyncLinker,
+    items: &[String],
+    count: usize,
+    size: usize,
+) -> WasiResult<()> {
+    let total: usize = items.iter().map(|item| item.len() + 1).sum();
+    write_u32(linker, count, items.len() as u32)?;
+    write_u32(linker, size, total as u32)
+}
+
+/// `args_get(argv, argv_buf) -> errno`
+fn args_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let items = ctx(linker)?.args.clone();
+    write_strings(linker, &items, args.usize(0)?, args.usize(1)?)
+}
+
+/// `args_sizes_get(argc, argv_buf_size) -> errno`
+fn args_sizes_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let items = ctx(linker)?.args.clone();
+    write_sizes(linker, &items, args.usize(0)?, args.usize(1)?)
+}
+
+/// `environ_get(environ, environ_buf) -> errno`
+fn environ_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let items = ctx(linker)?.envs.clone();
+    write_strings(linker, &items, args.usize(0)?, args.usize(1)?)
+}
+
+/// `environ_sizes_get(environc, environ_buf_size) -> errno`
+fn environ_sizes_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let items = ctx(linker)?.envs.clone();
+    write_sizes(linker, &items, args.usize(0)?, args.usize(1)?)
+}
+
+/// `clock_res_get(id, resolution) -> errno`
+fn clock_res_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    match args.u32(0)? {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_PROCESS_CPUTIME | CLOCK_THREAD_CPUTIME => {
+            write_u64(linker, args.usize(1)?, 1_000)
+        }
+        _ => Err(Errno::INVAL),
+    }
+}
+
+/// `clock_time_get(id, precision, time) -> errno`
+///
+/// The CPU-time clocks are not tracked and read as the monotonic clock.
+fn clock_time_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let now = match args.u32(0)? {
+        CLOCK_REALTIME => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Errno::IO)?
+            .as_nanos() as u64,
+        CLOCK_MONOTONIC | CLOCK_PROCESS_CPUTIME | CLOCK_THREAD_CPUTIME => {
+            ctx(linker)?.start.elapsed().as_nanos() as u64
+        }
+        _ => return Err(Errno::INVAL),
+    };
+    write_u64(linker, args.usize(2)?, now)
+}
+
+/// `random_get(buf, buf_len) -> errno`
+fn random_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let buf = linker.get_mut_memory(MAIN_MEMORY, args.usize(0)?, args.usize(1)?)?;
+    getrandom::getrandom(buf).map_err(|_| Errno::IO)
+}
+
+/// `proc_exit(code)`
+fn proc_exit(_linker: &mut AsyncLinker, args: &[WasmVal]) -> HostResult<Vec<WasmVal>> {
+    let code = Args(args).u32(0).unwrap_or(1);
+    Err(Box::new(GuestExit(code)))
+}
+
+/// `proc_raise(sig) -> errno`
+fn proc_raise(_linker: &mut AsyncLinker, _args: Args<'_>) -> WasiResult<()> {
+    Err(Errno::NOSYS)
+}
+
+/// `sched_yield() -> errno`
+async fn sched_yield(_linker: &mut AsyncLinker, _args: Args<'_>) -> WasiResult<()> {
+    tokio::task::yield_now().await;
+    Ok(())
+}
+
+/// `fd_filestat_set_times(fd, atim, mtim, fst_flags) -> errno`
+fn fd_filestat_set_times(_linker: &mut AsyncLinker, _args: Args<'_>) -> WasiResult<()> {
+    Err(Errno::NOTSUP)
+}
+
+/// `path_filestat_set_times(fd, flags, path, path_len, atim, mtim, fst_flags) -> errno`
+fn path_filestat_set_times(_linker: &mut AsyncLinker, _args: Args<'_>) -> WasiResult<()> {
+    Err(Errno::NOTSUP)
+}
+
+/// `sock_accept(fd, flags, fd) -> errno`
+fn sock_accept(_linker: &mut AsyncLinker, _args: Args<'_>) -> WasiResult<()> {
+    Err(Errno::NOTSUP)
+}
+
+/// `sock_recv(fd, ri_data, ri_data_len, ri_flags, ro_datalen, ro_flags) -> errno`
+fn sock_recv(_linker: &mut AsyncLinker, _args: Args<'_>) -> WasiResult<()> {
+    Err(Errno::NOTSUP)
+}
+
+/// `sock_send(fd, si_data, si_data_len, si_flags, so_datalen) -> errno`
+fn sock_send(_linker: &mut AsyncLinker, _args: Args<'_>) -> WasiResult<()> {
+    Err(Errno::NOTSUP)
+}
+
+/// `sock_shutdown(fd, how) -> errno`
+fn sock_shutdown(_linker: &mut AsyncLinker, _args: Args<'_>) -> WasiResult<()> {
+    Err(Errno::NOTSUP)
+}
@@ -0,0 +1,136 @@
+//! The `path_*` functions.
+
+use super::{
+    abi::*,
+    ctx,
+    table::{Descriptor, DirDesc, FileDesc, ResolvedPath},
+};
+use crate::sdk::{
+    linker::AsyncLinker,
+    wasi::vfs::{OpenOptions, Opened},
+};
+
+/// Resolves the `(path, path_len)` arguments at `idx` against the directory `fd`.
+fn resolve_arg(
+    linker: &mut AsyncLinker,
+    args: &Args<'_>,
+    fd: u32,
+    idx: usize,
+) -> WasiResult<ResolvedPath> {
+    let path = read_string(linker, args.usize(idx)?, args.usize(idx + 1)?)?;
+    ctx(linker)?.resolve(fd, &path)
+}
+
+/// `path_open(fd, dirflags, path, path_len, oflags, rights_base, rights_inheriting,
+/// fdflags, opened_fd) -> errno`
+pub(crate) async fn path_open(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let path = resolve_arg(linker, &args, args.u32(0)?, 2)?;
+    let oflags = args.u32(4)?;
+    let rights = args.u64(5)?;
+    let fdflags = args.u32(7)?;
+
+    let create = oflags & OFLAGS_CREAT != 0;
+    let truncate = oflags & OFLAGS_TRUNC != 0;
+    let append = fdflags & FDFLAGS_APPEND != 0;
+    let write = rights & RIGHTS_FD_WRITE != 0 || create || truncate || append;
+    let read = rights & RIGHTS_FD_READ != 0 || !write;
+    let opts = OpenOptions {
+        read,
+        write,
+        append,
+        create,
+        create_new: create && oflags & OFLAGS_EXCL != 0,
+        truncate,
+        directory: oflags & OFLAGS_DIRECTORY != 0,
+        follow: args.u32(1)? & LOOKUPFLAGS_SYMLINK_FOLLOW != 0,
+    };
+
+    let desc = match path.fs.open(&path.path, &opts).await? {
+        Opened::Dir => Descriptor::Dir(DirDesc {
+            fs: path.fs,
+            path: path.path,
+            preopen: None,
+        }),
+        Opened::File(file) => Descriptor::File(FileDesc {
+            file,
+            read,
+            write,
+            append,
+        }),
+    };
+
+    let fd = ctx(linker)?.insert(desc);
+    write_u32(linker, args.usize(8)?, fd)
+}
+
+/// `path_create_directory(fd, path, path_len) -> errno`
+pub(crate) async fn path_create_directory(
+    linker: &mut AsyncLinker,
+    args: Args<'_>,
+) -> WasiResult<()> {
+    let path = resolve_arg(linker, &args, args.u32(0)?, 1)?;
+    Ok(path.fs.create_dir(&path.path).await?)
+}
+
+/// `path_remove_directory(fd, path, path_len) -> errno`
+pub(crate) async fn path_remove_directory(
+    linker: &mut AsyncLinker,
+    args: Args<'_>,
+) -> WasiResult<()> {
+    let path = resolve_arg(linker, &args, args.u32(0)?, 1)?;
+    Ok(path.fs.remove_dir(&path.path).await?)
+}
+
+/// `path_unlink_file(fd, path, path_len) -> errno`
+pub(crate) async fn path_unlink_file(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let path = resolve_arg(linker, &args, args.u32(0)?, 1)?;
+    Ok(path.fs.remove_file(&path.path).await?)
+}
+
+/// `path_rename(fd, old_path, old_path_len, new_fd, new_path, new_path_len) -> errno`
+pub(crate) async fn path_rename(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let from = resolve_arg(linker, &args, args.u32(0)?, 1)?;
+    let to = resolve_arg(linker, &args, args.u32(3)?, 4)?;
+    if !from.fs.same_tree(&to.fs) {
+        return Err(Errno::XDEV);
+    }
+    Ok(from.fs.rename(&from.path, &to.path).await?)
+}
+
+/// `path_link(old_fd, old_flags, old_path, old_path_len, new_fd, new_path,
+/// new_path_len) -> errno`
+pub(crate) async fn path_link(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let follow = args.u32(1)? & LOOKUPFLAGS_SYMLINK_FOLLOW != 0;
+    let from = resolve_arg(linker, &args, args.u32(0)?, 2)?;
+    let to = resolve_arg(linker, &args, args.u32(4)?, 5)?;
+    if !from.fs.same_tree(&to.fs) {
+        return Err(Errno::XDEV);
+    }
+    Ok(to.fs.hard_link(&from.path, &to.path, follow).await?)
+}
+
+/// `path_symlink(old_path, old_path_len, fd, new_path, new_path_len) -> errno`
+///
+/// The target is stored as given; following the link is confined like any other path.
+pub(crate) async fn path_symlink(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let target = read_string(linker, args.usize(0)?, args.usize(1)?)?;
+    let link = resolve_arg(linker, &args, args.u32(2)?, 3)?;
+    Ok(link.fs.symlink(&target, &link.path).await?)
+}
+
+/// `path_readlink(fd, path, path_len, buf, buf_len, bufused) -> errno`
+pub(crate) async fn path_readlink(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let path = resolve_arg(linker, &args, args.u32(0)?, 1)?;
+    let target = path.fs.read_link(&path.path).await?;
+    let n = target.len().min(args.usize(4)?);
+    write_bytes(linker, args.usize(3)?, &target.as_bytes()[..n])?;
+    write_u32(linker, args.usize(5)?, n as u32)
+}
+
+/// `path_filestat_get(fd, flags, path, path_len, stat) -> errno`
+pub(crate) async fn path_filestat_get(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let follow = args.u32(1)? & LOOKUPFLAGS_SYMLINK_FOLLOW != 0;
+    let path = resolve_arg(linker, &args, args.u32(0)?, 2)?;
+    let stat = path.fs.stat(&path.path, follow).await?;
+    write_filestat(linker, args.usize(4)?, &stat)
+}
@@ -0,0 +1,175 @@
+//! The descriptor table of the async WASI module.
+
+use std::{collections::BTreeMap, time::Instant};
+
+use wasmedge_types::WasmEdgeResult;
+
+use super::abi::{Errno, WasiResult};
+use crate::sdk::wasi::{
+    invalid,
+    vfs::{Vfs, VfsFile},
+    ResolvedWasi,
+};
+
+/// A directory descriptor, confined to the tree it was opened from.
+#[derive(Debug, Clone)]
+pub(crate) struct DirDesc {
+    pub(crate) fs: Vfs,
+    /// The components of the directory below the root of `fs`.
+    pub(crate) path: Vec<String>,
+    /// The guest path, if this is a preopen.
+    pub(crate) preopen: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct FileDesc {
+    pub(crate) file: VfsFile,
+    pub(crate) read: bool,
+    pub(crate) write: bool,
+    pub(crate) append: bool,
+}
+
+#[derive(Debug)]
+pub(crate) enum Descriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    Dir(DirDesc),
+    File(FileDesc),
+}
+
+/// A path resolved against a directory descriptor.
+#[derive(Debug)]
+pub(crate) struct ResolvedPath {
+    pub(crate) fs: Vfs,
+    /// The components of the path below the root of `fs`.
+    pub(crate) path: Vec<String>,
+}
+
+/// The state of the async WASI module: arguments, environment and open descriptors.
+#[derive(Debug)]
+pub(crate) struct WasiCtx {
+    pub(crate) args: Vec<String>,
+    /// `KEY=VALUE` entries.
+    pub(crate) envs: Vec<String>,
+    pub(crate) start: Instant,
+    fds: BTreeMap<u32, Descriptor>,
+}
+
+impl WasiCtx {
+    pub(crate) fn new(wasi: &ResolvedWasi) -> WasmEdgeResult<Self> {
+        let mut fds = BTreeMap::new();
+        fds.insert(0, Descriptor::Stdin);
+        fds.insert(1, Descriptor::Stdout);
+        fds.insert(2, Descriptor::Stderr);
+        let mut mounts = vec![];
+        for preopen in &wasi.preopens {
+            let fs = Vfs::host(&preopen.host).map_err(|e| {
+                invalid(format!(
+                    "preopened directory {:?} is not accessible: {}",
+                    preopen.host, e
+                ))
+            })?;
+            let fs = if preopen.read_only {
+                fs.read_only()
+            } else {
+                fs
+            };
+            mounts.push((preopen.guest.clone(), fs));
+        }
+        mounts.extend(wasi.mounts.iter().cloned());
+        for (idx, (guest, fs)) in mounts.into_iter().enumerate() {
+            fds.insert(
+                3 + idx as u32,
+                Descriptor::Dir(DirDesc {
+                    fs,
+                    path: vec![],
+                    preopen: Some(guest),
+                }),
+            );
+        }
+        Ok(WasiCtx {
+            args: wasi.args.clone(),
+            envs: wasi
+                .envs
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect(),
+            start: Instant::now(),
+            fds,
+        })
+    }
+
+    pub(crate) fn get(&self, fd: u32) -> WasiResult<&Descriptor> {
+        self.fds.get(&fd).ok_or(Errno::BADF)
+    }
+
+    pub(crate) fn dir(&self, fd: u32) -> WasiResult<&DirDesc> {
+        match self.get(fd)? {
+            Descriptor::Dir(dir) => Ok(dir),
+            _ => Err(Errno::NOTDIR),
+        }
+    }
+
+    pub(crate) fn file_mut(&mut self, fd: u32) -> WasiResult<&mut FileDesc> {
+        match self.fds.get_mut(&fd) {
+            Some(Descriptor::File(file)) => Ok(file),
+            Some(Descriptor::Dir(_)) => Err(Errno::ISDIR),
+            Some(_) => Err(Errno::SPIPE),
+            None => Err(Errno::BADF),
+        }
+    }
+
+    /// Adds `desc` under the lowest free descriptor number.
+    pub(crate) fn insert(&mut self, desc: Descriptor) -> u32 {
+        let mut fd = 0;
+        while self.fds.contains_key(&fd) {
+            fd += 1;
+        }
+        self.fds.insert(fd, desc);
+        fd
+    }
+
+    pub(crate) fn remove(&mut self, fd: u32) -> WasiResult<Descriptor> {
+        self.fds.remove(&fd).ok_or(Errno::BADF)
+    }
+
+    /// Moves `from` to `to`, closing whatever `to` was.
+    pub(crate) fn renumber(&mut self, from: u32, to: u32) -> WasiResult<()> {
+        if !self.fds.contains_key(&to) {
+            return Err(Errno::BADF);
+        }
+        let desc = self.remove(from)?;
+        self.fds.insert(to, desc);
+        Ok(())
+    }
+
+    /// Resolves the guest `path` relative to the directory `fd`.
+    ///
+    /// Absolute paths and `..` leaving the tree are refused; the tree itself refuses
+    /// symlinks pointing outside of it.
+    pub(crate) fn resolve(&self, fd: u32, path: &str) -> WasiResult<ResolvedPath> {
+        let dir = self.dir(fd)?;
+        if path.is_empty() {
+            return Err(Errno::NOENT);
+        }
+        if path.starts_with('/') {
+            return Err(Errno::NOTCAPABLE);
+        }
+
+        let mut resolved = dir.path.clone();
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    resolved.pop().ok_or(Errno::NOTCAPABLE)?;
+                }
+                name => resolved.push(name.to_string()),
+            }
+        }
+        Ok(ResolvedPath {
+            fs: dir.fs.clone(),
+            path: resolved,
+        })
+    }
+}
@@ -0,0 +1,417 @@
+//! The socket functions, with the arguments of the WasmEdge 0.10 socket API.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, UdpSocket},
+};
+
+use super::{sockets, Family, Socket, SocketAction};
+use crate::sdk::{linker::AsyncLinker, wasi::preview1::abi::*};
+
+const ADDRESS_FAMILY_INET4: u32 = 0;
+const ADDRESS_FAMILY_INET6: u32 = 1;
+
+const SOCK_TYPE_DGRAM: u32 = 0;
+const SOCK_TYPE_STREAM: u32 = 1;
+
+const RIFLAGS_RECV_PEEK: u32 = 1;
+const RIFLAGS_RECV_WAITALL: u32 = 2;
+
+const SDFLAGS_WR: u32 = 2;
+
+impl Family {
+    fn of(addr: &SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(_) => Family::V4,
+            SocketAddr::V6(_) => Family::V6,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Family::V4 => ADDRESS_FAMILY_INET4 as u8,
+            Family::V6 => ADDRESS_FAMILY_INET6 as u8,
+        }
+    }
+
+    fn unspecified(self) -> SocketAddr {
+        match self {
+            Family::V4 => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            Family::V6 => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        }
+    }
+}
+
+/// Reads the `address_t { buf, buf_len }` at `ptr`, whose buffer holds the 4 or 16
+/// octets of the IP address.
+fn read_addr(linker: &AsyncLinker, ptr: usize, port: u32) -> WasiResult<SocketAddr> {
+    let buf = read_u32(linker, ptr)? as usize;
+    let len = read_u32(linker, ptr + 4)? as usize;
+    let ip = match len {
+        4 => {
+            let mut octets = [0; 4];
+            octets.copy_from_slice(&read_bytes(linker, buf, 4)?);
+            IpAddr::from(octets)
+        }
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&read_bytes(linker, buf, 16)?);
+            IpAddr::from(octets)
+        }
+        _ => return Err(Errno::INVAL),
+    };
+    let port = u16::try_from(port).map_err(|_| Errno::INVAL)?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Writes `addr` into the `address_t` at `ptr`, its type (4 or 6) at `ty` and its port
+/// at `port`.
+fn write_addr(
+    linker: &mut AsyncLinker,
+    addr: SocketAddr,
+    ptr: usize,
+    ty: usize,
+    port: usize,
+) -> WasiResult<()> {
+    let version = write_ip(linker, addr, ptr)?;
+    write_u32(linker, ty, version)?;
+    write_u32(linker, port, addr.port() as u32)
+}
+
+/// Writes the octets of the IP address of `addr` into the buffer of the `address_t` at
+/// `ptr`, and returns the IP version.
+fn write_ip(linker: &mut AsyncLinker, addr: SocketAddr, ptr: usize) -> WasiResult<u32> {
+    let (octets, version) = match addr.ip() {
+        IpAddr::V4(ip) => (ip.octets().to_vec(), 4),
+        IpAddr::V6(ip) => (ip.octets().to_vec(), 6),
+    };
+    let buf = read_u32(linker, ptr)? as usize;
+    if (read_u32(linker, ptr + 4)? as usize) < octets.len() {
+        return Err(Errno::INVAL);
+    }
+    write_bytes(linker, buf, &octets)?;
+    Ok(version)
+}
+
+/// The `sa_data` of a C `sockaddr` for `addr`: the port in network byte order, then the
+/// address where `sockaddr_in` or `sockaddr_in6` keep it.
+fn sa_data(addr: &SocketAddr) -> Vec<u8> {
+    let mut data = addr.port().to_be_bytes().to_vec();
+    match addr {
+        SocketAddr::V4(addr) => {
+            data.extend_from_slice(&addr.ip().octets());
+            data.resize(14, 0);
+        }
+        SocketAddr::V6(addr) => {
+            data.extend_from_slice(&addr.flowinfo().to_be_bytes());
+            data.extend_from_slice(&addr.ip().octets());
+            data.extend_from_slice(&addr.scope_id().to_le_bytes());
+        }
+    }
+    data
+}
+
+/// `sock_open(address_family, sock_type, fd) -> errno`
+pub(super) async fn sock_open(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let family = match args.u32(0)? {
+        ADDRESS_FAMILY_INET4 => Family::V4,
+        ADDRESS_FAMILY_INET6 => Family::V6,
+        _ => return Err(Errno::INVAL),
+    };
+    let socket = match (args.u32(1)?, family) {
+        (SOCK_TYPE_STREAM, Family::V4) => Socket::Tcp(TcpSocket::new_v4()?),
+        (SOCK_TYPE_STREAM, Family::V6) => Socket::Tcp(TcpSocket::new_v6()?),
+        (SOCK_TYPE_DGRAM, family) => Socket::Udp(family),
+        _ => return Err(Errno::INVAL),
+    };
+    let fd = sockets(linker)?.insert(socket)?;
+    write_u32(linker, args.usize(2)?, fd)
+}
+
+/// `sock_bind(fd, address, port) -> errno`
+pub(super) async fn sock_bind(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let addr = read_addr(linker, args.usize(1)?, args.u32(2)?)?;
+    let ctx = sockets(linker)?;
+    ctx.check(SocketAction::Bind, addr)?;
+    let socket = ctx.get_mut(fd)?;
+    match socket {
+        Socket::Tcp(tcp) => tcp.bind(addr)?,
+        Socket::Udp(family) if *family == Family::of(&addr) => {
+            *socket = Socket::Datagram(UdpSocket::bind(addr).await?);
+        }
+        _ => return Err(Errno::INVAL),
+    }
+    Ok(())
+}
+
+/// `sock_listen(fd, backlog) -> errno`
+pub(super) async fn sock_listen(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let backlog = args.u32(1)?;
+    let ctx = sockets(linker)?;
+    let tcp = match ctx.take(fd)? {
+        Socket::Tcp(tcp) => tcp,
+        other => {
+            ctx.put(fd, other);
+            return Err(Errno::INVAL);
+        }
+    };
+    ctx.put(fd, Socket::Listener(tcp.listen(backlog)?, None));
+    Ok(())
+}
+
+/// `sock_accept(fd, new_fd) -> errno`
+///
+/// Waits for a connection the policy allows.
+pub(super) async fn sock_accept(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let stream = loop {
+        let listener = match sockets(linker)?.get_mut(fd)? {
+            Socket::Listener(_, pending @ Some(_)) => break pending.take().unwrap(),
+            Socket::Listener(listener, None) => listener,
+            _ => return Err(Errno::INVAL),
+        };
+        let (stream, peer) = listener.accept().await?;
+        if sockets(linker)?.config.allows(SocketAction::Accept, peer) {
+            break stream;
+        }
+    };
+    let new_fd = sockets(linker)?.insert(Socket::Stream(stream))?;
+    write_u32(linker, args.usize(1)?, new_fd)
+}
+
+/// `sock_connect(fd, address, port) -> errno`
+pub(super) async fn sock_connect(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let addr = read_addr(linker, args.usize(1)?, args.u32(2)?)?;
+    let ctx = sockets(linker)?;
+    ctx.check(SocketAction::Connect, addr)?;
+
+    let connected = match ctx.take(fd)? {
+        Socket::Tcp(tcp) => tcp.connect(addr).await.map(Socket::Stream),
+        Socket::Udp(family) => match UdpSocket::bind(family.unspecified()).await {
+            Ok(udp) => udp.connect(addr).await.map(|_| Socket::Datagram(udp)),
+            Err(e) => Err(e),
+        },
+        Socket::Datagram(udp) => udp.connect(addr).await.map(|_| Socket::Datagram(udp)),
+        other => {
+            ctx.put(fd, other);
+            return Err(Errno::ISCONN);
+        }
+    };
+    // on failure the socket stays broken, as a failed connect leaves it on the host
+    ctx.put(fd, connected?);
+    Ok(())
+}
+
+/// `sock_recv(fd, ri_data, ri_data_len, ri_flags, ro_datalen, ro_flags) -> errno`
+///
+/// Like every read, at most 64 KiB are received at once, `RECV_WAITALL` included.
+pub(super) async fn sock_recv(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let iovecs = read_iovecs(linker, args.usize(1)?, args.usize(2)?)?;
+    let flags = args.u32(3)?;
+    let max = read_len(&iovecs);
+
+    let mut buf = vec![0; max];
+    let n = match sockets(linker)?.get_mut(fd)? {
+        Socket::Stream(stream) if flags & RIFLAGS_RECV_PEEK != 0 => stream.peek(&mut buf).await?,
+        Socket::Stream(stream) if flags & RIFLAGS_RECV_WAITALL != 0 => {
+            let mut n = 0;
+            while n < max {
+                match stream.read(&mut buf[n..]).await? {
+                    0 => break,
+                    read => n += read,
+                }
+            }
+            n
+        }
+        Socket::Stream(stream) => stream.read(&mut buf).await?,
+        Socket::Datagram(udp) if flags & RIFLAGS_RECV_PEEK != 0 => udp.peek_from(&mut buf).await?.0,
+        Socket::Datagram(udp) => udp.recv(&mut buf).await?,
+        _ => return Err(Errno::NOTCONN),
+    };
+    buf.truncate(n);
+
+    scatter(linker, &iovecs, &buf)?;
+    write_u32(linker, args.usize(4)?, n as u32)?;
+    write_bytes(linker, args.usize(5)?, &0u16.to_le_bytes())
+}
+
+/// `sock_recv_from(fd, ri_data, ri_data_len, address, ri_flags, port, ro_datalen,
+/// ro_flags) -> errno`
+///
+/// Receives a datagram, binding the socket to an ephemeral port first if needed, and
+/// writes its sender to `address`, whose length becomes 4 or 16, and `port`. Datagrams
+/// from senders the policy refuses are dropped.
+pub(super) async fn sock_recv_from(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let iovecs = read_iovecs(linker, args.usize(1)?, args.usize(2)?)?;
+    let flags = args.u32(4)?;
+
+    let mut buf = vec![0; read_len(&iovecs)];
+    let (n, peer) = loop {
+        let udp = datagram(linker, fd).await?;
+        let (n, peer) = match flags & RIFLAGS_RECV_PEEK != 0 {
+            true => udp.peek_from(&mut buf).await?,
+            false => udp.recv_from(&mut buf).await?,
+        };
+        if sockets(linker)?.config.allows(SocketAction::Accept, peer) {
+            break (n, peer);
+        }
+        if flags & RIFLAGS_RECV_PEEK != 0 {
+            // drop the peeked datagram so that the next one can be looked at
+            datagram(linker, fd).await?.recv_from(&mut buf).await?;
+        }
+    };
+    buf.truncate(n);
+
+    scatter(linker, &iovecs, &buf)?;
+    // the guest tells the sender's family by the number of octets
+    let addr = args.usize(3)?;
+    write_ip(linker, peer, addr)?;
+    write_u32(linker, addr + 4, if peer.is_ipv4() { 4 } else { 16 })?;
+    write_u32(linker, args.usize(5)?, peer.port() as u32)?;
+    write_u32(linker, args.usize(6)?, n as u32)?;
+    write_bytes(linker, args.usize(7)?, &0u16.to_le_bytes())
+}
+
+/// `sock_send(fd, si_data, si_data_len, si_flags, so_datalen) -> errno`
+pub(super) async fn sock_send(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let iovecs = read_iovecs(linker, args.usize(1)?, args.usize(2)?)?;
+    let data = gather(linker, &iovecs)?;
+
+    let n = match sockets(linker)?.get_mut(fd)? {
+        Socket::Stream(stream) => {
+            stream.write_all(&data).await?;
+            data.len()
+        }
+        Socket::Datagram(udp) => udp.send(&data).await?,
+        _ => return Err(Errno::NOTCONN),
+    };
+    write_u32(linker, args.usize(4)?, n as u32)
+}
+
+/// `sock_send_to(fd, si_data, si_data_len, address, port, si_flags, so_datalen) -> errno`
+///
+/// Sends a datagram, binding the socket to an ephemeral port first if needed.
+pub(super) async fn sock_send_to(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let iovecs = read_iovecs(linker, args.usize(1)?, args.usize(2)?)?;
+    let addr = read_addr(linker, args.usize(3)?, args.u32(4)?)?;
+    let data = gather(linker, &iovecs)?;
+    sockets(linker)?.check(SocketAction::Connect, addr)?;
+
+    let n = datagram(linker, fd).await?.send_to(&data, addr).await?;
+    write_u32(linker, args.usize(6)?, n as u32)
+}
+
+/// Returns the UDP socket `fd`, binding it to an ephemeral port first if it is not bound
+/// yet.
+async fn datagram(linker: &mut AsyncLinker, fd: u32) -> WasiResult<&mut UdpSocket> {
+    let ctx = sockets(linker)?;
+    if let Socket::Udp(family) = ctx.get_mut(fd)? {
+        let family = *family;
+        ctx.put(
+            fd,
+            Socket::Datagram(UdpSocket::bind(family.unspecified()).await?),
+        );
+    }
+    match ctx.get_mut(fd)? {
+        Socket::Datagram(udp) => Ok(udp),
+        _ => Err(Errno::INVAL),
+    }
+}
+
+/// `sock_shutdown(fd, how) -> errno`
+///
+/// `tokio` can only shut down the write half; shutting down reads is accepted and the
+/// guest keeps receiving whatever arrives.
+pub(super) async fn sock_shutdown(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let fd = args.u32(0)?;
+    let how = args.u32(1)?;
+    match sockets(linker)?.get_mut(fd)? {
+        Socket::Stream(stream) if how & SDFLAGS_WR != 0 => Ok(stream.shutdown().await?),
+        Socket::Stream(_) | Socket::Datagram(_) => Ok(()),
+        _ => Err(Errno::NOTCONN),
+    }
+}
+
+/// `sock_getlocaladdr(fd, address, address_type, port) -> errno`
+pub(super) async fn sock_getlocaladdr(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let addr = match sockets(linker)?.get_mut(args.u32(0)?)? {
+        Socket::Tcp(tcp) => tcp.local_addr()?,
+        Socket::Listener(listener, _) => listener.local_addr()?,
+        Socket::Stream(stream) => stream.local_addr()?,
+        Socket::Udp(family) => family.unspecified(),
+        Socket::Datagram(udp) => udp.local_addr()?,
+        Socket::Broken => return Err(Errno::INVAL),
+    };
+    write_addr(linker, addr, args.usize(1)?, args.usize(2)?, args.usize(3)?)
+}
+
+/// `sock_getpeeraddr(fd, address, address_type, port) -> errno`
+pub(super) async fn sock_getpeeraddr(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let addr = match sockets(linker)?.get_mut(args.u32(0)?)? {
+        Socket::Stream(stream) => stream.peer_addr()?,
+        Socket::Datagram(udp) => udp.peer_addr()?,
+        _ => return Err(Errno::NOTCONN),
+    };
+    write_addr(linker, addr, args.usize(1)?, args.usize(2)?, args.usize(3)?)
+}
+
+/// `sock_getaddrinfo(node, node_len, service, service_len, hints, res, max_res_len,
+/// res_len) -> errno`
+///
+/// Fills the `addrinfo` list the guest allocated at `*res`, following `ai_next`. Only
+/// numeric services are resolved.
+pub(super) async fn sock_getaddrinfo(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    let node = read_string(linker, args.usize(0)?, args.usize(1)?)?;
+    let service = read_string(linker, args.usize(2)?, args.usize(3)?)?;
+    let port = match service.as_str() {
+        "" => 0,
+        service => service.parse::<u16>().map_err(|_| Errno::INVAL)?,
+    };
+    // addrinfo: flags u16, family u8, socktype u8, protocol u8, addrlen u32 at 8,
+    // addr at 12, canonname at 16, canonname_len at 20, next at 24
+    let hints = read_bytes(linker, args.usize(4)?, 8)?;
+    let family = hints[2];
+    let max = args.usize(6)?;
+
+    let addrs = tokio::net::lookup_host((node.as_str(), port))
+        .await
+        .map_err(|_| Errno::NOENT)?
+        .filter(|addr| Family::of(addr).code() == family)
+        .take(max)
+        .collect::<Vec<_>>();
+
+    let mut entry = read_u32(linker, args.usize(5)?)? as usize;
+    let mut count = 0;
+    for addr in &addrs {
+        if entry == 0 {
+            break;
+        }
+        let data = sa_data(addr);
+        write_bytes(linker, entry + 2, &hints[2..5])?;
+        write_u32(linker, entry + 8, data.len() as u32 + 2)?;
+        // sockaddr: family u8, sa_data_len u32 at 4, sa_data at 8
+        let sockaddr = read_u32(linker, entry + 12)? as usize;
+        write_bytes(linker, sockaddr, &[family])?;
+        let len = (read_u32(linker, sockaddr + 4)? as usize).min(data.len());
+        let buf = read_u32(linker, sockaddr + 8)? as usize;
+        write_bytes(linker, buf, &data[..len])?;
+        count += 1;
+        entry = read_u32(linker, entry + 24)? as usize;
+    }
+    write_u32(linker, args.usize(7)?, count)
+}
+
+/// `fd_close(fd) -> errno`, for the socket descriptors.
+pub(super) async fn fd_close(linker: &mut AsyncLinker, args: Args<'_>) -> WasiResult<()> {
+    sockets(linker)?.remove(args.u32(0)?)?;
+    Ok(())
+}
@@ -0,0 +1,378 @@
+//! An async implementation of the WasmEdge socket functions.
+//!
+//! Guests written against `wasmedge_wasi_socket` import `sock_*` functions from
+//! `wasi_snapshot_preview1`, which the WasmEdge WASI module implements with blocking
+//! calls. Those imports are redirected to the functions of [`SOCKET_MODULE`], backed by
+//! `tokio` sockets, so a guest waiting for a connection or for data is suspended
+//! instead. The signatures are the ones of WasmEdge 0.10.
+//!
+//! Sockets are numbered from [`FIRST_SOCKET_FD`], away from the fds of the WASI module,
+//! and the guest's `fd_close` calls on those numbers close the socket.
+
+mod calls;
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::SocketAddr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
+use wasmedge_types::{ValType::I32, WasmEdgeResult};
+
+use super::preview1::{
+    abi::{errno, Args, Errno, WasiResult},
+    add_async,
+};
+use crate::{
+    core::types::WasmVal,
+    sdk::{
+        instance::function::SendResultFuture, linker::AsyncLinker, module::AsyncImportModuleBuilder,
+    },
+};
+use calls::*;
+
+/// The import module holding the async socket functions.
+pub(crate) const SOCKET_MODULE: &str = "wasmedge_asyncify_socket";
+
+/// The descriptor of the first socket a guest opens.
+pub(crate) const FIRST_SOCKET_FD: u32 = 0x4000;
+
+/// The `wasi_snapshot_preview1` imports redirected to [`SOCKET_MODULE`].
+pub(crate) const SOCKET_IMPORTS: [&str; 13] = [
+    "sock_open",
+    "sock_bind",
+    "sock_listen",
+    "sock_accept",
+    "sock_connect",
+    "sock_recv",
+    "sock_recv_from",
+    "sock_send",
+    "sock_send_to",
+    "sock_shutdown",
+    "sock_getlocaladdr",
+    "sock_getpeeraddr",
+    "sock_getaddrinfo",
+];
+
+/// What a guest is about to do with an address, as told to the policy of a
+/// [`SocketConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketAction {
+    /// Binding a socket to a local address.
+    Bind,
+    /// Connecting to a remote address, or sending a datagram to it with `sock_send_to`.
+    Connect,
+    /// Accepting a connection from a remote address, or receiving a datagram from it
+    /// with `sock_recv_from`. Refused connections are closed, refused datagrams dropped,
+    /// and the guest keeps waiting for the next one.
+    Accept,
+}
+
+type Policy = Arc<dyn Fn(SocketAction, SocketAddr) -> bool + Send + Sync>;
+
+/// Configures the async socket module added with
+/// [`create_async_sockets`](crate::sdk::AsyncLinkerBuilder::create_async_sockets).
+#[derive(Clone)]
+pub struct SocketConfig {
+    policy: Option<Policy>,
+    max_sockets: usize,
+}
+
+impl SocketConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks `f` before the guest binds to, connects to or accepts from an address.
+    /// Refused binds and connects fail with `EACCES`. Without a policy every address
+    /// is allowed.
+    pub fn policy<F>(mut self, f: F) -> Self
+    where
+        F: Fn(SocketAction, SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.policy = Some(Arc::new(f));
+        self
+    }
+
+    /// Limits how many sockets the guest may have open at once, 64 by default. Opening
+    /// more fails with `EMFILE`.
+    pub fn max_sockets(mut self, max_sockets: usize) -> Self {
+        self.max_sockets = max_sockets;
+        self
+    }
+
+    fn allows(&self, action: SocketAction, addr: SocketAddr) -> bool {
+        self.policy
+            .as_ref()
+            .is_none_or(|policy| policy(action, addr))
+    }
+
+    /// The descriptors sockets may get, whose `fd_close` calls go to the socket module.
+    pub(crate) fn fds(&self) -> Vec<i32> {
+        (0..self.max_sockets)
+            .map(|idx| (FIRST_SOCKET_FD as usize + idx) as i32)
+            .collect()
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        SocketConfig {
+            policy: None,
+            max_sockets: 64,
+        }
+    }
+}
+
+impl fmt::Debug for SocketConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SocketConfig")
+            .field("policy", &self.policy.as_ref().map(|_| ".."))
+            .field("max_sockets", &self.max_sockets)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Family {
+    V4,
+    V6,
+}
+
+#[derive(Debug)]
+pub(crate) enum Socket {
+    /// A TCP socket before `sock_listen` or `sock_connect`.
+    Tcp(TcpSocket),
+    /// A listening socket, with the connection `poll_oneoff` accepted while waiting for
+    /// one, which the next `sock_accept` returns.
+    Listener(TcpListener, Option<TcpStream>),
+    Stream(TcpStream),
+    /// A UDP socket before `sock_bind` or `sock_connect`.
+    Udp(Family),
+    Datagram(UdpSocket),
+    /// A socket whose `sock_listen` or `sock_connect` failed; it can only be closed.
+    Broken,
+}
+
+/// The sockets of a guest.
+#[derive(Debug)]
+pub(crate) struct SocketCtx {
+    config: SocketConfig,
+    sockets: BTreeMap<u32, Socket>,
+}
+
+impl SocketCtx {
+    pub(crate) fn new(config: SocketConfig) -> Self {
+        SocketCtx {
+            config,
+            sockets: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn contains(&self, fd: u32) -> bool {
+        self.sockets.contains_key(&fd)
+    }
+
+    /// Fails with `EACCES` if the policy refuses `addr`.
+    fn check(&self, action: SocketAction, addr: SocketAddr) -> WasiResult<()> {
+        if !self.config.allows(action, addr) {
+            return Err(Errno::ACCES);
+        }
+        Ok(())
+    }
+
+    fn get_mut(&mut self, fd: u32) -> WasiResult<&mut Socket> {
+        self.sockets.get_mut(&fd).ok_or(Errno::BADF)
+    }
+
+    /// Takes the socket `fd` out of the table, leaving it [`Socket::Broken`] until it is
+    /// put back.
+    fn take(&mut self, fd: u32) -> WasiResult<Socket> {
+        Ok(std::mem::replace(self.get_mut(fd)?, Socket::Broken))
+    }
+
+    fn put(&mut self, fd: u32, socket: Socket) {
+        self.sockets.insert(fd, socket);
+    }
+
+    /// Adds `socket` under the lowest free socket descriptor.
+    fn insert(&mut self, socket: Socket) -> WasiResult<u32> {
+        let end = FIRST_SOCKET_FD + self.config.max_sockets as u32;
+        let fd = (FIRST_SOCKET_FD..end)
+            .find(|fd| !self.sockets.contains_key(fd))
+            .ok_or(Errno::MFILE)?;
+        self.sockets.insert(fd, socket);
+        Ok(fd)
+    }
+
+    fn remove(&mut self, fd: u32) -> WasiResult<Socket> {
+        self.sockets.remove(&fd).ok_or(Errno::BADF)
+    }
+
+    /// Polls whether a read, or with `read` unset a write, on the socket `fd` can go on
+    /// without waiting. Sockets the call would fail on right away are ready.
+    pub(crate) fn poll_ready(&mut self, fd: u32, read: bool, cx: &mut Context<'_>) -> bool {
+        let config = &self.config;
+        match self.sockets.get_mut(&fd) {
+            Some(Socket::Stream(stream)) if read => stream.poll_read_ready(cx).is_ready(),
+            Some(Socket::Stream(stream)) => stream.poll_write_ready(cx).is_ready(),
+            Some(Socket::Datagram(udp)) if read => udp.poll_recv_ready(cx).is_ready(),
+            Some(Socket::Datagram(udp)) => udp.poll_send_ready(cx).is_ready(),
+            Some(Socket::Listener(listener, pending)) if read => loop {
+                if pending.is_some() {
+                    break true;
+                }
+                match listener.poll_accept(cx) {
+                    Poll::Ready(Ok((stream, peer))) => {
+                        if config.allows(SocketAction::Accept, peer) {
+                            *pending = Some(stream);
+                        }
+                    }
+                    Poll::Ready(Err(_)) => break true,
+                    Poll::Pending => break false,
+                }
+            },
+            _ => true,
+        }
+    }
+}
+
+fn sockets(linker: &mut AsyncLinker) -> WasiResult<&mut SocketCtx> {
+    linker.sockets.as_deref_mut().ok_or(Errno::BADF)
+}
+
+/// Adds the socket functions and the `fd_close` of sockets to `builder`.
+pub(crate) fn add_functions(b: &mut AsyncImportModuleBuilder) -> WasmEdgeResult<()> {
+    add_async!(b, sock_open, [I32, I32, I32]);
+    add_async!(b, sock_bind, [I32, I32, I32]);
+    add_async!(b, sock_listen, [I32, I32]);
+    add_async!(b, sock_accept, [I32, I32]);
+    add_async!(b, sock_connect, [I32, I32, I32]);
+    add_async!(b, sock_recv, [I32, I32, I32, I32, I32, I32]);
+    add_async!(b, sock_recv_from, [I32, I32, I32, I32, I32, I32, I32, I32]);
+    add_async!(b, sock_send, [I32, I32, I32, I32, I32]);
+    add_async!(b, sock_send_to, [I32, I32, I32, I32, I32, I32, I32]);
+    add_async!(b, sock_shutdown, [I32, I32]);
+    add_async!(b, sock_getlocaladdr, [I32, I32, I32, I32]);
+    add_async!(b, sock_getpeeraddr, [I32, I32, I32, I32]);
+    add_async!(
+        b,
+        sock_getaddrinfo,
+        [I32, I32, I32, I32, I32, I32, I32, I32]
+    );
+    add_async!(b, fd_close, [I32]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, time::Duration};
+
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream, UdpSocket},
+    };
+
+    use super::*;
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    /// Polls the readiness of `fd` once.
+    async fn is_ready(ctx: &mut SocketCtx, fd: u32, read: bool) -> bool {
+        std::future::poll_fn(|cx| Poll::Ready(ctx.poll_ready(fd, read, cx))).await
+    }
+
+    /// Waits for `fd` to be ready like `poll_oneoff` does, failing after a while.
+    async fn ready(ctx: &mut SocketCtx, fd: u32, read: bool) {
+        let wait = std::future::poll_fn(|cx| match ctx.poll_ready(fd, read, cx) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        });
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .expect("socket never became ready");
+    }
+
+    #[test]
+    fn listener_is_ready_with_a_connection() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let mut ctx = SocketCtx::new(SocketConfig::new());
+            let fd = ctx.insert(Socket::Listener(listener, None)).unwrap();
+            assert!(!is_ready(&mut ctx, fd, true).await);
+
+            let _client = TcpStream::connect(addr).await.unwrap();
+            ready(&mut ctx, fd, true).await;
+            assert!(matches!(ctx.get_mut(fd), Ok(Socket::Listener(_, Some(_)))));
+        })
+    }
+
+    #[test]
+    fn listener_drops_refused_connections() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let config = SocketConfig::new().policy(|action, _| action != SocketAction::Accept);
+            let mut ctx = SocketCtx::new(config);
+            let fd = ctx.insert(Socket::Listener(listener, None)).unwrap();
+
+            let _client = TcpStream::connect(addr).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert!(!is_ready(&mut ctx, fd, true).await);
+            assert!(matches!(ctx.get_mut(fd), Ok(Socket::Listener(_, None))));
+        })
+    }
+
+    #[test]
+    fn stream_is_readable_once_data_arrives() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (server, _) = listener.accept().await.unwrap();
+            let mut ctx = SocketCtx::new(SocketConfig::new());
+            let fd = ctx.insert(Socket::Stream(server)).unwrap();
+
+            ready(&mut ctx, fd, false).await;
+            assert!(!is_ready(&mut ctx, fd, true).await);
+            client.write_all(b"ping").await.unwrap();
+            ready(&mut ctx, fd, true).await;
+        })
+    }
+
+    #[test]
+    fn datagram_is_readable_once_a_datagram_arrives() {
+        block_on(async {
+            let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = receiver.local_addr().unwrap();
+            let mut ctx = SocketCtx::new(SocketConfig::new());
+            let fd = ctx.insert(Socket::Datagram(receiver)).unwrap();
+            assert!(!is_ready(&mut ctx, fd, true).await);
+
+            let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            sender.send_to(b"ping", addr).await.unwrap();
+            ready(&mut ctx, fd, true).await;
+        })
+    }
+
+    #[test]
+    fn unconnected_sockets_are_ready() {
+        block_on(async {
+            let mut ctx = SocketCtx::new(SocketConfig::new());
+            let fd = ctx.insert(Socket::Udp(Family::V4)).unwrap();
+            assert!(is_ready(&mut ctx, fd, true).await);
+            assert!(is_ready(&mut ctx, FIRST_SOCKET_FD + 1, false).await);
+        })
+    }
+}
@@ -0,0 +1,355 @@
+//! Routes the guest's stdio to host sources and sinks.
+//!
+//! The WasmEdge WASI module uses the host process's streams. For captured streams the
+//! guest's `fd_write` and `fd_read` imports are replaced by dispatchers that send calls on
+//! fds 0, 1 and 2 to the async functions of [`STDIO_MODULE`], and every other fd to WASI.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+
+use wasmedge_types::error::{FuncError, WasmEdgeError};
+
+use crate::{
+    core::types::WasmVal,
+    sdk::{instance::function::SendResultFuture, linker::AsyncLinker},
+};
+
+use super::preview1::abi::{
+    errno, gather, read_iovecs, read_len, scatter, truncate_iovecs, write_u32, Errno, WasiResult,
+    MAX_READ,
+};
+
+/// The import module holding the async replacements of WASI functions.
+pub(crate) const STDIO_MODULE: &str = "wasmedge_asyncify_wasi";
+
+type LineFn = dyn Fn(&[u8]) + Send + Sync;
+
+/// Where the guest's stdout or stderr goes.
+#[derive(Clone, Default)]
+pub enum OutputSink {
+    /// The host process's stream, written by the WasmEdge WASI module. The default.
+    #[default]
+    Inherit,
+    /// Drops the output.
+    Discard,
+    /// Appends the output to a shared buffer.
+    Buffer(Arc<Mutex<Vec<u8>>>),
+    /// Calls back once per line, without the trailing `\n`. A last unterminated line is
+    /// passed when the linker is dropped.
+    Lines(Arc<LineFn>),
+    /// Writes the output to an async writer, flushing after each write.
+    #[cfg(feature = "tokio")]
+    Writer(Arc<tokio::sync::Mutex<Pin<Box<dyn tokio::io::AsyncWrite + Send>>>>),
+}
+
+impl OutputSink {
+    pub fn lines<F: Fn(&[u8]) + Send + Sync + 'static>(f: F) -> Self {
+        OutputSink::Lines(Arc::new(f))
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn writer<W: tokio::io::AsyncWrite + Send + 'static>(writer: W) -> Self {
+        OutputSink::Writer(Arc::new(tokio::sync::Mutex::new(Box::pin(writer))))
+    }
+
+    fn is_captured(&self) -> bool {
+        !matches!(self, OutputSink::Inherit)
+    }
+}
+
+impl fmt::Debug for OutputSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputSink::Inherit => write!(f, "Inherit"),
+            OutputSink::Discard => write!(f, "Discard"),
+            OutputSink::Buffer(buf) => f.debug_tuple("Buffer").field(buf).finish(),
+            OutputSink::Lines(_) => write!(f, "Lines(..)"),
+            #[cfg(feature = "tokio")]
+            OutputSink::Writer(_) => write!(f, "Writer(..)"),
+        }
+    }
+}
+
+/// Where the guest's stdin comes from.
+#[derive(Clone, Default)]
+pub enum InputSource {
+    /// The host process's stdin, read by the WasmEdge WASI module. The default.
+    #[default]
+    Inherit,
+    /// Reads reach end of file immediately.
+    Empty,
+    /// Reads return these bytes, then end of file.
+    Bytes(Arc<[u8]>),
+    /// Reads wait on an async reader; the guest is suspended meanwhile.
+    #[cfg(feature = "tokio")]
+    Reader(Arc<tokio::sync::Mutex<Pin<Box<dyn tokio::io::AsyncRead + Send>>>>),
+}
+
+impl InputSource {
+    pub fn bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        InputSource::Bytes(bytes.into().into())
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn reader<R: tokio::io::AsyncRead + Send + 'static>(reader: R) -> Self {
+        InputSource::Reader(Arc::new(tokio::sync::Mutex::new(Box::pin(reader))))
+    }
+
+    fn is_captured(&self) -> bool {
+        !matches!(self, InputSource::Inherit)
+    }
+}
+
+impl fmt::Debug for InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputSource::Inherit => write!(f, "Inherit"),
+            InputSource::Empty => write!(f, "Empty"),
+            InputSource::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            #[cfg(feature = "tokio")]
+            InputSource::Reader(_) => write!(f, "Reader(..)"),
+        }
+    }
+}
+
+/// An [`InputSource`] along with the read position in [`InputSource::Bytes`], and the
+/// bytes read ahead to wait for readiness.
+#[derive(Debug, Default)]
+pub(crate) struct InputState {
+    source: InputSource,
+    pos: usize,
+    peeked: Vec<u8>,
+}
+
+impl InputState {
+    /// Reads at most `max` bytes; an empty result is end of file.
+    pub(crate) async fn read(&mut self, max: usize) -> std::io::Result<Vec<u8>> {
+        if !self.peeked.is_empty() {
+            let n = max.min(self.peeked.len());
+            return Ok(self.peeked.drain(..n).collect());
+        }
+        self.read_source(max).await
+    }
+
+    /// Waits until a read would not block, and returns how many bytes it would return
+    /// at least; 0 is end of file.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn ready(&mut self) -> std::io::Result<usize> {
+        if let InputSource::Bytes(bytes) = &self.source {
+            return Ok(bytes.len() - self.pos);
+        }
+        if self.peeked.is_empty() {
+            self.peeked = self.read_source(MAX_READ).await?;
+        }
+        Ok(self.peeked.len())
+    }
+
+    async fn read_source(&mut self, max: usize) -> std::io::Result<Vec<u8>> {
+        match &self.source {
+            // the async WASI module reads the host's stdin itself
+            #[cfg(feature = "async-wasi")]
+            InputSource::Inherit => {
+                use tokio::io::AsyncReadExt;
+
+                let mut buf = vec![0; max.min(MAX_READ)];
+                let n = tokio::io::stdin().read(&mut buf).await?;
+                buf.truncate(n);
+                Ok(buf)
+            }
+            #[cfg(not(feature = "async-wasi"))]
+            InputSource::Inherit => Ok(vec![]),
+            InputSource::Empty => Ok(vec![]),
+            InputSource::Bytes(bytes) => {
+                let end = bytes.len().min(self.pos + max);
+                let data = bytes[self.pos..end].to_vec();
+                self.pos = end;
+                Ok(data)
+            }
+            #[cfg(feature = "tokio")]
+            InputSource::Reader(reader) => {
+                use tokio::io::AsyncReadExt;
+
+                let mut buf = vec![0; max.min(MAX_READ)];
+                let n = reader.lock().await.read(&mut buf).await?;
+                buf.truncate(n);
+                Ok(buf)
+            }
+        }
+    }
+}
+
+/// An [`OutputSink`] along with the unterminated line of a [`OutputSink::Lines`] sink.
+#[derive(Debug, Default)]
+pub(crate) struct OutputState {
+    sink: OutputSink,
+    line: Vec<u8>,
+}
+
+impl OutputState {
+    /// Returns `true` if writes should go to the host process's stream.
+    #[cfg(feature = "async-wasi")]
+    pub(crate) fn is_inherited(&self) -> bool {
+        !self.sink.is_captured()
+    }
+
+    pub(crate) async fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match &self.sink {
+            // written by the WASI module to the host process's stream
+            OutputSink::Inherit | OutputSink::Discard => {}
+            OutputSink::Buffer(buf) => buf.lock().unwrap().extend_from_slice(data),
+            OutputSink::Lines(f) => {
+                self.line.extend_from_slice(data);
+                while let Some(pos) = self.line.iter().position(|b| *b == b'\n') {
+                    let line = self.line.drain(..=pos).collect::<Vec<u8>>();
+                    f(&line[..pos]);
+                }
+            }
+            #[cfg(feature = "tokio")]
+            OutputSink::Writer(writer) => {
+                use tokio::io::AsyncWriteExt;
+
+                let mut writer = writer.lock().await;
+                writer.write_all(data).await?;
+                writer.flush().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OutputState {
+    fn drop(&mut self) {
+        if let OutputSink::Lines(f) = &self.sink {
+            if !self.line.is_empty() {
+                f(&self.line);
+            }
+        }
+    }
+}
+
+/// The stdio sources and sinks of a linker.
+#[derive(Debug, Default)]
+pub(crate) struct Stdio {
+    pub(crate) stdin: InputState,
+    pub(crate) stdout: OutputState,
+    pub(crate) stderr: OutputState,
+}
+
+impl Stdio {
+    pub(crate) fn new(stdin: InputSource, stdout: OutputSink, stderr: OutputSink) -> Self {
+        Stdio {
+            stdin: InputState {
+                source: stdin,
+                pos: 0,
+                peeked: vec![],
+            },
+            stdout: OutputState {
+                sink: stdout,
+                line: vec![],
+            },
+            stderr: OutputState {
+                sink: stderr,
+                line: vec![],
+            },
+        }
+    }
+
+    /// Returns the output fds whose writes go through [`fd_write`].
+    pub(crate) fn captured_output_fds(&self) -> Vec<i32> {
+        let mut fds = vec![];
+        if self.stdout.sink.is_captured() {
+            fds.push(1);
+        }
+        if self.stderr.sink.is_captured() {
+            fds.push(2);
+        }
+        fds
+    }
+
+    /// Returns `true` if reads of fd 0 go through [`fd_read`].
+    pub(crate) fn captures_input(&self) -> bool {
+        self.stdin.source.is_captured()
+    }
+
+    /// Returns `true` if `other` captures the same streams, so that it can replace these
+    /// sinks without redirecting other WASI calls.
+    pub(crate) fn captures_like(&self, other: &Stdio) -> bool {
+        self.captured_output_fds() == other.captured_output_fds()
+            && self.captures_input() == other.captures_input()
+    }
+}
+
+pub(crate) fn i32_args<const N: usize>(args: &[WasmVal]) -> Result<[usize; N], WasmEdgeError> {
+    let mut r = [0; N];
+    if args.len() != N {
+        return Err(WasmEdgeError::Func(FuncError::Type));
+    }
+    for (idx, arg) in args.iter().enumerate() {
+        match arg {
+            WasmVal::I32(v) => r[idx] = *v as u32 as usize,
+            _ => return Err(WasmEdgeError::Func(FuncError::Type)),
+        }
+    }
+    Ok(r)
+}
+
+/// `fd_write(fd, iovs, iovs_len, nwritten) -> errno` for the captured output fds.
+///
+/// A call writes at most [`MAX_READ`] bytes, leaving the rest to the next one.
+pub(crate) fn fd_write(linker: &mut AsyncLinker, args: Vec<WasmVal>) -> SendResultFuture<'_> {
+    Box::new(async move {
+        let [fd, iovs, iovs_len, nwritten] = i32_args::<4>(&args)?;
+        errno(write_captured(linker, fd, iovs, iovs_len, nwritten).await)
+    })
+}
+
+async fn write_captured(
+    linker: &mut AsyncLinker,
+    fd: usize,
+    iovs: usize,
+    iovs_len: usize,
+    nwritten: usize,
+) -> WasiResult<()> {
+    let iovecs = read_iovecs(linker, iovs, iovs_len)?;
+    let data = gather(linker, &truncate_iovecs(&iovecs, MAX_READ))?;
+
+    let out = match fd {
+        1 => &mut linker.stdio.stdout,
+        2 => &mut linker.stdio.stderr,
+        _ => return Err(Errno::BADF),
+    };
+    out.write(&data).await?;
+
+    write_u32(linker, nwritten, data.len() as u32)
+}
+
+/// `fd_read(fd, iovs, iovs_len, nread) -> errno` for the captured stdin.
+pub(crate) fn fd_read(linker: &mut AsyncLinker, args: Vec<WasmVal>) -> SendResultFuture<'_> {
+    Box::new(async move {
+        let [fd, iovs, iovs_len, nread] = i32_args::<4>(&args)?;
+        errno(read_captured(linker, fd, iovs, iovs_len, nread).await)
+    })
+}
+
+async fn read_captured(
+    linker: &mut AsyncLinker,
+    fd: usize,
+    iovs: usize,
+    iovs_len: usize,
+    nread: usize,
+) -> WasiResult<()> {
+    if fd != 0 {
+        return Err(Errno::BADF);
+    }
+    let iovecs = read_iovecs(linker, iovs, iovs_len)?;
+
+    let data = linker.stdio.stdin.read(read_len(&iovecs)).await?;
+
+    scatter(linker, &iovecs, &data)?;
+    write_u32(linker, nread, data.len() as u32)
+}
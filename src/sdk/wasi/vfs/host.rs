@@ -0,0 +1,375 @@
+//! A tree backed by a host directory.
+
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use super::{not_found, DirEntry, FileType, FsError, OpenOptions, Opened, Stat, VfsFile};
+
+pub(crate) struct HostFs {
+    /// The canonical path of the directory; nothing outside of it is reachable.
+    root: PathBuf,
+}
+
+impl HostFs {
+    pub(crate) fn new(dir: &Path) -> io::Result<Self> {
+        let root = dir.canonicalize()?;
+        if !root.is_dir() {
+            return Err(FsError::NotADirectory.into());
+        }
+        Ok(HostFs { root })
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the host path of `path`, refusing it if a symlink along it leads outside
+    /// of the tree. The path returned has no symlink but, with `follow` unset, in its last
+    /// component, which may so be removed or read.
+    ///
+    /// Symlinks in the last component are followed here rather than by the host, one
+    /// at a time, so that each target is confined like the path itself.
+    async fn resolve(&self, path: &[String], follow: bool) -> io::Result<PathBuf> {
+        let mut host = path
+            .iter()
+            .fold(self.root.clone(), |host, name| host.join(name));
+        for _ in 0..MAX_SYMLINKS {
+            if host == self.root {
+                return Ok(host);
+            }
+            let name = match host.file_name() {
+                Some(name) => name.to_os_string(),
+                // the root, or a symlink target ending with `..`
+                None => return self.confine(&host).await,
+            };
+            let parent = host.parent().ok_or_else(not_found)?;
+            let real = self.confine(parent).await?.join(name);
+            if !follow {
+                return Ok(real);
+            }
+            match tokio::fs::symlink_metadata(&real).await {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    let target = tokio::fs::read_link(&real).await?;
+                    if target.is_absolute() {
+                        return Err(FsError::Escapes.into());
+                    }
+                    host = real.parent().ok_or_else(not_found)?.join(target);
+                }
+                _ => return Ok(real),
+            }
+        }
+        Err(FsError::Loop.into())
+    }
+
+    /// Returns the real path of `host`, or of its deepest existing ancestor followed by
+    /// the rest of `host`, if that is inside of the tree; what does not exist yet cannot
+    /// escape.
+    async fn confine(&self, host: &Path) -> io::Result<PathBuf> {
+        let mut probe = host.to_path_buf();
+        let mut rest = vec![];
+        let real = loop {
+            match tokio::fs::canonicalize(&probe).await {
+                Ok(real) => break real,
+                Err(_) => {
+                    match probe.file_name() {
+                        Some(name) => rest.push(name.to_os_string()),
+                        None => return Err(FsError::Escapes.into()),
+                    }
+                    probe.pop();
+                }
+            }
+        };
+        if !real.starts_with(&self.root) {
+            return Err(FsError::Escapes.into());
+        }
+        Ok(rest
+            .into_iter()
+            .rev()
+            .fold(real, |real, name| real.join(name)))
+    }
+
+    pub(crate) async fn stat(&self, path: &[String], follow: bool) -> io::Result<Stat> {
+        let host = self.resolve(path, follow).await?;
+        let meta = if follow {
+            tokio::fs::metadata(host).await?
+        } else {
+            tokio::fs::symlink_metadata(host).await?
+        };
+        Ok(stat_of(&meta))
+    }
+
+    pub(crate) async fn open(&self, path: &[String], opts: &OpenOptions) -> io::Result<Opened> {
+        let host = self.resolve(path, opts.follow).await?;
+        // `host` has no symlink left to follow, unless it was swapped in since
+        match tokio::fs::symlink_metadata(&host).await {
+            Ok(meta) if meta.is_dir() => {
+                if opts.writes() {
+                    return Err(FsError::IsADirectory.into());
+                }
+                return Ok(Opened::Dir);
+            }
+            Ok(meta) if meta.file_type().is_symlink() => return Err(FsError::Loop.into()),
+            Ok(_) if opts.directory => return Err(FsError::NotADirectory.into()),
+            Err(e) if opts.directory => return Err(e),
+            _ => {}
+        }
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options
+            .read(opts.read || !opts.writes())
+            .write(opts.writes() && !opts.append)
+            .append(opts.append)
+            .create(opts.create)
+            .create_new(opts.create_new)
+            .truncate(opts.truncate);
+        #[cfg(unix)]
+        options.custom_flags(libc::O_NOFOLLOW);
+        let file = options.open(&host).await?;
+
+        // a directory along `host` may have been swapped for a symlink since it was
+        // resolved, so check that the file opened is the one inside of the tree
+        let opened = file.metadata().await?;
+        let real = tokio::fs::canonicalize(&host).await?;
+        let expected = tokio::fs::metadata(&real).await?;
+        if !real.starts_with(&self.root) || !same_file(&opened, &expected) {
+            return Err(FsError::Escapes.into());
+        }
+        Ok(Opened::File(VfsFile::Host(file)))
+    }
+
+    pub(crate) async fn read_dir(&self, path: &[String]) -> io::Result<Vec<DirEntry>> {
+        let host = self.resolve(path, true).await?;
+        let mut dir = tokio::fs::read_dir(host).await?;
+        let mut entries = vec![];
+        while let Some(entry) = dir.next_entry().await? {
+            let filetype = entry
+                .file_type()
+                .await
+                .map_or(FileType::Unknown, |ty| filetype_of(&ty));
+            #[cfg(unix)]
+            let ino = entry.ino();
+            #[cfg(not(unix))]
+            let ino = 0;
+            entries.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                filetype,
+                ino,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Returns the contents of the file at `path`.
+    pub(crate) async fn read_file(&self, path: &[String]) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.resolve(path, true).await?).await
+    }
+
+    pub(crate) async fn create_dir(&self, path: &[String]) -> io::Result<()> {
+        tokio::fs::create_dir(self.resolve(path, false).await?).await
+    }
+
+    pub(crate) async fn remove_dir(&self, path: &[String]) -> io::Result<()> {
+        tokio::fs::remove_dir(self.resolve(path, false).await?).await
+    }
+
+    pub(crate) async fn remove_file(&self, path: &[String]) -> io::Result<()> {
+        let host = self.resolve(path, false).await?;
+        if tokio::fs::symlink_metadata(&host).await?.is_dir() {
+            return Err(FsError::IsADirectory.into());
+        }
+        tokio::fs::remove_file(host).await
+    }
+
+    pub(crate) async fn rename(&self, from: &[String], to: &[String]) -> io::Result<()> {
+        let from = self.resolve(from, false).await?;
+        let to = self.resolve(to, false).await?;
+        tokio::fs::rename(from, to).await
+    }
+
+    pub(crate) async fn hard_link(
+        &self,
+        from: &[String],
+        to: &[String],
+        follow: bool,
+    ) -> io::Result<()> {
+        let from = self.resolve(from, follow).await?;
+        let to = self.resolve(to, false).await?;
+        tokio::fs::hard_link(from, to).await
+    }
+
+    /// Creates a symlink to `target`, stored as given. Targets leading outside of the
+    /// tree are refused, and following a link is confined like any other path.
+    pub(crate) async fn symlink(&self, target: &str, link: &[String]) -> io::Result<()> {
+        if !stays_inside(target, link.len().saturating_sub(1)) {
+            return Err(FsError::Escapes.into());
+        }
+        let link = self.resolve(link, false).await?;
+        #[cfg(unix)]
+        {
+            tokio::fs::symlink(target, link).await
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = link;
+            Err(FsError::Unsupported.into())
+        }
+    }
+
+    pub(crate) async fn read_link(&self, path: &[String]) -> io::Result<String> {
+        let host = self.resolve(path, false).await?;
+        let target = tokio::fs::read_link(host).await?;
+        Ok(target.to_string_lossy().into_owned())
+    }
+}
+
+/// How many symlinks [`HostFs::resolve`] follows before giving up with `ELOOP`.
+const MAX_SYMLINKS: usize = 32;
+
+/// Returns `true` if the relative symlink `target`, in a directory `depth` levels below
+/// the root, leads to a path inside of the tree when read lexically.
+fn stays_inside(target: &str, depth: usize) -> bool {
+    let target = Path::new(target);
+    if target.is_absolute() {
+        return false;
+    }
+    let mut depth = depth;
+    for component in target.components() {
+        match component {
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(up) => depth = up,
+                None => return false,
+            },
+            Component::CurDir => {}
+            Component::Normal(_) => depth += 1,
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Returns `true` if both are the metadata of the same file.
+fn same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        a.dev() == b.dev() && a.ino() == b.ino()
+    }
+    #[cfg(not(unix))]
+    {
+        a.file_type() == b.file_type() && a.len() == b.len()
+    }
+}
+
+pub(crate) fn stat_of(meta: &std::fs::Metadata) -> Stat {
+    let nanos = |t: io::Result<std::time::SystemTime>| {
+        t.ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos() as u64)
+    };
+    let mut stat = Stat {
+        dev: 0,
+        ino: 0,
+        filetype: filetype_of(&meta.file_type()),
+        nlink: 1,
+        size: meta.len(),
+        atim: nanos(meta.accessed()),
+        mtim: nanos(meta.modified()),
+        ctim: nanos(meta.created()),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        stat.dev = meta.dev();
+        stat.ino = meta.ino();
+        stat.nlink = meta.nlink();
+        stat.ctim = (meta.ctime() as u64) * 1_000_000_000 + meta.ctime_nsec() as u64;
+    }
+    stat
+}
+
+fn filetype_of(ty: &std::fs::FileType) -> FileType {
+    if ty.is_dir() {
+        FileType::Directory
+    } else if ty.is_file() {
+        FileType::RegularFile
+    } else if ty.is_symlink() {
+        FileType::SymbolicLink
+    } else {
+        FileType::Unknown
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::super::tests::{block_on, path, TempDir};
+    use super::*;
+
+    fn escapes<T>(r: io::Result<T>) -> bool {
+        match r {
+            Ok(_) => false,
+            Err(e) => FsError::of(&e) == Some(FsError::Escapes),
+        }
+    }
+
+    #[test]
+    fn symlinks_out_of_the_tree_escape() {
+        block_on(async {
+            let dir = TempDir::new();
+            dir.write("outside/secret", "secret");
+            dir.write("tree/inside", "inside");
+            let tree = dir.path().join("tree");
+            symlink("../outside/secret", tree.join("relative")).unwrap();
+            symlink(dir.path().join("outside/secret"), tree.join("absolute")).unwrap();
+            symlink("..", tree.join("up")).unwrap();
+            symlink("inside", tree.join("sibling")).unwrap();
+            let fs = HostFs::new(&tree).unwrap();
+            let read = OpenOptions {
+                read: true,
+                follow: true,
+                ..OpenOptions::default()
+            };
+
+            for link in ["relative", "absolute"] {
+                assert!(escapes(fs.resolve(&path(link), true).await));
+                assert!(escapes(fs.stat(&path(link), true).await));
+                assert!(escapes(fs.open(&path(link), &read).await));
+                // the link itself may still be read
+                assert!(fs.resolve(&path(link), false).await.is_ok());
+            }
+            assert!(escapes(fs.confine(&dir.path().join("outside")).await));
+            assert!(escapes(fs.resolve(&path("up/outside/secret"), false).await));
+            assert!(escapes(fs.open(&path("up/outside/secret"), &read).await));
+
+            assert!(fs.open(&path("sibling"), &read).await.is_ok());
+        })
+    }
+
+    #[test]
+    fn missing_paths_are_confined_by_their_existing_ancestors() {
+        block_on(async {
+            let dir = TempDir::new();
+            std::fs::create_dir_all(dir.path().join("tree/sub")).unwrap();
+            let tree = dir.path().join("tree");
+            let fs = HostFs::new(&tree).unwrap();
+            let root = tree.canonicalize().unwrap();
+
+            assert_eq!(
+                fs.confine(&tree.join("sub/new/file")).await.unwrap(),
+                root.join("sub/new/file")
+            );
+            assert!(escapes(fs.confine(&tree.join("../new")).await));
+        })
+    }
+
+    #[test]
+    fn stays_inside_reads_targets_lexically() {
+        assert!(stays_inside("a/../b", 0));
+        assert!(stays_inside("../b", 1));
+        assert!(!stays_inside("../b", 0));
+        assert!(!stays_inside("/etc/passwd", 3));
+    }
+}
@@ -0,0 +1,387 @@
+//! A tree kept in memory.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, SeekFrom},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::{
+    already_exists, not_found, now, DirEntry, FileType, FsError, OpenOptions, Opened, Stat, VfsFile,
+};
+
+/// Inode numbers of memory nodes, unique across trees.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+fn next_ino() -> u64 {
+    NEXT_INO.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+struct FileData {
+    bytes: Vec<u8>,
+    ino: u64,
+    atim: u64,
+    mtim: u64,
+    ctim: u64,
+}
+
+impl FileData {
+    fn new(bytes: Vec<u8>) -> Self {
+        let now = now();
+        FileData {
+            bytes,
+            ino: next_ino(),
+            atim: now,
+            mtim: now,
+            ctim: now,
+        }
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: self.ino,
+            filetype: FileType::RegularFile,
+            nlink: 1,
+            size: self.bytes.len() as u64,
+            atim: self.atim,
+            mtim: self.mtim,
+            ctim: self.ctim,
+        }
+    }
+
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        let end = offset + data.len();
+        if self.bytes.len() < end {
+            self.bytes.resize(end, 0);
+        }
+        self.bytes[offset..end].copy_from_slice(data);
+        self.mtim = now();
+    }
+}
+
+struct MemDir {
+    entries: BTreeMap<String, Node>,
+    ino: u64,
+    mtim: u64,
+}
+
+impl MemDir {
+    fn new() -> Self {
+        MemDir {
+            entries: BTreeMap::new(),
+            ino: next_ino(),
+            mtim: now(),
+        }
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: self.ino,
+            filetype: FileType::Directory,
+            nlink: 1,
+            size: 0,
+            atim: self.mtim,
+            mtim: self.mtim,
+            ctim: self.mtim,
+        }
+    }
+}
+
+enum Node {
+    /// Shared with the open files, which keep working after the file is removed.
+    File(Arc<Mutex<FileData>>),
+    Dir(MemDir),
+}
+
+impl Node {
+    fn stat(&self) -> Stat {
+        match self {
+            Node::File(data) => data.lock().unwrap().stat(),
+            Node::Dir(dir) => dir.stat(),
+        }
+    }
+}
+
+pub(crate) struct MemFs {
+    root: Mutex<MemDir>,
+}
+
+/// Splits `path` into its parent and last component.
+fn split_last(path: &[String]) -> io::Result<(&[String], &str)> {
+    match path.split_last() {
+        Some((name, parent)) => Ok((parent, name.as_str())),
+        None => Err(FsError::Busy.into()),
+    }
+}
+
+fn dir_mut<'a>(root: &'a mut MemDir, path: &[String]) -> io::Result<&'a mut MemDir> {
+    let mut dir = root;
+    for name in path {
+        dir = match dir.entries.get_mut(name) {
+            Some(Node::Dir(child)) => child,
+            Some(Node::File(_)) => return Err(FsError::NotADirectory.into()),
+            None => return Err(not_found()),
+        };
+    }
+    Ok(dir)
+}
+
+impl MemFs {
+    pub(crate) fn new() -> Self {
+        MemFs {
+            root: Mutex::new(MemDir::new()),
+        }
+    }
+
+    pub(crate) fn exists(&self, path: &[String]) -> bool {
+        self.stat(path).is_ok()
+    }
+
+    pub(crate) fn stat(&self, path: &[String]) -> io::Result<Stat> {
+        let mut root = self.root.lock().unwrap();
+        if path.is_empty() {
+            return Ok(root.stat());
+        }
+        let (parent, name) = split_last(path)?;
+        let dir = dir_mut(&mut root, parent)?;
+        dir.entries.get(name).map(Node::stat).ok_or_else(not_found)
+    }
+
+    pub(crate) fn open(&self, path: &[String], opts: &OpenOptions) -> io::Result<Opened> {
+        let mut root = self.root.lock().unwrap();
+        if path.is_empty() {
+            return match opts.writes() {
+                true => Err(FsError::IsADirectory.into()),
+                false => Ok(Opened::Dir),
+            };
+        }
+        let (parent, name) = split_last(path)?;
+        let dir = dir_mut(&mut root, parent)?;
+
+        let data = match dir.entries.get(name) {
+            Some(Node::Dir(_)) if opts.writes() => return Err(FsError::IsADirectory.into()),
+            Some(Node::Dir(_)) => return Ok(Opened::Dir),
+            Some(Node::File(_)) if opts.directory => return Err(FsError::NotADirectory.into()),
+            Some(Node::File(_)) if opts.create_new => return Err(already_exists()),
+            Some(Node::File(data)) => {
+                if opts.truncate {
+                    let mut data = data.lock().unwrap();
+                    data.bytes.clear();
+                    data.mtim = now();
+                }
+                data.clone()
+            }
+            None if opts.create && !opts.directory => {
+                let data = Arc::new(Mutex::new(FileData::new(vec![])));
+                dir.entries
+                    .insert(name.to_string(), Node::File(data.clone()));
+                dir.mtim = now();
+                data
+            }
+            None => return Err(not_found()),
+        };
+        Ok(Opened::File(VfsFile::Memory(MemFile {
+            data,
+            pos: 0,
+            append: opts.append,
+        })))
+    }
+
+    pub(crate) fn read_dir(&self, path: &[String]) -> io::Result<Vec<DirEntry>> {
+        let mut root = self.root.lock().unwrap();
+        let dir = dir_mut(&mut root, path)?;
+        Ok(dir
+            .entries
+            .iter()
+            .map(|(name, node)| {
+                let stat = node.stat();
+                DirEntry {
+                    name: name.clone(),
+                    filetype: stat.filetype,
+                    ino: stat.ino,
+                }
+            })
+            .collect())
+    }
+
+    pub(crate) fn create_dir(&self, path: &[String]) -> io::Result<()> {
+        let mut root = self.root.lock().unwrap();
+        let (parent, name) = split_last(path)?;
+        let dir = dir_mut(&mut root, parent)?;
+        if dir.entries.contains_key(name) {
+            return Err(already_exists());
+        }
+        dir.entries
+            .insert(name.to_string(), Node::Dir(MemDir::new()));
+        dir.mtim = now();
+        Ok(())
+    }
+
+    pub(crate) fn create_dir_all(&self, path: &[String]) -> io::Result<()> {
+        let mut root = self.root.lock().unwrap();
+        let mut dir = &mut *root;
+        for name in path {
+            let node = dir
+                .entries
+                .entry(name.clone())
+                .or_insert_with(|| Node::Dir(MemDir::new()));
+            dir = match node {
+                Node::Dir(child) => child,
+                Node::File(_) => return Err(FsError::NotADirectory.into()),
+            };
+        }
+        Ok(())
+    }
+
+    pub(crate) fn insert_file(&self, path: &[String], bytes: Vec<u8>) -> io::Result<()> {
+        let (parent, name) = split_last(path)?;
+        self.create_dir_all(parent)?;
+        let mut root = self.root.lock().unwrap();
+        let dir = dir_mut(&mut root, parent)?;
+        match dir.entries.get(name) {
+            Some(Node::Dir(_)) => Err(FsError::IsADirectory.into()),
+            Some(Node::File(data)) => {
+                let mut data = data.lock().unwrap();
+                data.bytes = bytes;
+                data.mtim = now();
+                Ok(())
+            }
+            None => {
+                let data = Arc::new(Mutex::new(FileData::new(bytes)));
+                dir.entries.insert(name.to_string(), Node::File(data));
+                dir.mtim = now();
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn remove_dir(&self, path: &[String]) -> io::Result<()> {
+        let mut root = self.root.lock().unwrap();
+        let (parent, name) = split_last(path)?;
+        let dir = dir_mut(&mut root, parent)?;
+        match dir.entries.get(name) {
+            Some(Node::Dir(child)) if !child.entries.is_empty() => {
+                return Err(FsError::DirectoryNotEmpty.into())
+            }
+            Some(Node::Dir(_)) => {}
+            Some(Node::File(_)) => return Err(FsError::NotADirectory.into()),
+            None => return Err(not_found()),
+        }
+        dir.entries.remove(name);
+        dir.mtim = now();
+        Ok(())
+    }
+
+    pub(crate) fn remove_file(&self, path: &[String]) -> io::Result<()> {
+        let mut root = self.root.lock().unwrap();
+        let (parent, name) = split_last(path)?;
+        let dir = dir_mut(&mut root, parent)?;
+        match dir.entries.get(name) {
+            Some(Node::File(_)) => {}
+            Some(Node::Dir(_)) => return Err(FsError::IsADirectory.into()),
+            None => return Err(not_found()),
+        }
+        dir.entries.remove(name);
+        dir.mtim = now();
+        Ok(())
+    }
+
+    pub(crate) fn rename(&self, from: &[String], to: &[String]) -> io::Result<()> {
+        if to.starts_with(from) && to.len() > from.len() {
+            // a directory cannot move into itself
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        let mut root = self.root.lock().unwrap();
+        let (from_parent, from_name) = split_last(from)?;
+        let (to_parent, to_name) = split_last(to)?;
+
+        // check the destination before detaching anything
+        let moving_dir = match dir_mut(&mut root, from_parent)?.entries.get(from_name) {
+            Some(node) => matches!(node, Node::Dir(_)),
+            None => return Err(not_found()),
+        };
+        match dir_mut(&mut root, to_parent)?.entries.get(to_name) {
+            Some(Node::Dir(dir)) if moving_dir && !dir.entries.is_empty() => {
+                return Err(FsError::DirectoryNotEmpty.into())
+            }
+            Some(Node::Dir(_)) if !moving_dir => return Err(FsError::IsADirectory.into()),
+            Some(Node::File(_)) if moving_dir => return Err(FsError::NotADirectory.into()),
+            _ => {}
+        }
+
+        let from_dir = dir_mut(&mut root, from_parent)?;
+        let node = from_dir.entries.remove(from_name).ok_or_else(not_found)?;
+        from_dir.mtim = now();
+        let to_dir = dir_mut(&mut root, to_parent)?;
+        to_dir.entries.insert(to_name.to_string(), node);
+        to_dir.mtim = now();
+        Ok(())
+    }
+}
+
+/// An open file of a memory tree.
+#[derive(Debug)]
+pub(crate) struct MemFile {
+    data: Arc<Mutex<FileData>>,
+    pos: u64,
+    append: bool,
+}
+
+impl MemFile {
+    pub(crate) fn read(&mut self, max: usize) -> Vec<u8> {
+        let chunk = self.pread(self.pos, max);
+        self.pos += chunk.len() as u64;
+        chunk
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        let mut data = self.data.lock().unwrap();
+        if self.append {
+            self.pos = data.bytes.len() as u64;
+        }
+        data.write_at(self.pos as usize, bytes);
+        self.pos += bytes.len() as u64;
+    }
+
+    pub(crate) fn pread(&self, offset: u64, max: usize) -> Vec<u8> {
+        let mut data = self.data.lock().unwrap();
+        data.atim = now();
+        let start = (offset as usize).min(data.bytes.len());
+        let end = start + max.min(data.bytes.len() - start);
+        data.bytes[start..end].to_vec()
+    }
+
+    pub(crate) fn pwrite(&self, offset: u64, bytes: &[u8]) {
+        self.data.lock().unwrap().write_at(offset as usize, bytes);
+    }
+
+    pub(crate) fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.data.lock().unwrap().bytes.len() as i64;
+        let pos = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::Current(delta) => self.pos as i64 + delta,
+            SeekFrom::End(delta) => len + delta,
+        };
+        if pos < 0 {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+
+    pub(crate) fn stat(&self) -> Stat {
+        self.data.lock().unwrap().stat()
+    }
+
+    pub(crate) fn set_len(&self, len: u64) {
+        let mut data = self.data.lock().unwrap();
+        data.bytes.resize(len as usize, 0);
+        data.mtim = now();
+    }
+}
@@ -0,0 +1,661 @@
+//! File trees a guest of the async WASI module can be given as preopens.
+//!
+//! A [`Vfs`] is backed by a host directory, by memory, or by both: an overlay reads the
+//! host directory and keeps the guest's changes in memory. Paths handed to the backends
+//! are already split into components and confined to the tree.
+
+mod host;
+mod memory;
+mod overlay;
+
+use std::{
+    fmt,
+    io::{self, SeekFrom},
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use host::HostFs;
+use memory::{MemFile, MemFs};
+use overlay::OverlayFs;
+
+/// A file tree, mounted into the guest with
+/// [`WasiConfig::preopen_vfs`](super::WasiConfig::preopen_vfs).
+///
+/// Clones share the tree, so the host can seed it before the guest runs and read what
+/// the guest wrote afterwards.
+#[derive(Clone)]
+pub struct Vfs {
+    backend: Arc<Backend>,
+    read_only: bool,
+}
+
+enum Backend {
+    Host(HostFs),
+    Memory(MemFs),
+    Overlay(OverlayFs),
+}
+
+impl Vfs {
+    /// Maps the host directory `dir`, as [`WasiConfig::preopen`](super::WasiConfig::preopen)
+    /// does.
+    pub fn host(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(Backend::Host(HostFs::new(dir.as_ref())?)))
+    }
+
+    /// An empty tree kept in memory.
+    pub fn memory() -> Self {
+        Self::new(Backend::Memory(MemFs::new()))
+    }
+
+    /// The host directory `dir`, with every change the guest makes kept in memory.
+    /// The directory itself is never written.
+    pub fn overlay(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(Backend::Overlay(OverlayFs::new(dir.as_ref())?)))
+    }
+
+    fn new(backend: Backend) -> Self {
+        Vfs {
+            backend: Arc::new(backend),
+            read_only: false,
+        }
+    }
+
+    /// Refuses every change the guest makes through this handle. Other clones keep
+    /// their access.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Adds a file with `contents` at the `/`-separated `path`, creating its parent
+    /// directories and replacing an existing file.
+    ///
+    /// Host trees are populated on disk instead, so this fails for them.
+    pub fn insert_file(&self, path: &str, contents: impl Into<Vec<u8>>) -> io::Result<()> {
+        let path = split(path)?;
+        match &*self.backend {
+            Backend::Host(_) => Err(FsError::Unsupported.into()),
+            Backend::Memory(fs) => fs.insert_file(&path, contents.into()),
+            Backend::Overlay(fs) => fs.insert_file(&path, contents.into()),
+        }
+    }
+
+    /// Creates the directory at the `/`-separated `path` along with its parents.
+    ///
+    /// Host trees are populated on disk instead, so this fails for them.
+    pub fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        let path = split(path)?;
+        match &*self.backend {
+            Backend::Host(_) => Err(FsError::Unsupported.into()),
+            Backend::Memory(fs) => fs.create_dir_all(&path),
+            Backend::Overlay(fs) => fs.create_dir_all(&path),
+        }
+    }
+
+    /// Returns the contents of the file at the `/`-separated `path`, as the guest sees it.
+    pub async fn read_file(&self, path: &str) -> io::Result<Vec<u8>> {
+        let path = split(path)?;
+        let opts = OpenOptions {
+            read: true,
+            follow: true,
+            ..OpenOptions::default()
+        };
+        match self.open(&path, &opts).await? {
+            Opened::File(mut file) => file.read_up_to(usize::MAX).await,
+            Opened::Dir => Err(FsError::IsADirectory.into()),
+        }
+    }
+
+    /// Returns `true` if both handles share the same tree.
+    pub(crate) fn same_tree(&self, other: &Vfs) -> bool {
+        Arc::ptr_eq(&self.backend, &other.backend)
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            return Err(FsError::ReadOnly.into());
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn stat(&self, path: &[String], follow: bool) -> io::Result<Stat> {
+        match &*self.backend {
+            Backend::Host(fs) => fs.stat(path, follow).await,
+            Backend::Memory(fs) => fs.stat(path),
+            Backend::Overlay(fs) => fs.stat(path, follow).await,
+        }
+    }
+
+    pub(crate) async fn open(&self, path: &[String], opts: &OpenOptions) -> io::Result<Opened> {
+        if opts.writes() {
+            self.check_writable()?;
+        }
+        match &*self.backend {
+            Backend::Host(fs) => fs.open(path, opts).await,
+            Backend::Memory(fs) => fs.open(path, opts),
+            Backend::Overlay(fs) => fs.open(path, opts).await,
+        }
+    }
+
+    pub(crate) async fn read_dir(&self, path: &[String]) -> io::Result<Vec<DirEntry>> {
+        let mut entries = match &*self.backend {
+            Backend::Host(fs) => fs.read_dir(path).await?,
+            Backend::Memory(fs) => fs.read_dir(path)?,
+            Backend::Overlay(fs) => fs.read_dir(path).await?,
+        };
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    pub(crate) async fn create_dir(&self, path: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        match &*self.backend {
+            Backend::Host(fs) => fs.create_dir(path).await,
+            Backend::Memory(fs) => fs.create_dir(path),
+            Backend::Overlay(fs) => fs.create_dir(path).await,
+        }
+    }
+
+    pub(crate) async fn remove_dir(&self, path: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        if path.is_empty() {
+            return Err(FsError::Busy.into());
+        }
+        match &*self.backend {
+            Backend::Host(fs) => fs.remove_dir(path).await,
+            Backend::Memory(fs) => fs.remove_dir(path),
+            Backend::Overlay(fs) => fs.remove_dir(path).await,
+        }
+    }
+
+    pub(crate) async fn remove_file(&self, path: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        match &*self.backend {
+            Backend::Host(fs) => fs.remove_file(path).await,
+            Backend::Memory(fs) => fs.remove_file(path),
+            Backend::Overlay(fs) => fs.remove_file(path).await,
+        }
+    }
+
+    /// Moves `from` to `to`, both in this tree.
+    pub(crate) async fn rename(&self, from: &[String], to: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        if from.is_empty() || to.is_empty() {
+            return Err(FsError::Busy.into());
+        }
+        match &*self.backend {
+            Backend::Host(fs) => fs.rename(from, to).await,
+            Backend::Memory(fs) => fs.rename(from, to),
+            Backend::Overlay(fs) => fs.rename(from, to).await,
+        }
+    }
+
+    pub(crate) async fn hard_link(
+        &self,
+        from: &[String],
+        to: &[String],
+        follow: bool,
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        match &*self.backend {
+            Backend::Host(fs) => fs.hard_link(from, to, follow).await,
+            Backend::Memory(_) | Backend::Overlay(_) => Err(FsError::Unsupported.into()),
+        }
+    }
+
+    pub(crate) async fn symlink(&self, target: &str, link: &[String]) -> io::Result<()> {
+        self.check_writable()?;
+        match &*self.backend {
+            Backend::Host(fs) => fs.symlink(target, link).await,
+            Backend::Memory(_) | Backend::Overlay(_) => Err(FsError::Unsupported.into()),
+        }
+    }
+
+    pub(crate) async fn read_link(&self, path: &[String]) -> io::Result<String> {
+        match &*self.backend {
+            Backend::Host(fs) => fs.read_link(path).await,
+            // nothing but files and directories lives in memory
+            Backend::Memory(fs) => fs.stat(path).and(Err(FsError::NotALink.into())),
+            Backend::Overlay(fs) => fs.read_link(path).await,
+        }
+    }
+}
+
+impl fmt::Debug for Vfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match &*self.backend {
+            Backend::Host(fs) => format!("Host({:?})", fs.root()),
+            Backend::Memory(_) => "Memory".to_string(),
+            Backend::Overlay(fs) => format!("Overlay({:?})", fs.root()),
+        };
+        f.debug_struct("Vfs")
+            .field("backend", &kind)
+            .field("read_only", &self.read_only)
+            .finish()
+    }
+}
+
+/// Splits a host-given `/`-separated path, refusing `..`.
+fn split(path: &str) -> io::Result<Vec<String>> {
+    let mut components = vec![];
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("path {:?} leaves the tree", path),
+                ))
+            }
+            name => components.push(name.to_string()),
+        }
+    }
+    Ok(components)
+}
+
+/// Why a filesystem call failed, where `io::ErrorKind` has no stable equivalent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FsError {
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    NotALink,
+    ReadOnly,
+    Busy,
+    /// A path resolves outside of the tree, through a symlink.
+    Escapes,
+    /// Too many symlinks, or a symlink where none may be followed.
+    Loop,
+    Unsupported,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            FsError::NotADirectory => "not a directory",
+            FsError::IsADirectory => "is a directory",
+            FsError::DirectoryNotEmpty => "directory not empty",
+            FsError::NotALink => "not a symbolic link",
+            FsError::ReadOnly => "read-only filesystem",
+            FsError::Busy => "the root of the tree cannot be moved or removed",
+            FsError::Escapes => "path leaves the tree",
+            FsError::Loop => "too many levels of symbolic links",
+            FsError::Unsupported => "not supported by this filesystem",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for FsError {}
+
+impl From<FsError> for io::Error {
+    fn from(e: FsError) -> Self {
+        io::Error::other(e)
+    }
+}
+
+impl FsError {
+    /// Returns the [`FsError`] behind `e`, if any.
+    pub(crate) fn of(e: &io::Error) -> Option<FsError> {
+        e.get_ref()?.downcast_ref::<FsError>().copied()
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::from(io::ErrorKind::NotFound)
+}
+
+fn already_exists() -> io::Error {
+    io::Error::from(io::ErrorKind::AlreadyExists)
+}
+
+/// Nanoseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileType {
+    Unknown,
+    CharacterDevice,
+    Directory,
+    RegularFile,
+    SymbolicLink,
+}
+
+/// The attributes of a file, with times in nanoseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stat {
+    pub(crate) dev: u64,
+    pub(crate) ino: u64,
+    pub(crate) filetype: FileType,
+    pub(crate) nlink: u64,
+    pub(crate) size: u64,
+    pub(crate) atim: u64,
+    pub(crate) mtim: u64,
+    pub(crate) ctim: u64,
+}
+
+impl Stat {
+    pub(crate) fn character_device() -> Self {
+        Stat {
+            dev: 0,
+            ino: 0,
+            filetype: FileType::CharacterDevice,
+            nlink: 1,
+            size: 0,
+            atim: 0,
+            mtim: 0,
+            ctim: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DirEntry {
+    pub(crate) name: String,
+    pub(crate) filetype: FileType,
+    pub(crate) ino: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct OpenOptions {
+    pub(crate) read: bool,
+    pub(crate) write: bool,
+    pub(crate) append: bool,
+    pub(crate) create: bool,
+    pub(crate) create_new: bool,
+    pub(crate) truncate: bool,
+    /// Fail unless the path is a directory.
+    pub(crate) directory: bool,
+    /// Follow a symlink in the last component.
+    pub(crate) follow: bool,
+}
+
+impl OpenOptions {
+    /// Returns `true` if opening may change the tree or the file.
+    pub(crate) fn writes(&self) -> bool {
+        self.write || self.append || self.create || self.truncate
+    }
+}
+
+pub(crate) enum Opened {
+    Dir,
+    File(VfsFile),
+}
+
+/// An open file of a [`Vfs`].
+#[derive(Debug)]
+pub(crate) enum VfsFile {
+    Host(tokio::fs::File),
+    Memory(MemFile),
+}
+
+impl VfsFile {
+    /// Reads at most `max` bytes at the current position; an empty result is end of file.
+    /// Host files are read 64 KiB at a time at most.
+    pub(crate) async fn read(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        match self {
+            VfsFile::Host(file) => {
+                use tokio::io::AsyncReadExt;
+
+                let mut buf = vec![0; max.min(64 * 1024)];
+                let n = file.read(&mut buf).await?;
+                buf.truncate(n);
+                Ok(buf)
+            }
+            VfsFile::Memory(file) => Ok(file.read(max)),
+        }
+    }
+
+    /// Reads until `max` bytes or end of file.
+    async fn read_up_to(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        while data.len() < max {
+            let chunk = self.read((max - data.len()).min(64 * 1024)).await?;
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    pub(crate) async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            VfsFile::Host(file) => {
+                use tokio::io::AsyncWriteExt;
+
+                file.write_all(data).await?;
+                // tokio completes file writes in the background; wait for them
+                file.flush().await
+            }
+            VfsFile::Memory(file) => {
+                file.write(data);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads at most `max` bytes at `offset`, without moving the position.
+    pub(crate) async fn pread(&mut self, offset: u64, max: usize) -> io::Result<Vec<u8>> {
+        match self {
+            VfsFile::Host(_) => {
+                let pos = self.seek(SeekFrom::Current(0)).await?;
+                self.seek(SeekFrom::Start(offset)).await?;
+                let read = self.read_up_to(max).await;
+                self.seek(SeekFrom::Start(pos)).await?;
+                read
+            }
+            VfsFile::Memory(file) => Ok(file.pread(offset, max)),
+        }
+    }
+
+    /// Writes `data` at `offset`, without moving the position.
+    pub(crate) async fn pwrite(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        match self {
+            VfsFile::Host(_) => {
+                let pos = self.seek(SeekFrom::Current(0)).await?;
+                self.seek(SeekFrom::Start(offset)).await?;
+                let written = self.write(data).await;
+                self.seek(SeekFrom::Start(pos)).await?;
+                written
+            }
+            VfsFile::Memory(file) => {
+                file.pwrite(offset, data);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) async fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            VfsFile::Host(file) => {
+                use tokio::io::AsyncSeekExt;
+
+                file.seek(pos).await
+            }
+            VfsFile::Memory(file) => file.seek(pos),
+        }
+    }
+
+    pub(crate) async fn stat(&self) -> io::Result<Stat> {
+        match self {
+            VfsFile::Host(file) => Ok(host::stat_of(&file.metadata().await?)),
+            VfsFile::Memory(file) => Ok(file.stat()),
+        }
+    }
+
+    pub(crate) async fn set_len(&mut self, len: u64) -> io::Result<()> {
+        match self {
+            VfsFile::Host(file) => file.set_len(len).await,
+            VfsFile::Memory(file) => {
+                file.set_len(len);
+                Ok(())
+            }
+        }
+    }
+
+    /// Flushes the file to the host disk, if it lives there.
+    pub(crate) async fn sync(&mut self, data_only: bool) -> io::Result<()> {
+        match self {
+            VfsFile::Host(file) if data_only => file.sync_data().await,
+            VfsFile::Host(file) => file.sync_all().await,
+            VfsFile::Memory(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::{
+        future::Future,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    /// A directory of the host's temporary directory, removed on drop.
+    pub(super) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(super) fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "wasmedge-asyncify-vfs-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        pub(super) fn path(&self) -> &Path {
+            &self.0
+        }
+
+        /// Writes `contents` at the relative `path`, creating its parents.
+        pub(super) fn write(&self, path: &str, contents: &str) {
+            let path = self.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    pub(super) fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    pub(super) fn path(path: &str) -> Vec<String> {
+        split(path).unwrap()
+    }
+
+    pub(super) fn write_options(truncate: bool) -> OpenOptions {
+        OpenOptions {
+            write: true,
+            create: true,
+            truncate,
+            follow: true,
+            ..OpenOptions::default()
+        }
+    }
+
+    /// Writes `data` at the start of the file at `path`, creating it.
+    pub(super) async fn write_file(fs: &Vfs, path: &str, data: &[u8]) -> io::Result<()> {
+        match fs.open(&tests::path(path), &write_options(false)).await? {
+            Opened::File(mut file) => file.write(data).await,
+            Opened::Dir => Err(FsError::IsADirectory.into()),
+        }
+    }
+
+    #[test]
+    fn split_refuses_leaving_the_tree() {
+        assert_eq!(path("/a/./b//c/"), ["a", "b", "c"]);
+        assert!(split("a/../b").is_err());
+    }
+
+    #[test]
+    fn memory_tree_is_shared_by_clones() {
+        block_on(async {
+            let fs = Vfs::memory();
+            fs.insert_file("dir/a.txt", "a").unwrap();
+            let guest = fs.clone();
+            write_file(&guest, "dir/b.txt", b"b").await.unwrap();
+            guest
+                .rename(&path("dir/a.txt"), &path("c.txt"))
+                .await
+                .unwrap();
+
+            assert_eq!(fs.read_file("dir/b.txt").await.unwrap(), b"b");
+            assert_eq!(fs.read_file("c.txt").await.unwrap(), b"a");
+            let names: Vec<_> = fs
+                .read_dir(&path("dir"))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect();
+            assert_eq!(names, ["b.txt"]);
+        })
+    }
+
+    #[test]
+    fn read_only_trees_reject_writes() {
+        block_on(async {
+            let dir = TempDir::new();
+            dir.write("a.txt", "a");
+            let trees = [
+                Vfs::memory(),
+                Vfs::overlay(dir.path()).unwrap(),
+                Vfs::host(dir.path()).unwrap(),
+            ];
+            for fs in trees {
+                let writable = fs.clone();
+                let fs = fs.read_only();
+                let read_only = |r: io::Result<()>| FsError::of(&r.unwrap_err());
+
+                assert_eq!(
+                    read_only(write_file(&fs, "a.txt", b"b").await),
+                    Some(FsError::ReadOnly)
+                );
+                assert_eq!(
+                    read_only(fs.create_dir(&path("d")).await),
+                    Some(FsError::ReadOnly)
+                );
+                assert_eq!(
+                    read_only(fs.remove_file(&path("a.txt")).await),
+                    Some(FsError::ReadOnly)
+                );
+                assert_eq!(
+                    read_only(fs.rename(&path("a.txt"), &path("b.txt")).await),
+                    Some(FsError::ReadOnly)
+                );
+                assert!(fs.stat(&path("d"), true).await.is_err());
+
+                // other handles keep their access
+                write_file(&writable, "b.txt", b"b").await.unwrap();
+                assert_eq!(fs.read_file("b.txt").await.unwrap(), b"b");
+            }
+            assert!(!dir.path().join("d").exists());
+        })
+    }
+}
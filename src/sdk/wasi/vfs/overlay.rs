@@ -0,0 +1,302 @@
+//! A host directory with the guest's changes kept in memory.
+//!
+//! Lookups try the memory layer first and fall back to the host directory. Writing a
+//! host file copies it up into memory, and removing a host path records a whiteout
+//! hiding it and everything below it.
+
+use std::{collections::HashSet, io, path::Path, sync::Mutex};
+
+use super::{
+    already_exists, host::HostFs, memory::MemFs, not_found, DirEntry, FileType, FsError,
+    OpenOptions, Opened, Stat,
+};
+
+pub(crate) struct OverlayFs {
+    lower: HostFs,
+    upper: MemFs,
+    whiteouts: Mutex<HashSet<Vec<String>>>,
+}
+
+fn is_not_found(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::NotFound
+}
+
+impl OverlayFs {
+    pub(crate) fn new(dir: &Path) -> io::Result<Self> {
+        Ok(OverlayFs {
+            lower: HostFs::new(dir)?,
+            upper: MemFs::new(),
+            whiteouts: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        self.lower.root()
+    }
+
+    /// Returns `true` if `path` or one of its ancestors was removed from the host layer.
+    fn hidden(&self, path: &[String]) -> bool {
+        let whiteouts = self.whiteouts.lock().unwrap();
+        (1..=path.len()).any(|n| whiteouts.contains(&path[..n]))
+    }
+
+    /// Returns `true` if the host layer shows something at `path`.
+    async fn in_lower(&self, path: &[String]) -> bool {
+        !self.hidden(path) && self.lower.stat(path, false).await.is_ok()
+    }
+
+    fn whiteout(&self, path: &[String]) {
+        self.whiteouts.lock().unwrap().insert(path.to_vec());
+    }
+
+    /// Makes sure the directory `path` of the merged view exists in memory.
+    async fn copy_up_dir(&self, path: &[String]) -> io::Result<()> {
+        if self.stat(path, true).await?.filetype != FileType::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+        self.upper.create_dir_all(path)
+    }
+
+    /// Copies the host file at `path` into memory, unless it is there already.
+    async fn copy_up_file(&self, path: &[String], contents: bool) -> io::Result<()> {
+        if self.upper.exists(path) {
+            return Ok(());
+        }
+        let bytes = match contents {
+            true => self.lower.read_file(path).await?,
+            false => vec![],
+        };
+        self.copy_up_dir(&path[..path.len() - 1]).await?;
+        self.upper.insert_file(path, bytes)
+    }
+
+    pub(crate) fn insert_file(&self, path: &[String], bytes: Vec<u8>) -> io::Result<()> {
+        self.upper.insert_file(path, bytes)
+    }
+
+    pub(crate) fn create_dir_all(&self, path: &[String]) -> io::Result<()> {
+        self.upper.create_dir_all(path)
+    }
+
+    pub(crate) async fn stat(&self, path: &[String], follow: bool) -> io::Result<Stat> {
+        if path.is_empty() {
+            return self.lower.stat(path, follow).await;
+        }
+        match self.upper.stat(path) {
+            Err(e) if is_not_found(&e) => {}
+            found => return found,
+        }
+        if self.hidden(path) {
+            return Err(not_found());
+        }
+        self.lower.stat(path, follow).await
+    }
+
+    pub(crate) async fn open(&self, path: &[String], opts: &OpenOptions) -> io::Result<Opened> {
+        if path.is_empty() || !opts.writes() {
+            if !path.is_empty() && self.upper.exists(path) {
+                return self.upper.open(path, opts);
+            }
+            if self.hidden(path) {
+                return Err(not_found());
+            }
+            return self.lower.open(path, opts).await;
+        }
+
+        if !self.upper.exists(path) {
+            match self.stat(path, true).await {
+                Ok(stat) if stat.filetype == FileType::Directory => {
+                    return Err(FsError::IsADirectory.into())
+                }
+                Ok(_) if opts.create_new => return Err(already_exists()),
+                Ok(_) => self.copy_up_file(path, !opts.truncate).await?,
+                Err(e) if is_not_found(&e) => self.copy_up_dir(&path[..path.len() - 1]).await?,
+                Err(e) => return Err(e),
+            }
+        }
+        self.upper.open(path, opts)
+    }
+
+    pub(crate) async fn read_dir(&self, path: &[String]) -> io::Result<Vec<DirEntry>> {
+        let upper = match self.upper.read_dir(path) {
+            Ok(entries) => Some(entries),
+            Err(e) if is_not_found(&e) => None,
+            Err(e) => return Err(e),
+        };
+        let lower = match self.hidden(path) {
+            true => None,
+            false => match self.lower.read_dir(path).await {
+                Ok(entries) => Some(entries),
+                // the memory layer decides what the path is
+                Err(_) if upper.is_some() => None,
+                Err(e) => return Err(e),
+            },
+        };
+
+        if upper.is_none() && lower.is_none() {
+            return Err(not_found());
+        }
+
+        let mut entries = upper.unwrap_or_default();
+        let mut child = path.to_vec();
+        for entry in lower.into_iter().flatten() {
+            child.push(entry.name.clone());
+            if !self.upper.exists(&child) && !self.hidden(&child) {
+                entries.push(entry);
+            }
+            child.pop();
+        }
+        Ok(entries)
+    }
+
+    pub(crate) async fn create_dir(&self, path: &[String]) -> io::Result<()> {
+        match self.stat(path, false).await {
+            Ok(_) => return Err(already_exists()),
+            Err(e) if is_not_found(&e) => {}
+            Err(e) => return Err(e),
+        }
+        self.copy_up_dir(&path[..path.len() - 1]).await?;
+        self.upper.create_dir(path)
+    }
+
+    pub(crate) async fn remove_dir(&self, path: &[String]) -> io::Result<()> {
+        if self.stat(path, false).await?.filetype != FileType::Directory {
+            return Err(FsError::NotADirectory.into());
+        }
+        if !self.read_dir(path).await?.is_empty() {
+            return Err(FsError::DirectoryNotEmpty.into());
+        }
+        if self.upper.exists(path) {
+            self.upper.remove_dir(path)?;
+        }
+        if self.in_lower(path).await {
+            self.whiteout(path);
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn remove_file(&self, path: &[String]) -> io::Result<()> {
+        if self.stat(path, false).await?.filetype == FileType::Directory {
+            return Err(FsError::IsADirectory.into());
+        }
+        if self.upper.exists(path) {
+            self.upper.remove_file(path)?;
+        }
+        if self.in_lower(path).await {
+            self.whiteout(path);
+        }
+        Ok(())
+    }
+
+    /// Moves a file, copying it up first. Directories move only while they live in
+    /// memory alone.
+    pub(crate) async fn rename(&self, from: &[String], to: &[String]) -> io::Result<()> {
+        let is_dir = self.stat(from, false).await?.filetype == FileType::Directory;
+        if is_dir && (self.in_lower(from).await || self.in_lower(to).await) {
+            return Err(FsError::Unsupported.into());
+        }
+        if let Ok(stat) = self.stat(to, false).await {
+            if !is_dir && stat.filetype == FileType::Directory {
+                return Err(FsError::IsADirectory.into());
+            }
+        }
+
+        if !is_dir {
+            self.copy_up_file(from, true).await?;
+        }
+        self.copy_up_dir(&to[..to.len() - 1]).await?;
+        self.upper.rename(from, to)?;
+        if self.in_lower(from).await {
+            self.whiteout(from);
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn read_link(&self, path: &[String]) -> io::Result<String> {
+        if self.upper.exists(path) {
+            return Err(FsError::NotALink.into());
+        }
+        if self.hidden(path) {
+            return Err(not_found());
+        }
+        self.lower.read_link(path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        tests::{block_on, path, write_file, write_options, TempDir},
+        Vfs,
+    };
+    use super::*;
+
+    #[test]
+    fn whiteouts_hide_removed_subtrees() {
+        block_on(async {
+            let dir = TempDir::new();
+            dir.write("a/b/c.txt", "c");
+            let fs = OverlayFs::new(dir.path()).unwrap();
+
+            fs.remove_file(&path("a/b/c.txt")).await.unwrap();
+            fs.remove_dir(&path("a/b")).await.unwrap();
+            assert!(fs.hidden(&path("a/b/anything")));
+            assert!(is_not_found(
+                &fs.stat(&path("a/b"), false).await.unwrap_err()
+            ));
+            assert!(fs.read_dir(&path("a")).await.unwrap().is_empty());
+
+            // a directory made again in its place starts empty
+            fs.create_dir(&path("a/b")).await.unwrap();
+            assert!(fs.read_dir(&path("a/b")).await.unwrap().is_empty());
+            assert!(dir.path().join("a/b/c.txt").exists());
+        })
+    }
+
+    #[test]
+    fn writes_and_truncation_copy_files_up() {
+        block_on(async {
+            let dir = TempDir::new();
+            dir.write("a.txt", "lower");
+            dir.write("b.txt", "lower");
+            let fs = Vfs::overlay(dir.path()).unwrap();
+
+            write_file(&fs, "a.txt", b"UP").await.unwrap();
+            assert_eq!(fs.read_file("a.txt").await.unwrap(), b"UPwer");
+            match fs.open(&path("b.txt"), &write_options(true)).await.unwrap() {
+                Opened::File(mut file) => file.write(b"new").await.unwrap(),
+                Opened::Dir => panic!("b.txt is a file"),
+            }
+            assert_eq!(fs.read_file("b.txt").await.unwrap(), b"new");
+
+            assert_eq!(std::fs::read(dir.path().join("a.txt")).unwrap(), b"lower");
+            assert_eq!(std::fs::read(dir.path().join("b.txt")).unwrap(), b"lower");
+        })
+    }
+
+    #[test]
+    fn rename_refuses_host_directories() {
+        block_on(async {
+            let dir = TempDir::new();
+            dir.write("lower/a.txt", "a");
+            let fs = OverlayFs::new(dir.path()).unwrap();
+            fs.create_dir(&path("upper")).await.unwrap();
+
+            let e = fs.rename(&path("lower"), &path("moved")).await.unwrap_err();
+            assert_eq!(FsError::of(&e), Some(FsError::Unsupported));
+            let e = fs.rename(&path("upper"), &path("lower")).await.unwrap_err();
+            assert_eq!(FsError::of(&e), Some(FsError::Unsupported));
+
+            // files and directories living in memory alone move
+            fs.rename(&path("lower/a.txt"), &path("upper/a.txt"))
+                .await
+                .unwrap();
+            fs.rename(&path("upper"), &path("moved")).await.unwrap();
+            assert!(fs.stat(&path("moved/a.txt"), false).await.is_ok());
+            assert!(is_not_found(
+                &fs.stat(&path("lower/a.txt"), false).await.unwrap_err()
+            ));
+            assert!(dir.path().join("lower/a.txt").exists());
+        })
+    }
+}
@@ -1,4 +1,7 @@
-use std::{ffi::CString, os::unix::ffi::OsStrExt, path::Path};
+#[cfg(all(unix, feature = "aot"))]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(feature = "aot")]
+use std::{ffi::CString, path::Path};
 use wasmedge_sys::ffi::{WasmEdge_Result, WasmEdge_ResultGetCode, WasmEdge_ResultOK};
 use wasmedge_types::{
     error::{
@@ -8,12 +11,12 @@ use wasmedge_types::{
     WasmEdgeResult,
 };
 
-#[cfg(unix)]
+#[cfg(all(unix, feature = "aot"))]
 pub(crate) fn path_to_cstring(path: &Path) -> WasmEdgeResult<CString> {
     Ok(CString::new(path.as_os_str().as_bytes())?)
 }
 
-#[cfg(windows)]
+#[cfg(all(windows, feature = "aot"))]
 pub(crate) fn path_to_cstring(path: &Path) -> WasmEdgeResult<CString> {
     match path.to_str() {
         Some(s) => Ok(CString::new(s)?),
@@ -23,6 +26,13 @@ pub(crate) fn path_to_cstring(path: &Path) -> WasmEdgeResult<CString> {
     }
 }
 
+/// Returns `true` if `result` reports that the guest terminated, e.g. through WASI `proc_exit`.
+///
+/// [`check`] treats termination as success.
+pub(crate) fn is_terminated(result: WasmEdge_Result) -> bool {
+    unsafe { WasmEdge_ResultGetCode(result) == 0x01 }
+}
+
 pub(crate) fn check(result: WasmEdge_Result) -> WasmEdgeResult<()> {
     let code = unsafe {
         if !WasmEdge_ResultOK(result) {
@@ -0,0 +1,209 @@
+//! Drives the linker with a minimal executor, without any async runtime, so that these
+//! tests build and pass with `--no-default-features`.
+
+use std::{
+    fmt,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use wasmedge_asyncify::{types::WasmVal, *};
+
+/// Runs a future to completion on the current thread.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = Box::pin(fut);
+    let thread = std::thread::current();
+    let waker = waker_fn::waker_fn(move || thread.unpark());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(r) => return r,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// Polls a future once.
+fn poll_once<F: Future + Unpin>(fut: &mut F) -> Poll<F::Output> {
+    let waker = waker_fn::waker_fn(|| {});
+    Pin::new(fut).poll(&mut Context::from_waker(&waker))
+}
+
+/// A timer future backed by a plain thread.
+struct Sleep {
+    state: Arc<Mutex<(bool, Option<Waker>)>>,
+}
+
+impl Sleep {
+    fn new(dur: Duration) -> Self {
+        let state = Arc::new(Mutex::new((false, None::<Waker>)));
+        let timer_state = state.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(dur);
+            let mut state = timer_state.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        });
+        Sleep { state }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.0 {
+            Poll::Ready(())
+        } else {
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+fn read_wasm(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("wasm")
+        .join(name);
+    std::fs::read(path).unwrap()
+}
+
+/// Instantiates `wasm/{name}` with its stdout captured into `stdout` and `host` as the
+/// import module.
+fn instance<F>(name: &str, stdout: &Arc<Mutex<Vec<u8>>>, host: F) -> Pin<Box<AsyncLinker>>
+where
+    F: FnOnce(&mut AsyncImportModuleBuilder) -> WasmEdgeResult<()>,
+{
+    let mut builder = AsyncLinkerBuilder::new(&Some(Config::create().unwrap())).unwrap();
+    let wasi = WasiConfig::new()
+        .env("a", "1")
+        .env("b", "1")
+        .stdout(OutputSink::Buffer(stdout.clone()));
+    builder.create_wasi_with(&wasi).unwrap();
+    builder.create_import_object("host", host).unwrap();
+    let module = builder.load_wasm(&read_wasm(name)).unwrap();
+    builder.instance(&module).unwrap()
+}
+
+static SLEEPS: AtomicUsize = AtomicUsize::new(0);
+
+fn sleep(_linker: &mut AsyncLinker, _args: Vec<WasmVal>) -> ResultFuture<'_> {
+    Box::new(async {
+        SLEEPS.fetch_add(1, Ordering::SeqCst);
+        Sleep::new(Duration::from_millis(20)).await;
+        Ok(vec![])
+    })
+}
+
+#[test]
+fn suspends_on_an_async_import_without_a_runtime() {
+    let stdout = Arc::new(Mutex::new(vec![]));
+    let mut inst = instance("hello.wasm", &stdout, |b| {
+        b.add_async_func("sleep", (vec![], vec![]), sleep)
+    });
+
+    let before = SLEEPS.load(Ordering::SeqCst);
+    block_on(inst.call("_start", vec![])).unwrap();
+
+    assert_eq!(SLEEPS.load(Ordering::SeqCst), before + 1);
+    let stdout = String::from_utf8(stdout.lock().unwrap().clone()).unwrap();
+    assert!(
+        stdout.ends_with("wasm: hello\nwasm: world\n"),
+        "{:?}",
+        stdout
+    );
+}
+
+fn to_uppercase(linker: &mut AsyncLinker, args: Vec<WasmVal>) -> ResultFuture<'_> {
+    Box::new(async move {
+        let (ptr, len) = match (args.first(), args.get(1)) {
+            (Some(WasmVal::I32(ptr)), Some(WasmVal::I32(len))) => (*ptr as usize, *len as usize),
+            _ => return Ok(vec![WasmVal::I32(-1)]),
+        };
+        Sleep::new(Duration::from_millis(5)).await;
+        linker
+            .get_mut_memory("memory", ptr, len)?
+            .make_ascii_uppercase();
+        Ok(vec![WasmVal::I32(len as i32)])
+    })
+}
+
+#[test]
+fn writes_guest_memory_after_resuming() {
+    let stdout = Arc::new(Mutex::new(vec![]));
+    let mut inst = instance("memory.wasm", &stdout, |b| {
+        b.add_async_func(
+            "to_uppercase",
+            (vec![ValType::I32, ValType::I32], vec![ValType::I32]),
+            to_uppercase,
+        )
+    });
+
+    block_on(inst.call("_start", vec![])).unwrap();
+
+    assert_eq!(&stdout.lock().unwrap()[..], b"HELLO WASM\n");
+}
+
+#[derive(Debug)]
+struct Refused;
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "refused")
+    }
+}
+
+impl std::error::Error for Refused {}
+
+fn failing_sleep(_linker: &mut AsyncLinker, _args: Vec<WasmVal>) -> ResultFuture<'_> {
+    Box::new(async {
+        Sleep::new(Duration::from_millis(5)).await;
+        Err(Box::new(Refused) as BoxError)
+    })
+}
+
+#[test]
+fn reports_the_error_of_an_async_import() {
+    let stdout = Arc::new(Mutex::new(vec![]));
+    let mut inst = instance("hello.wasm", &stdout, |b| {
+        b.add_async_func("sleep", (vec![], vec![]), failing_sleep)
+    });
+
+    let e = block_on(inst.call("_start", vec![])).unwrap_err();
+
+    assert!(e.downcast_ref::<Refused>().is_some(), "{}", e);
+    let report = e.trap().expect("a trap report");
+    assert_eq!(report.import(), Some("host.sleep"));
+    assert_eq!(report.function(), Some("_start"));
+    assert!(!inst.is_poisoned());
+}
+
+fn never(_linker: &mut AsyncLinker, _args: Vec<WasmVal>) -> ResultFuture<'_> {
+    Box::new(std::future::pending())
+}
+
+#[test]
+fn dropping_a_suspended_call_resets_the_linker() {
+    let stdout = Arc::new(Mutex::new(vec![]));
+    let mut inst = instance("hello.wasm", &stdout, |b| {
+        b.add_async_func("sleep", (vec![], vec![]), never)
+    });
+
+    let mut call = inst.call("_start", vec![]);
+    assert!(poll_once(&mut call).is_pending());
+    drop(call);
+
+    assert!(!inst.is_poisoned());
+    let stdout = String::from_utf8(stdout.lock().unwrap().clone()).unwrap();
+    assert!(stdout.ends_with("wasm: hello\n"), "{:?}", stdout);
+}